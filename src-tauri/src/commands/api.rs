@@ -1,16 +1,22 @@
-use crate::entities::{conversation, message, model, user};
-use crate::State;
+use crate::commands::backend::ChatBackend;
+use crate::commands::reasoning::{Piece, Thoughts};
+use crate::commands::retry::RetryPolicy;
+use crate::commands::sse;
+use crate::commands::tools::ToolDefinition;
+pub use crate::entities::message::Role;
+use crate::entities::message::{self, MessageKind, ToolCall};
+use crate::entities::model::Parameters;
+use crate::entities::provider;
 use ::reqwest::{
-    header::{AUTHORIZATION, CONTENT_TYPE},
-    Response,
+    header::{AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER},
+    Response, StatusCode,
 };
-use chrono::Utc;
 use core::str;
+use hf_hub::Cache;
 use log::{debug, error, info};
-use sea_orm::{
-    ActiveModelTrait, ActiveValue::Set, ColumnTrait, EntityTrait, QueryFilter, QueryOrder,
-};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
 
 #[derive(Debug, Deserialize, thiserror::Error)]
 #[cfg_attr(test, derive(Serialize))]
@@ -42,6 +48,12 @@ pub enum Error {
     #[error("Conversation {0} is missing")]
     MissingConversation(u32),
 
+    #[error("Summary {0} is missing")]
+    MissingSummary(u32),
+
+    #[error("Conversation {0} is already generating")]
+    AlreadyGenerating(u32),
+
     #[error("Db error {0}")]
     DbError(#[from] sea_orm::DbErr),
 
@@ -54,12 +66,83 @@ pub enum Error {
     #[error("Invalid Token")]
     InvalidToken,
 
+    #[error("{0} rejected the API key, check the provider settings")]
+    Unauthorized(String),
+
+    #[error("Rate limited by the inference endpoint, {}", try_again(.retry_after))]
+    RateLimited { retry_after: Option<Duration> },
+
+    #[error("The model is loading, {}", try_again(.estimated_time))]
+    ModelLoading { estimated_time: Option<Duration> },
+
+    #[error("Model {0} was not found on this endpoint, check its name or pick another model")]
+    ModelNotFound(String),
+
+    #[error("The conversation is too long for this model ({0}), start a new conversation or lower max new tokens")]
+    ContextTooLong(String),
+
+    #[error("The request was rejected: {0}")]
+    Validation(String),
+
+    #[error("The endpoint answered {status}: {message}")]
+    Http { status: u16, message: String },
+
     #[error(transparent)]
     IoError(#[from] std::io::Error),
 
     #[error(transparent)]
     Local(#[from] crate::commands::local::Error),
+
+    #[error(transparent)]
+    Template(#[from] crate::commands::template::Error),
+}
+fn try_again(wait: &Option<Duration>) -> String {
+    match wait {
+        Some(wait) => format!("try again in {}s", wait.as_secs().max(1)),
+        None => "try again later".to_string(),
+    }
+}
+
+/// What the UI can do about an error.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorKind {
+    InvalidToken,
+    Unauthorized,
+    RateLimited,
+    ModelLoading,
+    ModelNotFound,
+    ContextTooLong,
+    Validation,
+    Other,
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            _ if self.is_invalid_token() => ErrorKind::InvalidToken,
+            Error::Unauthorized(_) => ErrorKind::Unauthorized,
+            Error::RateLimited { .. } => ErrorKind::RateLimited,
+            Error::ModelLoading { .. } => ErrorKind::ModelLoading,
+            Error::ModelNotFound(_) => ErrorKind::ModelNotFound,
+            Error::ContextTooLong(_) => ErrorKind::ContextTooLong,
+            Error::Validation(_) => ErrorKind::Validation,
+            _ => ErrorKind::Other,
+        }
+    }
+
+    /// The hub rejected our token, it needs to be deleted to force a new login.
+    pub fn is_invalid_token(&self) -> bool {
+        matches!(
+            self,
+            Error::InvalidToken
+                | Error::SseError(SseError {
+                    error: InnerError::InvalidToken,
+                })
+        )
+    }
 }
+
 // we must manually implement serde::Serialize
 impl serde::Serialize for Error {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -70,50 +153,213 @@ impl serde::Serialize for Error {
     }
 }
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
-#[serde(rename_all = "kebab-case")]
-pub enum Role {
-    User,
-    Assistant,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone)]
 pub struct Message {
     pub role: Role,
     pub content: String,
+    /// The tools an assistant message called.
+    pub tool_calls: Vec<ToolCall>,
+    /// The call a `Role::Tool` message answers.
+    pub tool_call_id: Option<String>,
+    /// Pictures for vision models, see `images::inline`.
+    pub images: Vec<InlineImage>,
+}
+
+/// A picture sent with a message.
+#[derive(Clone, PartialEq)]
+pub struct InlineImage {
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+/// Without the bytes, messages are logged.
+impl std::fmt::Debug for InlineImage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InlineImage")
+            .field("mime_type", &self.mime_type)
+            .field("bytes", &self.data.len())
+            .finish()
+    }
+}
+
+impl InlineImage {
+    pub fn base64(&self) -> String {
+        use base64::Engine;
+        base64::engine::general_purpose::STANDARD.encode(&self.data)
+    }
+}
+
+/// Messages as OpenAI compatible servers expect them. Text only messages
+/// keep a plain `content`, it becomes a list of parts with pictures.
+impl Serialize for Message {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        #[derive(Serialize)]
+        struct Wire<'a> {
+            role: Role,
+            content: Content<'a>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            tool_calls: Option<Vec<serde_json::Value>>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            tool_call_id: Option<&'a str>,
+        }
+
+        #[derive(Serialize)]
+        #[serde(untagged)]
+        enum Content<'a> {
+            Text(&'a str),
+            Parts(Vec<Part<'a>>),
+        }
+
+        #[derive(Serialize)]
+        #[serde(tag = "type", rename_all = "snake_case")]
+        enum Part<'a> {
+            Text { text: &'a str },
+            ImageUrl { image_url: ImageUrl },
+        }
+
+        #[derive(Serialize)]
+        struct ImageUrl {
+            url: String,
+        }
+
+        let content = if self.images.is_empty() {
+            Content::Text(&self.content)
+        } else {
+            let images = self.images.iter().map(|image| Part::ImageUrl {
+                image_url: ImageUrl {
+                    url: format!("data:{};base64,{}", image.mime_type, image.base64()),
+                },
+            });
+            Content::Parts(
+                std::iter::once(Part::Text {
+                    text: &self.content,
+                })
+                .chain(images)
+                .collect(),
+            )
+        };
+        // As OpenAI sends them, `arguments` is a json string.
+        let tool_calls = (!self.tool_calls.is_empty()).then(|| {
+            self.tool_calls
+                .iter()
+                .map(|call| {
+                    serde_json::json!({
+                        "id": call.id,
+                        "type": "function",
+                        "function": {"name": call.name, "arguments": call.arguments},
+                    })
+                })
+                .collect()
+        });
+        Wire {
+            role: self.role,
+            content,
+            tool_calls,
+            tool_call_id: self.tool_call_id.as_deref(),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// A message sent to the model, with the ids of the db messages it was made of.
+#[derive(Debug, Clone)]
+pub struct Turn {
+    pub ids: Vec<u32>,
+    pub message: Message,
 }
 
+/// Introduces a summary in the prompt, the model sees it as part of the user's turn.
+pub const SUMMARY_PREFIX: &str = "Summary of the conversation so far:";
+
 impl Message {
-    fn from_db(messages: Vec<message::Model>) -> Vec<Self> {
-        let mut role = Role::Assistant;
-        let mut last_user = None;
-        let mut last_message = None;
-        let mut newmessages = Vec::with_capacity(messages.len());
+    pub fn new(role: Role, content: String) -> Self {
+        Self {
+            role,
+            content,
+            tool_calls: vec![],
+            tool_call_id: None,
+            images: vec![],
+        }
+    }
+
+    /// Tool calls and their results are turns of their own.
+    fn is_plain(&self) -> bool {
+        self.role != Role::Tool && self.tool_calls.is_empty()
+    }
+
+    /// The conversation as sent to every backend: the system prompt, the
+    /// latest summary in place of the messages it covers, then the rest with
+    /// consecutive messages of the same role merged into one turn. `messages`
+    /// is a single branch of the conversation, see `branch::branch`.
+    pub fn from_db(mut messages: Vec<message::Model>, system_prompt: Option<String>) -> Vec<Turn> {
+        messages.sort_by_key(|message| message.id);
+        let summary = messages
+            .iter()
+            .rev()
+            .find(|message| message.kind == MessageKind::Summary)
+            .cloned();
+        let until = summary.as_ref().and_then(|summary| summary.summary_until);
+        let mut turns: Vec<Turn> = Vec::with_capacity(messages.len() + 2);
+        if let Some(content) = system_prompt {
+            turns.push(Turn {
+                ids: vec![],
+                message: Message::new(Role::System, content),
+            });
+        }
         for message in messages {
-            if Some(message.user_id) != last_user {
-                if let Some(last_message) = last_message.take() {
-                    newmessages.push(last_message);
+            if message.kind == MessageKind::Summary
+                || until.is_some_and(|until| message.id <= until)
+            {
+                continue;
+            }
+            let next = Message {
+                role: message.role,
+                content: message.content,
+                tool_calls: message.tool_calls.map(|calls| calls.0).unwrap_or_default(),
+                tool_call_id: message.tool_call_id,
+                images: vec![],
+            };
+            match turns.last_mut() {
+                Some(turn)
+                    if turn.message.role == next.role
+                        && turn.message.is_plain()
+                        && next.is_plain() =>
+                {
+                    turn.ids.push(message.id);
+                    turn.message.content.push('\n');
+                    turn.message.content.push_str(&next.content);
+                    turn.message.images.extend(next.images);
                 }
-                role = match role {
-                    Role::User => Role::Assistant,
-                    Role::Assistant => Role::User,
-                };
-                last_message = Some(Message {
-                    role,
-                    content: message.content.clone(),
-                });
-                last_user = Some(message.user_id);
-            } else {
-                last_message.as_mut().map(|m| {
-                    m.content.push('\n');
-                    m.content.push_str(&message.content);
-                });
+                _ => turns.push(Turn {
+                    ids: vec![message.id],
+                    message: next,
+                }),
             }
         }
-        if let Some(last_message) = last_message.take() {
-            newmessages.push(last_message);
+        if let Some(summary) = summary {
+            let content = format!("{SUMMARY_PREFIX}\n{}", summary.content);
+            // After the system messages, as part of the user's first turn.
+            let first = turns
+                .iter()
+                .position(|turn| turn.message.role != Role::System)
+                .unwrap_or(turns.len());
+            match turns.get_mut(first) {
+                Some(turn) if turn.message.role == Role::User => {
+                    turn.message.content = format!("{content}\n\n{}", turn.message.content);
+                }
+                _ => turns.insert(
+                    first,
+                    Turn {
+                        ids: vec![],
+                        message: Message::new(Role::User, content),
+                    },
+                ),
+            }
         }
-        newmessages
+        turns
     }
 }
 
@@ -124,195 +370,614 @@ pub struct Payload {
     stream: bool,
     max_tokens: usize,
     temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_k: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repetition_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<FunctionTool>,
+}
+
+/// A tool as OpenAI compatible servers and Ollama expect it.
+#[derive(Debug, Serialize)]
+pub struct FunctionTool {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    function: ToolDefinition,
+}
+
+pub fn function_tools(tools: &[ToolDefinition]) -> Vec<FunctionTool> {
+    tools
+        .iter()
+        .map(|tool| FunctionTool {
+            kind: "function",
+            function: tool.clone(),
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+struct StreamOptions {
+    /// Asks for a last chunk with the token counts.
+    include_usage: bool,
+}
+
+/// Whether the server at `url` takes `stream_options`. Some OpenAI compatible
+/// servers reject the request when it carries fields they do not know.
+fn reports_usage(url: &str) -> bool {
+    let host = ::reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_default();
+    host == "api.openai.com"
+        || host == "huggingface.co"
+        || host.ends_with(".huggingface.co")
+        || host.ends_with(".huggingface.cloud")
+}
+
+impl Payload {
+    fn new(
+        model: String,
+        messages: Vec<Message>,
+        tools: &[ToolDefinition],
+        parameters: &Parameters,
+        usage: bool,
+    ) -> Self {
+        Self {
+            model,
+            messages,
+            stream: true,
+            max_tokens: parameters.max_new_tokens(),
+            temperature: parameters.temperature,
+            top_p: parameters.top_p(),
+            top_k: parameters.top_k(),
+            repetition_penalty: parameters.repetition_penalty(),
+            stop: parameters.stop(),
+            stream_options: usage.then_some(StreamOptions {
+                include_usage: true,
+            }),
+            tools: function_tools(tools),
+        }
+    }
+}
+
+/// The model name the server expects, hub endpoints carry it in their url
+/// (`.../models/{model_id}/v1/chat/completions`).
+pub(crate) fn model_name(url: &str) -> String {
+    url.split_once("/models/")
+        .and_then(|(_, rest)| rest.split_once("/v1/"))
+        .map(|(model_id, _)| model_id.to_string())
+        .unwrap_or_else(|| "tgi".to_string())
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Choice {
-    delta: Message,
+    delta: Delta,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Delta {
+    #[serde(default)]
+    content: Option<String>,
+    /// The thoughts of reasoning models, servers name it either way.
+    #[serde(default)]
+    reasoning_content: Option<String>,
+    #[serde(default)]
+    reasoning: Option<String>,
+    #[serde(default)]
+    tool_calls: Option<Vec<ToolCallDelta>>,
+}
+
+/// A piece of a tool call, the arguments are streamed in several of them.
+#[derive(Debug, Deserialize)]
+pub struct ToolCallDelta {
+    #[serde(default)]
+    index: usize,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    function: Option<FunctionDelta>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FunctionDelta {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    arguments: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Chunk {
     choices: Vec<Choice>,
+    #[serde(default)]
+    usage: Option<Usage>,
 }
 
-pub enum Stream {
+/// Tokens spent on a reply, as counted by the backend.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub struct Usage {
+    #[serde(default)]
+    pub prompt_tokens: usize,
+    #[serde(default)]
+    pub completion_tokens: usize,
+}
+
+enum Backend {
     Api(Api),
     Local(crate::commands::local::Stream),
+    Ollama(crate::commands::ollama::Stream),
+    Tgi(crate::commands::tgi::Stream),
+}
+
+/// A reply streaming in from any backend, with the thoughts of reasoning
+/// models split from the answer.
+pub struct Stream {
+    backend: Backend,
+    thoughts: Thoughts,
+    done: bool,
+}
+
+impl From<Backend> for Stream {
+    fn from(backend: Backend) -> Self {
+        Self {
+            backend,
+            thoughts: Thoughts::default(),
+            done: false,
+        }
+    }
+}
+
+impl From<Api> for Stream {
+    fn from(api: Api) -> Self {
+        Backend::Api(api).into()
+    }
+}
+
+impl From<crate::commands::local::Stream> for Stream {
+    fn from(local: crate::commands::local::Stream) -> Self {
+        Backend::Local(local).into()
+    }
+}
+
+impl From<crate::commands::ollama::Stream> for Stream {
+    fn from(ollama: crate::commands::ollama::Stream) -> Self {
+        Backend::Ollama(ollama).into()
+    }
+}
+
+impl From<crate::commands::tgi::Stream> for Stream {
+    fn from(tgi: crate::commands::tgi::Stream) -> Self {
+        Backend::Tgi(tgi).into()
+    }
 }
 
 impl Stream {
-    pub async fn next(&mut self) -> Result<Option<String>, Error> {
-        match self {
-            Stream::Api(api) => api.next().await,
-            Stream::Local(local) => Ok(local.next().await),
+    pub async fn next(&mut self) -> Result<Option<Piece>, Error> {
+        while !self.done {
+            let piece = match &mut self.backend {
+                Backend::Api(api) => api.next().await?,
+                Backend::Local(local) => local.next().await.map(Piece::content),
+                Backend::Ollama(ollama) => ollama.next().await?.map(Piece::content),
+                Backend::Tgi(tgi) => tgi.next().await?.map(Piece::content),
+            };
+            let piece = match piece {
+                Some(piece) => self.thoughts.split(piece),
+                // The local stream may not be asked again once over.
+                None => {
+                    self.done = true;
+                    self.thoughts.finish()
+                }
+            };
+            if !piece.is_empty() {
+                return Ok(Some(piece));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The token counts, once the backend sent them. TGI does not.
+    pub fn usage(&self) -> Option<Usage> {
+        match &self.backend {
+            Backend::Api(api) => api.decoder.usage,
+            Backend::Local(local) => local.usage(),
+            Backend::Ollama(ollama) => ollama.usage(),
+            Backend::Tgi(_) => None,
+        }
+    }
+
+    /// The tools the model called instead of answering, once the stream is over.
+    pub fn tool_calls(&mut self) -> Vec<ToolCall> {
+        match &mut self.backend {
+            Backend::Api(api) => with_ids(std::mem::take(&mut api.decoder.tool_calls)),
+            Backend::Local(local) => local.tool_calls(),
+            Backend::Ollama(ollama) => ollama.tool_calls(),
+            Backend::Tgi(_) => vec![],
+        }
+    }
+
+    /// Stops the generation before the model is done.
+    pub async fn cancel(self) {
+        match self.backend {
+            // Dropping the response closes the connection.
+            Backend::Api(_) | Backend::Ollama(_) | Backend::Tgi(_) => {}
+            Backend::Local(local) => local.cancel().await,
         }
     }
 }
 
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    error: ErrorMessage,
+    #[serde(default)]
+    estimated_time: Option<f64>,
+}
+
+/// Hub and TGI send a plain string, OpenAI compatible servers an object.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ErrorMessage {
+    Text(String),
+    Object { message: String },
+}
+
+fn is_context_too_long(message: &str) -> bool {
+    let message = message.to_lowercase();
+    message.contains("must be <=")
+        || message.contains("context length")
+        || message.contains("too many tokens")
+        || message.contains("too long")
+}
+
+/// Decodes a non 2xx answer of an inference endpoint.
+fn http_error(
+    status: StatusCode,
+    retry_after: Option<Duration>,
+    model: &str,
+    body: &[u8],
+) -> Error {
+    let (message, estimated_time) = match serde_json::from_slice::<ErrorBody>(body) {
+        Ok(ErrorBody {
+            error: ErrorMessage::Text(message) | ErrorMessage::Object { message },
+            estimated_time,
+        }) => (message, estimated_time),
+        Err(_) => (String::from_utf8_lossy(body).trim().to_string(), None),
+    };
+    let estimated_time = estimated_time
+        .filter(|time| time.is_finite() && *time >= 0.0)
+        .map(Duration::from_secs_f64);
+    if message == "Authorization header is correct, but the token seems invalid" {
+        return Error::InvalidToken;
+    }
+    match status.as_u16() {
+        401 => Error::InvalidToken,
+        404 => Error::ModelNotFound(model.to_string()),
+        429 => Error::RateLimited { retry_after },
+        503 if estimated_time.is_some() || message.to_lowercase().contains("loading") => {
+            Error::ModelLoading {
+                estimated_time: estimated_time.or(retry_after),
+            }
+        }
+        400 | 413 | 422 if is_context_too_long(&message) => Error::ContextTooLong(message),
+        422 => Error::Validation(message),
+        status => Error::Http { status, message },
+    }
+}
+
+/// Calls need an id for their result to refer to, not every server sends one.
+pub(crate) fn with_ids(tool_calls: Vec<ToolCall>) -> Vec<ToolCall> {
+    tool_calls
+        .into_iter()
+        .filter(|call| !call.name.is_empty())
+        .enumerate()
+        .map(|(i, mut call)| {
+            if call.id.is_empty() {
+                call.id = format!("call_{i}");
+            }
+            call
+        })
+        .collect()
+}
+
 pub struct Api {
     res: Response,
-    leftover: Vec<u8>,
-}
-
-pub async fn query(url: String, messages: Vec<Message>, token: &str) -> Result<Api, Error> {
-    info!("Query {url} {} messages", messages.len());
-    let client = ::reqwest::Client::new();
-    let model = "tgi".to_string();
-
-    let stream = true;
-    let max_tokens = 1024;
-    let temperature = 0.0;
-    let payload = Payload {
-        model,
-        messages,
-        stream,
-        max_tokens,
-        temperature,
-    };
+    decoder: ChatDecoder,
+}
+
+/// Turns the server-sent events of a chat completion into text deltas.
+#[derive(Default)]
+struct ChatDecoder {
+    decoder: sse::Decoder,
+    done: bool,
+    usage: Option<Usage>,
+    /// Indexed as the server numbers them.
+    tool_calls: Vec<ToolCall>,
+}
+
+impl ChatDecoder {
+    fn feed(&mut self, bytes: &[u8]) -> Result<Piece, Error> {
+        let mut piece = Piece::default();
+        for event in self.decoder.feed(bytes) {
+            if self.done {
+                break;
+            }
+            if event.data == "[DONE]" {
+                self.done = true;
+            } else if let Ok(chunk) = serde_json::from_str::<Chunk>(&event.data) {
+                self.usage = chunk.usage.or(self.usage);
+                if let Some(choice) = chunk.choices.into_iter().next() {
+                    let delta = choice.delta;
+                    piece.content.push_str(&delta.content.unwrap_or_default());
+                    piece.reasoning.push_str(
+                        &delta
+                            .reasoning_content
+                            .or(delta.reasoning)
+                            .unwrap_or_default(),
+                    );
+                    for tool_call in delta.tool_calls.unwrap_or_default() {
+                        self.add_tool_call(tool_call);
+                    }
+                }
+            } else if let Ok(parsed) = serde_json::from_str::<SseError>(&event.data) {
+                error!("Chunk Error {parsed:?} (event id {:?})", event.id);
+                return Err(Error::SseError(parsed));
+            } else {
+                return Err(Error::InvalidChunkError(event.data));
+            }
+        }
+        Ok(piece)
+    }
+
+    fn add_tool_call(&mut self, delta: ToolCallDelta) {
+        if self.tool_calls.len() <= delta.index {
+            self.tool_calls.resize_with(delta.index + 1, || ToolCall {
+                id: String::new(),
+                name: String::new(),
+                arguments: String::new(),
+            });
+        }
+        let call = &mut self.tool_calls[delta.index];
+        if let Some(id) = delta.id {
+            call.id = id;
+        }
+        if let Some(function) = delta.function {
+            call.name.push_str(&function.name.unwrap_or_default());
+            call.arguments
+                .push_str(&function.arguments.unwrap_or_default());
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        if let Some(line) = self.decoder.finish() {
+            // Not an event stream, most likely a plain json error.
+            if let Ok(parsed) = serde_json::from_slice::<SseError>(&line) {
+                error!("Chunk Error {parsed:?}");
+                return Err(Error::SseError(parsed));
+            }
+            return Err(Error::InvalidChunkError(
+                String::from_utf8_lossy(&line).into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Where a chat completion request is sent, and with which credentials.
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    url: String,
+    model: String,
+    headers: BTreeMap<String, String>,
+}
+
+impl Endpoint {
+    pub fn new(url: String) -> Self {
+        let model = model_name(&url);
+        Self {
+            url,
+            model,
+            headers: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_model(mut self, model: String) -> Self {
+        self.model = model;
+        self
+    }
+
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.headers
+            .insert(AUTHORIZATION.to_string(), format!("Bearer {token}"));
+        self
+    }
+
+    pub fn with_headers(mut self, headers: &BTreeMap<String, String>) -> Self {
+        self.headers.extend(headers.clone());
+        self
+    }
+
+    /// Authenticates with the provider's key and headers.
+    pub fn with_provider(mut self, provider: &provider::Model) -> Self {
+        self = self.with_headers(&provider.headers.0);
+        if let Some(api_key) = &provider.api_key {
+            self = self.with_token(api_key);
+        }
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Posts `body` as json, a non 2xx answer is decoded into an `Error`.
+    pub async fn post<T: Serialize>(&self, body: &T) -> Result<Response, Error> {
+        let mut request = ::reqwest::Client::new()
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/json")
+            .json(body);
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let res = request.send().await?;
+        debug!("Client response received");
+        check_status(res, &self.model).await
+    }
+}
+
+pub(crate) async fn check_status(res: Response, model: &str) -> Result<Response, Error> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+    let retry_after = res
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .map(Duration::from_secs);
+    let body = res.bytes().await?;
+    let err = http_error(status, retry_after, model, &body);
+    error!("Query failed {status}: {err}");
+    Err(err)
+}
 
-    let res = client
-        .post(url)
-        .header(CONTENT_TYPE, "application/json")
-        .header(AUTHORIZATION, format!("Bearer {token}"))
-        .json(&payload)
-        .send()
-        .await?;
-    debug!("Client response received");
-    // let res = res.error_for_status()?;
+pub async fn query(
+    endpoint: &Endpoint,
+    messages: Vec<Message>,
+    tools: &[ToolDefinition],
+    parameters: &Parameters,
+) -> Result<Api, Error> {
+    info!("Query {} {} messages", endpoint.url, messages.len());
+    let usage = reports_usage(&endpoint.url);
+    let payload = Payload::new(endpoint.model.clone(), messages, tools, parameters, usage);
+    let res = endpoint.post(&payload).await?;
     return Ok(Api {
         res,
-        leftover: vec![],
+        decoder: ChatDecoder::default(),
     });
 }
 
 impl Api {
-    pub async fn next(&mut self) -> Result<Option<String>, Error> {
-        if let Some(chunk) = self.res.chunk().await? {
-            let mut content = String::new();
-
-            for (i, mut subchunk) in chunk.split(|&c| c == b'\n').enumerate() {
-                if i == 0 && !self.leftover.is_empty() {
-                    self.leftover.extend(subchunk);
-                    subchunk = &self.leftover[..];
-                }
-                if subchunk.is_empty() {
-                    // Do nothing
-                } else if subchunk.starts_with(b"data: ") {
-                    if subchunk == b"data: [DONE]" {
-                        continue;
-                    }
-                    if let Ok(parsed) =
-                        serde_json::from_slice::<Chunk>(&subchunk[b"data: ".len()..])
-                    {
-                        let msg = &parsed.choices[0].delta.content;
-                        content.push_str(msg);
-                    } else {
-                        let owned = subchunk.to_owned();
-                        self.leftover.extend(owned);
-                    }
-                } else if let Ok(parsed) = serde_json::from_slice::<SseError>(&subchunk) {
-                    error!("Chunk Error {parsed:?}");
-                    return Err(Error::SseError(parsed));
-                } else {
-                    return Err(Error::InvalidChunkError(
-                        String::from_utf8_lossy(&subchunk).into(),
-                    ));
-                }
-            }
-            Ok(Some(content))
-        } else {
-            Ok(None)
-        }
-    }
-}
-
-#[tauri::command]
-pub async fn get_chunk(
-    state: tauri::State<'_, State>,
-    conversationid: u32,
-) -> Result<Option<String>, Error> {
-    let db = &state.db;
-    let (_conversation, model): (conversation::Model, Option<model::Model>) =
-        conversation::Entity::find_by_id(conversationid)
-            .find_also_related(model::Entity)
-            .one(db)
-            .await?
-            .ok_or(Error::MissingConversation(conversationid))?;
-    let model = model.expect("Associated model");
-    let mut stream = state.stream.lock().await;
-    let chunk = if let Some(ref mut stream) = &mut *stream {
-        stream.next().await?
-    } else {
-        let messages: Vec<message::Model> = message::Entity::find()
-            .filter(message::Column::ConversationId.eq(conversationid))
-            .all(db)
-            .await?;
-
-        let url = model.endpoint;
-        if url.starts_with("https://") {
-            let messages = Message::from_db(messages);
-            let cache = &state.cache;
-            let token = cache.token().expect("Expected token");
-            let mut newstream = query(url, messages, &token).await?;
-            match newstream.next().await {
-                Ok(chunk) => {
-                    *stream = Some(Stream::Api(newstream));
-                    chunk
-                }
-                Err(Error::SseError(SseError {
-                    error: InnerError::InvalidToken,
-                })) => {
-                    error!("Invalid token, deleting it");
-                    std::fs::remove_file(cache.token_path())?;
-                    return Err(Error::InvalidToken);
-                }
-                Err(err) => {
-                    return Err(err);
+    pub async fn next(&mut self) -> Result<Option<Piece>, Error> {
+        while !self.decoder.done {
+            if let Some(chunk) = self.res.chunk().await? {
+                let piece = self.decoder.feed(&chunk)?;
+                if !piece.is_empty() {
+                    return Ok(Some(piece));
                 }
+            } else {
+                self.decoder.finish()?;
+                break;
             }
-        } else {
-            let model_id = url;
-            let mut newstream = crate::commands::local::local_stream(model_id, messages).await?;
-            let chunk = newstream.next().await;
-            *stream = Some(Stream::Local(newstream));
-            chunk
         }
-    };
-    if chunk.is_none() {
-        *stream = None;
-    }
-    drop(stream);
-    if let Some(chunk) = &chunk {
-        let user: user::Model = user::Entity::find_by_id(model.user_id)
-            .one(db)
-            .await?
-            .expect("At least 1 message");
-        let message: message::Model = message::Entity::find()
-            .filter(message::Column::ConversationId.eq(conversationid))
-            .order_by_desc(message::Column::CreatedAt)
-            .one(db)
-            .await?
-            .expect("At least 1 message");
-        if message.user_id == user.id {
-            let content = message.content.clone();
-            let mut message: message::ActiveModel = message.into();
-            message.content = Set(format!("{}{}", content, chunk));
-            message.update(db).await?;
-        } else {
-            let now = Utc::now();
-            let message = message::ActiveModel {
-                conversation_id: Set(conversationid),
-                user_id: Set(user.id),
-                content: Set(chunk.to_string()),
-                created_at: Set(now.clone()),
-                updated_at: Set(now.clone()),
-                ..Default::default()
-            };
-            let _ = message.insert(db).await?;
+        Ok(None)
+    }
+}
+
+/// Sends the request until the endpoint accepts it or fails for good.
+async fn query_with_retry(
+    endpoint: &Endpoint,
+    messages: Vec<Message>,
+    tools: &[ToolDefinition],
+    parameters: &Parameters,
+    on_wait: &mut (dyn FnMut(&Error, Duration) + Send),
+) -> Result<Api, Error> {
+    RetryPolicy::default()
+        .run(
+            || query(endpoint, messages.clone(), tools, parameters),
+            on_wait,
+        )
+        .await
+}
+
+/// Hugging Face hosted inference, authenticated with the hub token.
+pub struct HuggingFace {
+    url: String,
+    cache: Cache,
+}
+
+impl HuggingFace {
+    pub fn new(url: String, cache: Cache) -> Self {
+        Self { url, cache }
+    }
+}
+
+#[async_trait::async_trait]
+impl ChatBackend for HuggingFace {
+    async fn stream(
+        &self,
+        messages: Vec<Message>,
+        tools: &[ToolDefinition],
+        parameters: &Parameters,
+        on_wait: &mut (dyn FnMut(&Error, Duration) + Send),
+    ) -> Result<Stream, Error> {
+        let token = self.cache.token().ok_or(Error::InvalidToken)?;
+        let endpoint = Endpoint::new(self.url.clone()).with_token(&token);
+        let api = query_with_retry(&endpoint, messages, tools, parameters, on_wait).await?;
+        Ok(api.into())
+    }
+}
+
+/// `top_k` and `repetition_penalty` are TGI extensions, api.openai.com and
+/// most providers reject requests carrying them.
+fn without_extensions(parameters: &Parameters) -> Parameters {
+    Parameters {
+        top_k: 0,
+        repetition_penalty: 0.0,
+        ..parameters.clone()
+    }
+}
+
+/// Any OpenAI compatible `/v1/chat/completions` endpoint.
+pub struct OpenAi {
+    endpoint: Endpoint,
+}
+
+impl OpenAi {
+    pub fn new(url: String) -> Self {
+        Self {
+            endpoint: Endpoint::new(url),
         }
     }
-    Ok(chunk)
+
+    /// `model_name` defaults to the first model the provider lists.
+    pub fn from_provider(provider: &provider::Model, model_name: Option<String>) -> Self {
+        let mut endpoint = Endpoint::new(provider.url("chat/completions")).with_provider(provider);
+        if let Some(model) = model_name.or_else(|| provider.models.0.first().cloned()) {
+            endpoint = endpoint.with_model(model);
+        }
+        Self { endpoint }
+    }
+}
+
+#[async_trait::async_trait]
+impl ChatBackend for OpenAi {
+    async fn stream(
+        &self,
+        messages: Vec<Message>,
+        tools: &[ToolDefinition],
+        parameters: &Parameters,
+        on_wait: &mut (dyn FnMut(&Error, Duration) + Send),
+    ) -> Result<Stream, Error> {
+        let parameters = without_extensions(parameters);
+        let api = query_with_retry(&self.endpoint, messages, tools, &parameters, on_wait)
+            .await
+            .map_err(|err| {
+                // Not the hub token, logging in again would not help.
+                if err.is_invalid_token() {
+                    Error::Unauthorized(self.endpoint.url.clone())
+                } else {
+                    err
+                }
+            })?;
+        Ok(api.into())
+    }
 }
 
 #[cfg(test)]
@@ -337,4 +1002,589 @@ mod tests {
             panic!("Invalid deserialization of chunk error {error:?}");
         }
     }
+
+    fn decode(chunks: &[&[u8]]) -> Result<String, Error> {
+        let mut decoder = ChatDecoder::default();
+        let mut content = String::new();
+        for chunk in chunks {
+            content.push_str(&decoder.feed(chunk)?.content);
+        }
+        decoder.finish()?;
+        Ok(content)
+    }
+
+    #[test]
+    fn decode_split_chunks() {
+        let stream: &[u8] = b"data: {\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"Hel\"}}]}\n\n\
+            : keep-alive\n\n\
+            data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"lo\"}}]}\r\n\r\n\
+            data: {\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n\
+            data: {\"choices\":[],\"usage\":{\"prompt_tokens\":1}}\n\n\
+            data: [DONE]\n\n";
+        for i in 0..stream.len() {
+            let (a, b) = stream.split_at(i);
+            assert_eq!(decode(&[a, b]).unwrap(), "Hello", "split at {i}");
+        }
+    }
+
+    #[test]
+    fn decode_usage() {
+        let mut decoder = ChatDecoder::default();
+        decoder
+            .feed(b"data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"}}],\"usage\":null}\n\n")
+            .unwrap();
+        assert_eq!(decoder.usage, None);
+        decoder
+            .feed(b"data: {\"choices\":[],\"usage\":{\"prompt_tokens\":12,\"completion_tokens\":1,\"total_tokens\":13}}\n\n")
+            .unwrap();
+        assert_eq!(
+            decoder.usage,
+            Some(Usage {
+                prompt_tokens: 12,
+                completion_tokens: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn openai_payload_leaves_out_extensions() {
+        let parameters = Parameters {
+            top_k: 40,
+            repetition_penalty: 1.2,
+            ..Default::default()
+        };
+        let payload = serde_json::to_value(Payload::new(
+            "gpt-4o".to_string(),
+            vec![],
+            &[],
+            &parameters,
+            true,
+        ))
+        .unwrap();
+        assert_eq!(payload["top_k"], 40);
+        let payload = serde_json::to_value(Payload::new(
+            "gpt-4o".to_string(),
+            vec![],
+            &[],
+            &without_extensions(&parameters),
+            true,
+        ))
+        .unwrap();
+        assert!(payload.get("top_k").is_none());
+        assert!(payload.get("repetition_penalty").is_none());
+    }
+
+    #[test]
+    fn usage_only_asked_of_known_servers() {
+        assert!(reports_usage("https://api.openai.com/v1/chat/completions"));
+        assert!(reports_usage(
+            "https://api-inference.huggingface.co/models/meta-llama/Llama-3.1-8B-Instruct/v1/chat/completions"
+        ));
+        assert!(!reports_usage("http://localhost:8080/v1/chat/completions"));
+        assert!(!reports_usage("https://api.openai.com.example.org/v1"));
+        let payload = serde_json::to_value(Payload::new(
+            "local".to_string(),
+            vec![],
+            &[],
+            &Parameters::default(),
+            false,
+        ))
+        .unwrap();
+        assert!(payload.get("stream_options").is_none());
+    }
+
+    #[test]
+    fn decode_reasoning() {
+        let mut decoder = ChatDecoder::default();
+        let piece = decoder
+            .feed(b"data: {\"choices\":[{\"delta\":{\"content\":null,\"reasoning_content\":\"Hmm\"}}]}\n\n\
+                data: {\"choices\":[{\"delta\":{\"reasoning\":\", easy.\"}}]}\n\n\
+                data: {\"choices\":[{\"delta\":{\"content\":\"42\"}}]}\n\n")
+            .unwrap();
+        assert_eq!(
+            piece,
+            Piece {
+                content: "42".to_string(),
+                reasoning: "Hmm, easy.".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn decode_tool_calls() {
+        let mut decoder = ChatDecoder::default();
+        let content = decoder
+            .feed(b"data: {\"choices\":[{\"delta\":{\"role\":\"assistant\",\"content\":null,\"tool_calls\":[{\"index\":0,\"id\":\"call_abc\",\"type\":\"function\",\"function\":{\"name\":\"calculator\",\"arguments\":\"\"}}]}}]}\n\n\
+                data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"{\\\"expression\\\":\"}}]}}]}\n\n\
+                data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\" \\\"6*7\\\"}\"}}]}}]}\n\n\
+                data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":1,\"function\":{\"name\":\"current_time\"}}]}}]}\n\n\
+                data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"tool_calls\"}]}\n\n\
+                data: [DONE]\n\n")
+            .unwrap();
+        assert!(content.is_empty());
+        assert_eq!(
+            with_ids(decoder.tool_calls),
+            vec![
+                ToolCall {
+                    id: "call_abc".to_string(),
+                    name: "calculator".to_string(),
+                    arguments: r#"{"expression": "6*7"}"#.to_string(),
+                },
+                ToolCall {
+                    id: "call_1".to_string(),
+                    name: "current_time".to_string(),
+                    arguments: String::new(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn decode_error_payloads() {
+        // Plain json body, not an event stream.
+        let error = br#"{"error": "Authorization header is correct, but the token seems invalid"}"#;
+        assert!(decode(&[error]).unwrap_err().is_invalid_token());
+
+        // Error event in the middle of a stream.
+        let stream = b"data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"}}]}\n\n\
+            event: error\ndata: {\"error\":\"Input validation error\",\"error_type\":\"validation\"}\n\n";
+        match decode(&[stream]) {
+            Err(Error::SseError(SseError {
+                error: InnerError::Default(message),
+            })) => assert_eq!(message, "Input validation error"),
+            other => panic!("Unexpected {other:?}"),
+        }
+
+        // Garbage
+        match decode(&[b"data: {not json}\n\n"]) {
+            Err(Error::InvalidChunkError(data)) => assert_eq!(data, "{not json}"),
+            other => panic!("Unexpected {other:?}"),
+        }
+        match decode(&[b"<html>Bad gateway</html>"]) {
+            Err(Error::InvalidChunkError(data)) => assert_eq!(data, "<html>Bad gateway</html>"),
+            other => panic!("Unexpected {other:?}"),
+        }
+    }
+
+    #[test]
+    fn decode_http_errors() {
+        let model = "some/model";
+        let error = |status: u16, retry_after: Option<u64>, body: &str| {
+            http_error(
+                StatusCode::from_u16(status).unwrap(),
+                retry_after.map(Duration::from_secs),
+                model,
+                body.as_bytes(),
+            )
+        };
+
+        match error(429, Some(30), r#"{"error":"Rate limit reached"}"#) {
+            Error::RateLimited { retry_after } => {
+                assert_eq!(retry_after, Some(Duration::from_secs(30)))
+            }
+            other => panic!("Unexpected {other:?}"),
+        }
+        match error(
+            503,
+            None,
+            r#"{"error":"Model some/model is currently loading","estimated_time":20.5}"#,
+        ) {
+            Error::ModelLoading { estimated_time } => {
+                assert_eq!(estimated_time, Some(Duration::from_secs_f64(20.5)))
+            }
+            other => panic!("Unexpected {other:?}"),
+        }
+        match error(404, None, r#"{"error":"Model some/model does not exist"}"#) {
+            Error::ModelNotFound(name) => assert_eq!(name, model),
+            other => panic!("Unexpected {other:?}"),
+        }
+        let too_long = r#"{"error":"Input validation error: `inputs` tokens + `max_new_tokens` must be <= 4096. Given: 4000 `inputs` tokens and 1024 `max_new_tokens`","error_type":"validation"}"#;
+        assert_eq!(error(422, None, too_long).kind(), ErrorKind::ContextTooLong);
+        let openai = r#"{"error":{"message":"This model's maximum context length is 8192 tokens","type":"invalid_request_error"}}"#;
+        assert_eq!(error(400, None, openai).kind(), ErrorKind::ContextTooLong);
+        match error(
+            422,
+            None,
+            r#"{"error":"`temperature` must be strictly positive"}"#,
+        ) {
+            Error::Validation(message) => {
+                assert_eq!(message, "`temperature` must be strictly positive")
+            }
+            other => panic!("Unexpected {other:?}"),
+        }
+        assert_eq!(
+            error(
+                401,
+                None,
+                r#"{"error":"Invalid credentials in Authorization header"}"#
+            )
+            .kind(),
+            ErrorKind::InvalidToken
+        );
+        assert_eq!(
+            error(
+                400,
+                None,
+                r#"{"error":"Authorization header is correct, but the token seems invalid"}"#
+            )
+            .kind(),
+            ErrorKind::InvalidToken
+        );
+        match error(502, None, "Bad gateway") {
+            Error::Http { status, message } => {
+                assert_eq!(status, 502);
+                assert_eq!(message, "Bad gateway");
+            }
+            other => panic!("Unexpected {other:?}"),
+        }
+        // A 503 which is not about loading is just an unavailable server.
+        assert_eq!(error(503, None, "").kind(), ErrorKind::Other);
+    }
+
+    #[test]
+    fn model_name_from_endpoint() {
+        assert_eq!(
+            model_name("https://api-inference.huggingface.co/models/meta-llama/Llama-3.1-8B-Instruct/v1/chat/completions"),
+            "meta-llama/Llama-3.1-8B-Instruct"
+        );
+        assert_eq!(model_name("https://example.com/v1/chat/completions"), "tgi");
+    }
+
+    /// Answers a single request with `response`, returns its url and the raw
+    /// request it received.
+    async fn mock_server(response: &'static str) -> (String, tokio::task::JoinHandle<String>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![];
+            let mut buf = [0; 4096];
+            loop {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .filter_map(|line| line.split_once(':'))
+                        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                        .and_then(|(_, value)| value.trim().parse().ok())
+                        .unwrap_or(0);
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+                if n == 0 {
+                    break;
+                }
+            }
+            socket.write_all(response.as_bytes()).await.unwrap();
+            socket.shutdown().await.ok();
+            String::from_utf8(request).unwrap()
+        });
+        (url, server)
+    }
+
+    fn lan_provider(url: String) -> provider::Model {
+        provider::Model {
+            id: 1,
+            name: "lan".to_string(),
+            base_url: format!("{url}/v1/"),
+            api_key: Some("sk-test".to_string()),
+            headers: provider::Headers(BTreeMap::from([("x-team".to_string(), "lan".to_string())])),
+            models: provider::ModelNames(vec!["llama".to_string()]),
+        }
+    }
+
+    fn hello() -> Vec<Message> {
+        vec![Message::new(Role::User, "Hello".to_string())]
+    }
+
+    #[tokio::test]
+    async fn openai_provider_streams() {
+        let (url, server) = mock_server(
+            "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\nconnection: close\r\n\r\n\
+            data: {\"choices\":[{\"delta\":{\"content\":\"Hi \"}}]}\n\n\
+            data: {\"choices\":[{\"delta\":{\"content\":\"there\"}}]}\n\n\
+            data: [DONE]\n\n",
+        )
+        .await;
+        let backend = OpenAi::from_provider(&lan_provider(url), None);
+        let mut stream = backend
+            .stream(hello(), &[], &Parameters::default(), &mut |_, _| {})
+            .await
+            .unwrap();
+        let mut content = String::new();
+        while let Some(delta) = stream.next().await.unwrap() {
+            content.push_str(&delta.content);
+        }
+        assert_eq!(content, "Hi there");
+
+        let request = server.await.unwrap();
+        assert!(
+            request.starts_with("POST /v1/chat/completions "),
+            "{request}"
+        );
+        let headers = request.to_lowercase();
+        assert!(
+            headers.contains("authorization: bearer sk-test"),
+            "{request}"
+        );
+        assert!(headers.contains("x-team: lan"), "{request}");
+        assert!(request.contains(r#""model":"llama""#), "{request}");
+    }
+
+    #[tokio::test]
+    async fn openai_provider_rejects_key() {
+        let (url, _server) = mock_server(
+            "HTTP/1.1 401 Unauthorized\r\ncontent-type: application/json\r\n\
+            content-length: 27\r\nconnection: close\r\n\r\n\
+            {\"error\":\"Invalid API key\"}",
+        )
+        .await;
+        let backend = OpenAi::from_provider(&lan_provider(url), None);
+        let err = backend
+            .stream(hello(), &[], &Parameters::default(), &mut |_, _| {})
+            .await
+            .err()
+            .unwrap();
+        // Must not be mistaken for the hub token.
+        assert!(!err.is_invalid_token());
+        assert_eq!(err.kind(), ErrorKind::Unauthorized);
+    }
+
+    #[test]
+    fn system_role() {
+        let message = Message::new(Role::System, "Answer in French".to_string());
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            serde_json::json!({"role": "system", "content": "Answer in French"})
+        );
+    }
+
+    #[test]
+    fn tool_messages() {
+        let call = Message {
+            tool_calls: vec![ToolCall {
+                id: "call_0".to_string(),
+                name: "current_time".to_string(),
+                arguments: "{}".to_string(),
+            }],
+            ..Message::new(Role::Assistant, String::new())
+        };
+        assert_eq!(
+            serde_json::to_value(&call).unwrap(),
+            serde_json::json!({
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [{
+                    "id": "call_0",
+                    "type": "function",
+                    "function": {"name": "current_time", "arguments": "{}"},
+                }],
+            })
+        );
+        let result = Message {
+            tool_call_id: Some("call_0".to_string()),
+            ..Message::new(Role::Tool, "Monday".to_string())
+        };
+        assert_eq!(
+            serde_json::to_value(&result).unwrap(),
+            serde_json::json!({"role": "tool", "content": "Monday", "tool_call_id": "call_0"})
+        );
+    }
+
+    #[test]
+    fn image_messages() {
+        let message = Message {
+            images: vec![InlineImage {
+                mime_type: "image/png".to_string(),
+                data: b"png".to_vec(),
+            }],
+            ..Message::new(Role::User, "What is this?".to_string())
+        };
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            serde_json::json!({
+                "role": "user",
+                "content": [
+                    {"type": "text", "text": "What is this?"},
+                    {"type": "image_url", "image_url": {"url": "data:image/png;base64,cG5n"}},
+                ],
+            })
+        );
+    }
+
+    /// Roles do not depend on who wrote the message, every test message
+    /// comes from the same user.
+    fn db_message(id: u32, role: Role, content: &str) -> message::Model {
+        message::Model {
+            id,
+            user_id: 1,
+            content: content.to_string(),
+            role,
+            conversation_id: 1,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+            truncated: false,
+            kind: MessageKind::Text,
+            summary_until: None,
+            parent_id: id.checked_sub(1).filter(|&parent| parent > 0),
+            active: true,
+            metrics: None,
+            tool_calls: None,
+            tool_call_id: None,
+            images: None,
+            sources: None,
+            reasoning: None,
+        }
+    }
+
+    fn roles(turns: &[Turn]) -> Vec<Role> {
+        turns.iter().map(|turn| turn.message.role).collect()
+    }
+
+    #[test]
+    fn from_db_merges_turns() {
+        let turns = Message::from_db(
+            vec![
+                db_message(1, Role::User, "Hi"),
+                db_message(2, Role::User, "Are you there?"),
+                db_message(3, Role::Assistant, "Yes"),
+            ],
+            None,
+        );
+        assert_eq!(roles(&turns), vec![Role::User, Role::Assistant]);
+        assert_eq!(turns[0].ids, vec![1, 2]);
+        assert_eq!(turns[0].message.content, "Hi\nAre you there?");
+    }
+
+    #[test]
+    fn from_db_keeps_tool_turns_apart() {
+        let tool_call = message::ToolCalls(vec![ToolCall {
+            id: "call_0".to_string(),
+            name: "calculator".to_string(),
+            arguments: r#"{"expression": "6*7"}"#.to_string(),
+        }]);
+        let turns = Message::from_db(
+            vec![
+                db_message(1, Role::User, "What is 6 times 7?"),
+                db_message(2, Role::Assistant, "Let me compute it."),
+                message::Model {
+                    tool_calls: Some(tool_call),
+                    ..db_message(3, Role::Assistant, "")
+                },
+                message::Model {
+                    tool_call_id: Some("call_0".to_string()),
+                    ..db_message(4, Role::Tool, "42")
+                },
+                db_message(5, Role::Assistant, "It is 42."),
+            ],
+            None,
+        );
+        assert_eq!(
+            roles(&turns),
+            vec![
+                Role::User,
+                Role::Assistant,
+                Role::Assistant,
+                Role::Tool,
+                Role::Assistant
+            ]
+        );
+        assert_eq!(turns[2].message.tool_calls[0].name, "calculator");
+        assert_eq!(turns[3].message.tool_call_id.as_deref(), Some("call_0"));
+    }
+
+    #[test]
+    fn from_db_model_speaks_first() {
+        let turns = Message::from_db(
+            vec![
+                db_message(1, Role::Assistant, "How can I help?"),
+                db_message(2, Role::User, "Hi"),
+                db_message(3, Role::Assistant, "Hello"),
+            ],
+            None,
+        );
+        assert_eq!(
+            roles(&turns),
+            vec![Role::Assistant, Role::User, Role::Assistant]
+        );
+    }
+
+    #[test]
+    fn from_db_system_messages() {
+        let turns = Message::from_db(
+            vec![
+                db_message(1, Role::User, "Hi"),
+                db_message(2, Role::System, "The user left the chat"),
+                db_message(3, Role::Assistant, "Bye"),
+            ],
+            Some("Be brief".to_string()),
+        );
+        assert_eq!(
+            roles(&turns),
+            vec![Role::System, Role::User, Role::System, Role::Assistant]
+        );
+        assert_eq!(turns[0].ids, Vec::<u32>::new());
+        assert_eq!(turns[0].message.content, "Be brief");
+        assert_eq!(turns[2].ids, vec![2]);
+        assert_eq!(Message::from_db(vec![], None).len(), 0);
+    }
+
+    #[test]
+    fn from_db_substitutes_summary() {
+        let summary = |id, until, content| message::Model {
+            kind: MessageKind::Summary,
+            summary_until: Some(until),
+            ..db_message(id, Role::Assistant, content)
+        };
+        let messages = vec![
+            db_message(1, Role::User, "Hi"),
+            db_message(2, Role::Assistant, "Hello"),
+            db_message(3, Role::User, "My name is Ada"),
+            db_message(4, Role::Assistant, "Nice to meet you"),
+            summary(5, 2, "The user said hi."),
+            db_message(6, Role::User, "What is my name?"),
+            summary(7, 4, "The user is called Ada."),
+        ];
+        let turns = Message::from_db(messages.clone(), None);
+        assert_eq!(turns.len(), 1);
+        assert_eq!(turns[0].ids, vec![6]);
+        assert_eq!(
+            turns[0].message.content,
+            format!("{SUMMARY_PREFIX}\nThe user is called Ada.\n\nWhat is my name?")
+        );
+
+        // Without the latest one, the previous summary is used.
+        let turns = Message::from_db(messages[..6].to_vec(), None);
+        assert_eq!(turns.len(), 3);
+        assert_eq!(turns[0].ids, vec![3]);
+        assert_eq!(
+            turns[0].message.content,
+            format!("{SUMMARY_PREFIX}\nThe user said hi.\n\nMy name is Ada")
+        );
+        assert_eq!(turns[1].ids, vec![4]);
+        assert_eq!(turns[2].ids, vec![6]);
+
+        // A summary is its own turn when the model spoke next, after the
+        // system prompt.
+        let turns = Message::from_db(
+            vec![
+                db_message(1, Role::User, "Hi"),
+                db_message(2, Role::Assistant, "Hello"),
+                db_message(3, Role::User, "Tell me a story"),
+                db_message(4, Role::Assistant, "Once upon a time"),
+                summary(5, 3, "The user asked for a story."),
+            ],
+            Some("You are a storyteller".to_string()),
+        );
+        assert_eq!(
+            roles(&turns),
+            vec![Role::System, Role::User, Role::Assistant]
+        );
+        assert_eq!(turns[1].ids, Vec::<u32>::new());
+        assert_eq!(turns[2].ids, vec![4]);
+    }
 }
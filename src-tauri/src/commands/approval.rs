@@ -0,0 +1,332 @@
+//! Tool calls wait for the user's approval before they run, unless the tool
+//! is always allowed. `generate` sends a `ToolCallRequest` and waits, the
+//! webview answers with `resolve_tool_call`. A view opened meanwhile finds
+//! the calls with `get_pending_tool_calls`.
+
+use crate::commands::mcp;
+use crate::commands::tools::Registry;
+use crate::entities::message::ToolCall;
+use crate::entities::tool_permission;
+use crate::State;
+use chrono::Utc;
+use log::{info, warn};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, ColumnTrait, DatabaseConnection, DbErr, EntityTrait,
+    QueryFilter,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::{oneshot, Mutex};
+
+/// Calls nobody answers within this time are rejected, so the conversation
+/// is not blocked for good.
+pub const APPROVAL_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Tool call {0} is not waiting for approval")]
+    NotPending(String),
+
+    #[error("Db error {0}")]
+    DbError(#[from] DbErr),
+}
+
+impl serde::Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
+
+/// The user's answer to a tool call.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "decision")]
+pub enum Approval {
+    /// Runs the call with `arguments`, which the user may have edited.
+    /// `always` stops asking for this tool.
+    Approve {
+        arguments: String,
+        always: bool,
+    },
+    Reject,
+}
+
+/// A call waiting for the user.
+pub struct Pending {
+    conversation_id: u32,
+    tool_call: ToolCall,
+    tx: oneshot::Sender<Approval>,
+}
+
+/// The calls waiting for the user, keyed by message id and call id.
+pub type Approvals = Mutex<HashMap<(u32, String), Pending>>;
+
+/// Asks the user whether `call` of message `message_id` may run, `notify`
+/// tells the webview and returns whether it could. The call is rejected when
+/// it could not or after `timeout`. `None` when the generation was cancelled
+/// meanwhile.
+pub async fn request(
+    approvals: &Approvals,
+    conversation_id: u32,
+    message_id: u32,
+    call: &ToolCall,
+    notify: impl FnOnce() -> bool,
+    cancel: &mut oneshot::Receiver<()>,
+    timeout: Duration,
+) -> Option<Approval> {
+    let key = (message_id, call.id.clone());
+    let (tx, rx) = oneshot::channel();
+    let pending = Pending {
+        conversation_id,
+        tool_call: call.clone(),
+        tx,
+    };
+    approvals.lock().await.insert(key.clone(), pending);
+    if !notify() {
+        warn!("Could not ask for tool call {}, rejecting it", call.id);
+        approvals.lock().await.remove(&key);
+        return Some(Approval::Reject);
+    }
+    tokio::select! {
+        approval = rx => Some(approval.unwrap_or(Approval::Reject)),
+        _ = tokio::time::sleep(timeout) => {
+            warn!("Tool call {} was not answered, rejecting it", call.id);
+            approvals.lock().await.remove(&key);
+            Some(Approval::Reject)
+        }
+        _ = cancel => {
+            approvals.lock().await.remove(&key);
+            None
+        }
+    }
+}
+
+async fn resolve(
+    approvals: &Approvals,
+    message_id: u32,
+    call_id: String,
+    approval: Approval,
+) -> Result<(), Error> {
+    let pending = approvals
+        .lock()
+        .await
+        .remove(&(message_id, call_id.clone()))
+        .ok_or(Error::NotPending(call_id))?;
+    // The generation may have been cancelled meanwhile.
+    pending.tx.send(approval).ok();
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PendingToolCall {
+    message_id: u32,
+    tool_call: ToolCall,
+}
+
+async fn pending(approvals: &Approvals, conversation_id: u32) -> Vec<PendingToolCall> {
+    approvals
+        .lock()
+        .await
+        .iter()
+        .filter(|(_, pending)| pending.conversation_id == conversation_id)
+        .map(|((message_id, _), pending)| PendingToolCall {
+            message_id: *message_id,
+            tool_call: pending.tool_call.clone(),
+        })
+        .collect()
+}
+
+/// Whether `name` may run without asking.
+pub async fn is_allowed(db: &DatabaseConnection, name: &str) -> Result<bool, DbErr> {
+    Ok(tool_permission::Entity::find()
+        .filter(tool_permission::Column::Name.eq(name))
+        .one(db)
+        .await?
+        .is_some_and(|permission| permission.always_allow))
+}
+
+pub async fn set_allowed(
+    db: &DatabaseConnection,
+    name: &str,
+    always_allow: bool,
+) -> Result<(), DbErr> {
+    info!("Tool {name} always allowed: {always_allow}");
+    let existing = tool_permission::Entity::find()
+        .filter(tool_permission::Column::Name.eq(name))
+        .one(db)
+        .await?;
+    match existing {
+        Some(permission) => {
+            let mut permission: tool_permission::ActiveModel = permission.into();
+            permission.always_allow = Set(always_allow);
+            permission.updated_at = Set(Utc::now());
+            permission.update(db).await?;
+        }
+        None => {
+            let permission = tool_permission::ActiveModel {
+                name: Set(name.to_string()),
+                always_allow: Set(always_allow),
+                updated_at: Set(Utc::now()),
+                ..Default::default()
+            };
+            permission.insert(db).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Answers the call `callid` of message `messageid`, which a generation is
+/// waiting on.
+#[tauri::command]
+pub async fn resolve_tool_call(
+    state: tauri::State<'_, State>,
+    messageid: u32,
+    callid: String,
+    approval: Approval,
+) -> Result<(), Error> {
+    resolve(&state.approvals, messageid, callid, approval).await
+}
+
+/// The calls of the conversation waiting for the user, for a view opened
+/// while the generation waits.
+#[tauri::command]
+pub async fn get_pending_tool_calls(
+    state: tauri::State<'_, State>,
+    conversationid: u32,
+) -> Result<Vec<PendingToolCall>, Error> {
+    Ok(pending(&state.approvals, conversationid).await)
+}
+
+#[derive(Serialize)]
+pub struct ToolPermission {
+    name: String,
+    description: String,
+    always_allow: bool,
+}
+
+/// The tools the model can be offered, the MCP servers' included, and
+/// whether they run without asking.
+#[tauri::command]
+pub async fn get_tool_permissions(
+    state: tauri::State<'_, State>,
+) -> Result<Vec<ToolPermission>, Error> {
+    let allowed: Vec<String> = tool_permission::Entity::find()
+        .filter(tool_permission::Column::AlwaysAllow.eq(true))
+        .all(&state.db)
+        .await?
+        .into_iter()
+        .map(|permission| permission.name)
+        .collect();
+    Ok(mcp::with_servers(&state, Registry::builtin())
+        .await
+        .definitions()
+        .into_iter()
+        .map(|definition| ToolPermission {
+            always_allow: allowed.contains(&definition.name),
+            name: definition.name,
+            description: definition.description,
+        })
+        .collect())
+}
+
+#[tauri::command]
+pub async fn set_tool_permission(
+    state: tauri::State<'_, State>,
+    name: String,
+    alwaysallow: bool,
+) -> Result<(), Error> {
+    Ok(set_allowed(&state.db, &name, alwaysallow).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn approvals_from_the_webview() {
+        let approve: Approval = serde_json::from_str(
+            r#"{"decision": "approve", "arguments": "{\"expression\": \"1 + 1\"}", "always": true}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            approve,
+            Approval::Approve {
+                arguments: r#"{"expression": "1 + 1"}"#.to_string(),
+                always: true,
+            }
+        );
+        let reject: Approval = serde_json::from_str(r#"{"decision": "reject"}"#).unwrap();
+        assert_eq!(reject, Approval::Reject);
+    }
+
+    fn call() -> ToolCall {
+        ToolCall {
+            id: "call_0".to_string(),
+            name: "calculator".to_string(),
+            arguments: "{}".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn pending_until_answered() {
+        let approvals = Approvals::default();
+        let (_cancel_tx, mut cancel) = oneshot::channel();
+        let call = call();
+        let ask = request(
+            &approvals,
+            1,
+            7,
+            &call,
+            || true,
+            &mut cancel,
+            APPROVAL_TIMEOUT,
+        );
+        let answer = async {
+            // A view opened meanwhile finds the call.
+            while pending(&approvals, 1).await.is_empty() {
+                tokio::task::yield_now().await;
+            }
+            assert_eq!(
+                pending(&approvals, 1).await,
+                [PendingToolCall {
+                    message_id: 7,
+                    tool_call: call.clone(),
+                }]
+            );
+            assert!(pending(&approvals, 2).await.is_empty());
+            resolve(&approvals, 7, "call_0".to_string(), Approval::Reject)
+                .await
+                .unwrap();
+        };
+        let (approval, ()) = tokio::join!(ask, answer);
+        assert_eq!(approval, Some(Approval::Reject));
+        assert!(approvals.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejected_when_nobody_answers() {
+        let approvals = Approvals::default();
+        let (_cancel_tx, mut cancel) = oneshot::channel();
+        let call = call();
+        // The webview is gone.
+        let approval = request(
+            &approvals,
+            1,
+            7,
+            &call,
+            || false,
+            &mut cancel,
+            APPROVAL_TIMEOUT,
+        );
+        assert_eq!(approval.await, Some(Approval::Reject));
+        assert!(approvals.lock().await.is_empty());
+        // Nobody answers in time.
+        let timeout = Duration::from_millis(10);
+        let approval = request(&approvals, 1, 7, &call, || true, &mut cancel, timeout);
+        assert_eq!(approval.await, Some(Approval::Reject));
+        assert!(approvals.lock().await.is_empty());
+    }
+}
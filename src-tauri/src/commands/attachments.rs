@@ -0,0 +1,109 @@
+//! Files the user shares with the model. Only their text is kept, the model
+//! reads it through the `read_attachment` tool, or in the prompt when the
+//! file was sent with a message.
+
+use crate::commands::documents;
+use crate::entities::{attachment, conversation};
+use crate::State;
+use chrono::Utc;
+use log::info;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, ColumnTrait, EntityTrait, QueryFilter, QueryOrder,
+};
+
+/// Larger files are refused before their text is extracted.
+const MAX_FILE_BYTES: usize = 20 << 20;
+
+/// Larger texts are refused rather than stored.
+const MAX_ATTACHMENT_BYTES: usize = 1 << 20;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Missing conversation {0}")]
+    MissingConversation(u32),
+
+    #[error("{0} is too large to attach, the limit is 20 MB")]
+    TooLarge(String),
+
+    #[error("The text of {0} is too long, the limit is 1 MB")]
+    TooLong(String),
+
+    #[error(transparent)]
+    Document(#[from] documents::Error),
+
+    #[error("Db error {0}")]
+    DbError(#[from] sea_orm::DbErr),
+}
+
+impl serde::Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
+
+/// Stores the text of the file, to be read by the tool or sent with
+/// `new_message`.
+#[tauri::command]
+pub async fn attach_file(
+    state: tauri::State<'_, State>,
+    conversationid: u32,
+    name: String,
+    data: Vec<u8>,
+) -> Result<attachment::Model, Error> {
+    let db = &state.db;
+    if data.len() > MAX_FILE_BYTES {
+        return Err(Error::TooLarge(name));
+    }
+    conversation::Entity::find_by_id(conversationid)
+        .one(db)
+        .await?
+        .ok_or(Error::MissingConversation(conversationid))?;
+    // Pdf parsing is slow and panics on some broken files.
+    let file = name.clone();
+    let content = tauri::async_runtime::spawn_blocking(move || documents::extract(&file, &data))
+        .await
+        .map_err(|_| documents::Error::Pdf {
+            name: name.clone(),
+            message: "the file is damaged".to_string(),
+        })??;
+    if content.len() > MAX_ATTACHMENT_BYTES {
+        return Err(Error::TooLong(name));
+    }
+    info!("Attaching {name} to conversation {conversationid}");
+    let attachment = attachment::ActiveModel {
+        conversation_id: Set(conversationid),
+        name: Set(name),
+        content: Set(content),
+        created_at: Set(Utc::now()),
+        ..Default::default()
+    };
+    Ok(attachment.insert(db).await?)
+}
+
+/// The files not sent with a message yet.
+#[tauri::command]
+pub async fn get_attachments(
+    state: tauri::State<'_, State>,
+    conversationid: u32,
+) -> Result<Vec<attachment::Model>, Error> {
+    Ok(attachment::Entity::find()
+        .filter(attachment::Column::ConversationId.eq(conversationid))
+        .filter(attachment::Column::MessageId.is_null())
+        .order_by_asc(attachment::Column::Id)
+        .all(&state.db)
+        .await?)
+}
+
+#[tauri::command]
+pub async fn delete_attachment(
+    state: tauri::State<'_, State>,
+    attachmentid: u32,
+) -> Result<(), Error> {
+    attachment::Entity::delete_by_id(attachmentid)
+        .exec(&state.db)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use crate::commands::api::{Endpoint, Error, HuggingFace, Message, OpenAi, Stream};
+use crate::commands::local::Local;
+use crate::commands::ollama::Ollama;
+use crate::commands::tgi::Tgi;
+use crate::commands::tools::ToolDefinition;
+use crate::entities::model::{self, ModelKind, Parameters};
+use crate::entities::provider;
+use hf_hub::Cache;
+use sea_orm::{DatabaseConnection, DbErr, EntityTrait};
+use std::time::Duration;
+
+/// Something that can stream a chat completion.
+#[async_trait::async_trait]
+pub trait ChatBackend: Send + Sync {
+    /// Starts the reply to `messages`, the model may call `tools` instead of
+    /// answering. Backends which retry call `on_wait` before each wait.
+    async fn stream(
+        &self,
+        messages: Vec<Message>,
+        tools: &[ToolDefinition],
+        parameters: &Parameters,
+        on_wait: &mut (dyn FnMut(&Error, Duration) + Send),
+    ) -> Result<Stream, Error>;
+}
+
+/// The backend serving `model`, `provider` is the one `model.provider_id` points to.
+pub fn backend(
+    cache: &Cache,
+    model: &model::Model,
+    provider: Option<&provider::Model>,
+) -> Box<dyn ChatBackend> {
+    let endpoint = model.endpoint.clone();
+    match model.kind {
+        ModelKind::HuggingFace => Box::new(HuggingFace::new(endpoint, cache.clone())),
+        ModelKind::OpenAi => match provider {
+            Some(provider) => Box::new(OpenAi::from_provider(provider, model.model_name.clone())),
+            None => Box::new(OpenAi::new(endpoint)),
+        },
+        ModelKind::Tgi => {
+            let model_id = model.model_name.clone().unwrap_or_default();
+            let url = format!("{}/generate_stream", endpoint.trim_end_matches('/'));
+            let mut endpoint = Endpoint::new(url).with_model(model_id.clone());
+            if let Some(provider) = provider {
+                endpoint = endpoint.with_provider(provider);
+            }
+            Box::new(Tgi::new(endpoint, model_id, cache.clone()))
+        }
+        ModelKind::Ollama => Box::new(Ollama::new(
+            endpoint,
+            model.model_name.clone().unwrap_or_default(),
+        )),
+        ModelKind::Local => Box::new(Local::new(endpoint)),
+    }
+}
+
+/// Like `backend`, loading the model's provider first.
+pub async fn load_backend(
+    db: &DatabaseConnection,
+    cache: &Cache,
+    model: &model::Model,
+) -> Result<Box<dyn ChatBackend>, DbErr> {
+    let provider = match model.provider_id {
+        Some(provider_id) => provider::Entity::find_by_id(provider_id).one(db).await?,
+        None => None,
+    };
+    Ok(backend(cache, model, provider.as_ref()))
+}
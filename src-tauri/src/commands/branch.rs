@@ -0,0 +1,192 @@
+//! Conversations are trees: every message answers its `parent_id`. Editing a
+//! message or regenerating a reply adds a sibling, and following the active
+//! sibling at each step gives the branch that is shown and sent to the model.
+
+use crate::entities::message::{self, MessageKind};
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter};
+use std::collections::{HashMap, HashSet};
+
+/// Every message of the conversation, summaries included.
+pub async fn load(
+    db: &DatabaseConnection,
+    conversation_id: u32,
+) -> Result<Vec<message::Model>, DbErr> {
+    message::Entity::find()
+        .filter(message::Column::ConversationId.eq(conversation_id))
+        .all(db)
+        .await
+}
+
+fn children(
+    messages: &[message::Model],
+    parent_id: Option<u32>,
+) -> impl Iterator<Item = &message::Model> {
+    messages
+        .iter()
+        .filter(move |message| message.kind == MessageKind::Text && message.parent_id == parent_id)
+}
+
+/// The last message of the active branch, `None` when there are no messages.
+/// Without an active child the most recent one is followed.
+pub fn active_leaf(messages: &[message::Model]) -> Option<u32> {
+    let mut leaf = None;
+    while let Some(child) = children(messages, leaf).max_by_key(|child| (child.active, child.id)) {
+        leaf = Some(child.id);
+    }
+    leaf
+}
+
+/// The messages from the first one down to `leaf`, in order, with the
+/// summaries of any of them.
+pub fn branch(messages: &[message::Model], leaf: Option<u32>) -> Vec<message::Model> {
+    let by_id: HashMap<u32, &message::Model> = messages
+        .iter()
+        .map(|message| (message.id, message))
+        .collect();
+    let mut path = vec![];
+    let mut next = leaf;
+    while let Some(message) = next.and_then(|id| by_id.get(&id)) {
+        path.push((*message).clone());
+        // Parents are always older, this also guards against cycles.
+        next = message.parent_id.filter(|&parent| parent < message.id);
+    }
+    let ids: HashSet<u32> = path.iter().map(|message| message.id).collect();
+    path.extend(
+        messages
+            .iter()
+            .filter(|message| {
+                message.kind == MessageKind::Summary
+                    && message
+                        .summary_until
+                        .is_some_and(|until| ids.contains(&until))
+            })
+            .cloned(),
+    );
+    path.sort_by_key(|message| message.id);
+    path
+}
+
+pub fn active_branch(messages: &[message::Model]) -> Vec<message::Model> {
+    branch(messages, active_leaf(messages))
+}
+
+/// The messages `message` can be swapped with, itself included, oldest first.
+pub fn siblings(messages: &[message::Model], message: &message::Model) -> Vec<u32> {
+    let mut ids: Vec<u32> = children(messages, message.parent_id)
+        .map(|sibling| sibling.id)
+        .collect();
+    ids.sort_unstable();
+    ids
+}
+
+/// Marks the answers to `parent_id` as unused, before another one is made
+/// the active one. Meant to run in the same transaction, so a failure does
+/// not leave the branch without an active message.
+pub async fn deactivate_children(
+    db: &impl ConnectionTrait,
+    conversation_id: u32,
+    parent_id: Option<u32>,
+) -> Result<(), DbErr> {
+    let parent = match parent_id {
+        Some(parent_id) => message::Column::ParentId.eq(parent_id),
+        None => message::Column::ParentId.is_null(),
+    };
+    message::Entity::update_many()
+        .col_expr(message::Column::Active, Expr::value(false))
+        .filter(message::Column::ConversationId.eq(conversation_id))
+        .filter(message::Column::Kind.eq(MessageKind::Text))
+        .filter(parent)
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::message::Role;
+    use chrono::Utc;
+
+    fn db_message(id: u32, parent_id: Option<u32>, active: bool) -> message::Model {
+        message::Model {
+            id,
+            user_id: 1,
+            content: format!("Message {id}"),
+            role: Role::User,
+            conversation_id: 1,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            truncated: false,
+            kind: MessageKind::Text,
+            summary_until: None,
+            parent_id,
+            active,
+            metrics: None,
+            tool_calls: None,
+            tool_call_id: None,
+            images: None,
+            sources: None,
+            reasoning: None,
+        }
+    }
+
+    fn ids(messages: &[message::Model]) -> Vec<u32> {
+        messages.iter().map(|message| message.id).collect()
+    }
+
+    /// 1 ─ 2 ─ 3
+    ///   └ 4 ─ 5
+    ///       └ 6
+    fn tree() -> Vec<message::Model> {
+        vec![
+            db_message(1, None, true),
+            db_message(2, Some(1), false),
+            db_message(3, Some(2), true),
+            db_message(4, Some(1), true),
+            db_message(5, Some(4), false),
+            db_message(6, Some(4), true),
+        ]
+    }
+
+    #[test]
+    fn follows_active_children() {
+        let messages = tree();
+        assert_eq!(active_leaf(&messages), Some(6));
+        assert_eq!(ids(&active_branch(&messages)), vec![1, 4, 6]);
+        assert_eq!(ids(&branch(&messages, Some(3))), vec![1, 2, 3]);
+        assert_eq!(active_leaf(&[]), None);
+        assert!(active_branch(&[]).is_empty());
+    }
+
+    #[test]
+    fn newest_child_without_active_one() {
+        let messages = vec![
+            db_message(1, None, true),
+            db_message(2, Some(1), false),
+            db_message(3, Some(1), false),
+        ];
+        assert_eq!(active_leaf(&messages), Some(3));
+    }
+
+    #[test]
+    fn summaries_of_the_branch() {
+        let summary = |id, until| message::Model {
+            kind: MessageKind::Summary,
+            summary_until: Some(until),
+            ..db_message(id, Some(until), true)
+        };
+        let mut messages = tree();
+        messages.extend([summary(7, 2), summary(8, 4)]);
+        assert_eq!(active_leaf(&messages), Some(6));
+        assert_eq!(ids(&active_branch(&messages)), vec![1, 4, 6, 8]);
+        assert_eq!(ids(&branch(&messages, Some(3))), vec![1, 2, 3, 7]);
+    }
+
+    #[test]
+    fn sibling_ids() {
+        let messages = tree();
+        assert_eq!(siblings(&messages, &messages[4]), vec![5, 6]);
+        assert_eq!(siblings(&messages, &messages[0]), vec![1]);
+    }
+}
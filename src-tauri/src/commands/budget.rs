@@ -0,0 +1,308 @@
+//! Keeps the prompt within the model's context, dropping the oldest turns.
+
+use crate::commands::api::{model_name, Message, Role, Turn};
+use crate::entities::model::{self, ModelKind, Parameters};
+use crate::State;
+use hf_hub::api::tokio::{ApiBuilder, ApiError};
+use hf_hub::Cache;
+use log::{info, warn};
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// Role markers and separators the chat template adds around each message.
+const MESSAGE_OVERHEAD: usize = 4;
+
+/// What a picture takes of the context, vision models encode them into a few
+/// hundred to a few thousand tokens.
+const IMAGE_TOKENS: usize = 1_000;
+
+/// Counts tokens with the model's tokenizer, or estimates them when the model
+/// is not on the hub.
+pub struct TokenCounter {
+    tokenizer: Option<tokenizers::Tokenizer>,
+}
+
+impl TokenCounter {
+    pub fn estimate() -> Self {
+        Self { tokenizer: None }
+    }
+
+    pub fn count(&self, message: &Message) -> usize {
+        let tool_calls: usize = message
+            .tool_calls
+            .iter()
+            .map(|call| self.tokens(&call.name) + self.tokens(&call.arguments))
+            .sum();
+        let images = message.images.len() * IMAGE_TOKENS;
+        self.tokens(&message.content) + tool_calls + images + MESSAGE_OVERHEAD
+    }
+
+    /// Tokens of `text` alone, without the chat template around it.
+    pub fn tokens(&self, text: &str) -> usize {
+        self.tokenizer
+            .as_ref()
+            .and_then(|tokenizer| tokenizer.encode(text, false).ok())
+            .map(|encoding| encoding.len())
+            // Roughly 4 characters per token for english text.
+            .unwrap_or_else(|| text.len().div_ceil(4))
+    }
+}
+
+#[derive(Deserialize)]
+struct Config {
+    max_position_embeddings: Option<usize>,
+}
+
+/// The hub repository the model's weights, tokenizer and config come from.
+fn hub_model_id(model: &model::Model) -> Option<String> {
+    match model.kind {
+        ModelKind::HuggingFace => Some(model_name(&model.endpoint)),
+        // Providers name their models their own way, `gpt-4o`, `llama3`...
+        ModelKind::OpenAi | ModelKind::Tgi => {
+            model.model_name.clone().filter(|name| is_repository(name))
+        }
+        // Ollama tags are not hub repositories.
+        ModelKind::Ollama => None,
+        ModelKind::Local => Some(model.endpoint.clone()),
+    }
+}
+
+/// Whether `name` looks like `org/repo`.
+fn is_repository(name: &str) -> bool {
+    let mut parts = name.split('/');
+    let (Some(org), Some(repo), None) = (parts.next(), parts.next(), parts.next()) else {
+        return false;
+    };
+    !org.is_empty() && !repo.is_empty()
+}
+
+pub(crate) async fn fetch(
+    cache: &Cache,
+    model_id: &str,
+    filename: &str,
+) -> Result<PathBuf, ApiError> {
+    let api = ApiBuilder::new()
+        .with_cache_dir(cache.path().clone())
+        .with_token(cache.token())
+        .build()?;
+    api.model(model_id.to_string()).get(filename).await
+}
+
+/// Like `fetch`, remembering the files which could not be fetched so each
+/// generation does not wait on the hub again.
+async fn fetch_once(state: &State, model_id: &str, filename: &str) -> Option<PathBuf> {
+    let key = (model_id.to_string(), filename.to_string());
+    if state.not_on_hub.lock().await.contains(&key) {
+        return None;
+    }
+    match fetch(&state.cache, model_id, filename).await {
+        Ok(path) => Some(path),
+        Err(err) => {
+            warn!("No {filename} for {model_id}: {err}");
+            state.not_on_hub.lock().await.insert(key);
+            None
+        }
+    }
+}
+
+/// The prompt budget and a way to count tokens against it.
+pub struct Budget {
+    pub counter: TokenCounter,
+    /// `None` when the context length is unknown, nothing is dropped then.
+    pub max_input_tokens: Option<usize>,
+}
+
+impl Budget {
+    /// `parameters.truncate` bounds the prompt when set, otherwise the context
+    /// length from the model's config minus the room left for the reply.
+    pub async fn for_model(state: &State, model: &model::Model, parameters: &Parameters) -> Self {
+        let Some(model_id) = hub_model_id(model) else {
+            return Self {
+                counter: TokenCounter::estimate(),
+                max_input_tokens: parameters.truncate(),
+            };
+        };
+        let tokenizer = fetch_once(state, &model_id, "tokenizer.json")
+            .await
+            .and_then(|path| {
+                tokenizers::Tokenizer::from_file(path)
+                    .map_err(|err| warn!("Invalid tokenizer for {model_id}: {err}"))
+                    .ok()
+            });
+        let max_input_tokens = match parameters.truncate() {
+            Some(truncate) => Some(truncate),
+            None => fetch_once(state, &model_id, "config.json")
+                .await
+                .and_then(|path| std::fs::read_to_string(path).ok())
+                .and_then(|config| serde_json::from_str::<Config>(&config).ok())
+                .and_then(|config| config.max_position_embeddings)
+                .map(|context| context.saturating_sub(parameters.max_new_tokens())),
+        };
+        Self {
+            counter: TokenCounter { tokenizer },
+            max_input_tokens,
+        }
+    }
+
+    /// Splits `turns` into the messages to send and the ids of the db messages
+    /// left out, see `fit_to_budget`.
+    pub fn apply(&self, turns: Vec<Turn>, pinned: usize) -> (Vec<Message>, Vec<u32>) {
+        let Some(budget) = self.max_input_tokens else {
+            return (turns.into_iter().map(|turn| turn.message).collect(), vec![]);
+        };
+        let messages: Vec<Message> = turns.iter().map(|turn| turn.message.clone()).collect();
+        let dropped = fit_to_budget(&messages, pinned, budget, |m| self.counter.count(m));
+        if !dropped.is_empty() {
+            info!("Dropped {} turns to fit in {budget} tokens", dropped.len());
+        }
+        let mut omitted = vec![];
+        let mut kept = vec![];
+        for (i, turn) in turns.into_iter().enumerate() {
+            if dropped.contains(&i) {
+                omitted.extend(turn.ids);
+            } else {
+                kept.push(turn.message);
+            }
+        }
+        (kept, omitted)
+    }
+}
+
+/// Indices of the messages to drop so the rest fits in `budget` tokens. The
+/// first `pinned` messages (the system prompt) and the last one (the user's
+/// question) are always kept, then the most recent turns that fit: history
+/// stays contiguous, nothing is skipped to squeeze in an older turn. Tool
+/// results are not kept without the call they answer.
+pub fn fit_to_budget(
+    messages: &[Message],
+    pinned: usize,
+    budget: usize,
+    count: impl Fn(&Message) -> usize,
+) -> Vec<usize> {
+    if messages.len() <= pinned + 1 {
+        return vec![];
+    }
+    let last = messages.len() - 1;
+    let mut used: usize =
+        messages[..pinned].iter().map(&count).sum::<usize>() + count(&messages[last]);
+    let mut first_kept = last;
+    while first_kept > pinned {
+        let tokens = count(&messages[first_kept - 1]);
+        if used + tokens > budget {
+            break;
+        }
+        used += tokens;
+        first_kept -= 1;
+    }
+    while first_kept < last && messages[first_kept].role == Role::Tool {
+        first_kept += 1;
+    }
+    (pinned..first_kept).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(sizes: &[usize]) -> Vec<Message> {
+        sizes
+            .iter()
+            .enumerate()
+            .map(|(i, &size)| {
+                let role = if i % 2 == 0 {
+                    Role::User
+                } else {
+                    Role::Assistant
+                };
+                Message::new(role, "x".repeat(size))
+            })
+            .collect()
+    }
+
+    fn fit(sizes: &[usize], pinned: usize, budget: usize) -> Vec<usize> {
+        fit_to_budget(&messages(sizes), pinned, budget, |m| m.content.len())
+    }
+
+    #[test]
+    fn everything_fits() {
+        assert_eq!(fit(&[10, 10, 10], 0, 30), Vec::<usize>::new());
+        assert_eq!(fit(&[], 0, 0), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn oldest_turns_are_dropped() {
+        assert_eq!(fit(&[10, 10, 10, 10, 10], 0, 30), vec![0, 1]);
+        assert_eq!(fit(&[10, 10, 10, 10, 10], 0, 39), vec![0, 1]);
+    }
+
+    #[test]
+    fn history_stays_contiguous() {
+        // The small first turn would fit, but not without a gap.
+        assert_eq!(fit(&[1, 50, 10, 10], 0, 25), vec![0, 1]);
+    }
+
+    #[test]
+    fn latest_message_is_always_kept() {
+        assert_eq!(fit(&[10, 10, 100], 0, 50), vec![0, 1]);
+        assert_eq!(fit(&[100], 0, 50), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn pinned_messages_are_kept() {
+        assert_eq!(fit(&[20, 10, 10, 10, 10], 1, 40), vec![1, 2]);
+        assert_eq!(fit(&[20, 10, 10, 10, 10], 1, 10), vec![1, 2, 3]);
+        assert_eq!(fit(&[20, 10], 1, 10), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn tool_results_need_their_call() {
+        let mut messages = messages(&[10, 10, 10, 10, 10]);
+        messages[2].role = Role::Tool;
+        messages[3].role = Role::Tool;
+        // Turns 2 and 3 would fit, not the call they answer.
+        assert_eq!(
+            fit_to_budget(&messages, 0, 30, |m| m.content.len()),
+            vec![0, 1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn pictures_count() {
+        let counter = TokenCounter::estimate();
+        let mut message = Message::new(Role::User, "What is this?".to_string());
+        let text = counter.count(&message);
+        message.images.push(crate::commands::api::InlineImage {
+            mime_type: "image/png".to_string(),
+            data: vec![0; 100],
+        });
+        assert_eq!(counter.count(&message), text + IMAGE_TOKENS);
+    }
+
+    #[test]
+    fn provider_names_are_not_repositories() {
+        assert!(is_repository("meta-llama/Llama-3.2-1B-Instruct"));
+        assert!(!is_repository("gpt-4o"));
+        assert!(!is_repository("llama3"));
+        assert!(!is_repository("models/org/repo"));
+        assert!(!is_repository("/repo"));
+    }
+
+    #[test]
+    fn omitted_ids() {
+        let turns: Vec<Turn> = messages(&[40, 40, 8])
+            .into_iter()
+            .enumerate()
+            .map(|(i, message)| Turn {
+                ids: vec![2 * i as u32, 2 * i as u32 + 1],
+                message,
+            })
+            .collect();
+        let budget = Budget {
+            counter: TokenCounter::estimate(),
+            max_input_tokens: Some(30),
+        };
+        let (kept, omitted) = budget.apply(turns, 0);
+        assert_eq!(kept.len(), 2);
+        assert_eq!(omitted, vec![0, 1]);
+    }
+}
@@ -1,12 +1,17 @@
+use crate::commands::branch;
+use crate::commands::images;
+use crate::entities::attachment;
 use crate::entities::conversation;
-use crate::entities::message;
+use crate::entities::message::{self, Image, Images, MessageKind, Role};
 use crate::entities::model;
+use crate::entities::model::{Parameters, ParametersOverride};
 use crate::entities::user;
 use crate::State;
 use chrono::{DateTime, Utc};
 use log::info;
 use sea_orm::{
     ActiveModelTrait, ActiveValue::Set, ColumnTrait, EntityTrait, PaginatorTrait, QueryFilter,
+    QueryOrder, TransactionTrait,
 };
 use serde::{Deserialize, Serialize};
 
@@ -18,6 +23,18 @@ pub enum Error {
     #[error("Missing model {0}")]
     MissingModel(u32),
 
+    #[error("Missing conversation {0}")]
+    MissingConversation(u32),
+
+    #[error("Missing message {0}")]
+    MissingMessage(u32),
+
+    #[error("Missing attachment {0}")]
+    MissingAttachment(u32),
+
+    #[error(transparent)]
+    Image(#[from] images::Error),
+
     #[error("Db error {0}")]
     DbError(#[from] sea_orm::DbErr),
 }
@@ -118,8 +135,27 @@ pub async fn new_message(
     conversationid: u32,
     content: String,
     authorid: u32,
-) -> Result<(), Error> {
+    images: Option<Vec<Image>>,
+    attachmentids: Option<Vec<u32>>,
+) -> Result<message::Model, Error> {
     let db = &state.db;
+    let images = images.filter(|images| !images.is_empty());
+    if let Some(images) = &images {
+        images::exist(&state.cache, images)?;
+    }
+    let attachmentids = attachmentids.unwrap_or_default();
+    let attachments = attachment::Entity::find()
+        .filter(attachment::Column::Id.is_in(attachmentids.clone()))
+        .filter(attachment::Column::ConversationId.eq(conversationid))
+        .filter(attachment::Column::MessageId.is_null())
+        .all(db)
+        .await?;
+    if let Some(id) = attachmentids
+        .iter()
+        .find(|id| !attachments.iter().any(|attachment| attachment.id == **id))
+    {
+        return Err(Error::MissingAttachment(*id));
+    }
     let user = user::Entity::find_by_id(authorid)
         .one(db)
         .await?
@@ -128,37 +164,151 @@ pub async fn new_message(
         .one(db)
         .await?
         .ok_or(Error::MissingModel(conversationid))?;
-    let has_messages = message::Entity::find()
-        .filter(message::Column::ConversationId.eq(conversation.id))
-        .count(db)
-        .await?;
-    if has_messages == 0 {
+    let messages = branch::load(db, conversation.id).await?;
+    if messages.is_empty() {
         let new_title = content.split(" ").take(5).collect::<Vec<&str>>().join(" ");
         let mut conv: conversation::ActiveModel = conversation.clone().into();
         conv.title = Set(new_title);
         conv.update(db).await.ok();
     }
+    // Models speak as their own user, anyone else is the user.
+    let is_model = model::Entity::find()
+        .filter(model::Column::UserId.eq(user.id))
+        .count(db)
+        .await?
+        > 0;
+    let role = if is_model {
+        Role::Assistant
+    } else {
+        Role::User
+    };
     let now = Utc::now();
     let message = message::ActiveModel {
         conversation_id: Set(conversation.id),
         user_id: Set(user.id),
         content: Set(content),
+        role: Set(role),
         created_at: Set(now.clone()),
         updated_at: Set(now),
+        parent_id: Set(branch::active_leaf(&messages)),
+        images: Set(images.map(Images)),
         ..Default::default()
     };
     let message = message.insert(db).await?;
+    for attachment in attachments {
+        let mut attachment: attachment::ActiveModel = attachment.into();
+        attachment.message_id = Set(Some(message.id));
+        attachment.update(db).await?;
+    }
     info!(
         "Inserted new mesage {:?} Conv: {:?} user {:?}",
         message.id, conversation.id, user.id
     );
+    Ok(message)
+}
+
+/// Sends another version of `messageid` in its place, the conversation goes
+/// on from the new one and the original stays on its own branch.
+#[tauri::command]
+pub async fn edit_message(
+    state: tauri::State<'_, State>,
+    messageid: u32,
+    content: String,
+) -> Result<message::Model, Error> {
+    let db = &state.db;
+    let original = message::Entity::find_by_id(messageid)
+        .one(db)
+        .await?
+        .filter(|message| message.kind == MessageKind::Text)
+        .ok_or(Error::MissingMessage(messageid))?;
+    let txn = db.begin().await?;
+    branch::deactivate_children(&txn, original.conversation_id, original.parent_id).await?;
+    let now = Utc::now();
+    let message = message::ActiveModel {
+        conversation_id: Set(original.conversation_id),
+        user_id: Set(original.user_id),
+        content: Set(content),
+        role: Set(original.role),
+        created_at: Set(now),
+        updated_at: Set(now),
+        parent_id: Set(original.parent_id),
+        // Only the text is edited.
+        images: Set(original.images),
+        ..Default::default()
+    };
+    let message = message.insert(&txn).await?;
+    // The original keeps its documents on its own branch.
+    let attachments = attachment::Entity::find()
+        .filter(attachment::Column::MessageId.eq(original.id))
+        .all(&txn)
+        .await?;
+    for original in attachments {
+        let attachment = attachment::ActiveModel {
+            conversation_id: Set(original.conversation_id),
+            message_id: Set(Some(message.id)),
+            name: Set(original.name),
+            content: Set(original.content),
+            created_at: Set(original.created_at),
+            ..Default::default()
+        };
+        attachment.insert(&txn).await?;
+    }
+    txn.commit().await?;
+    Ok(message)
+}
+
+/// Switches to the branch going through `messageid`, further down the
+/// branch the sibling last used is picked again.
+#[tauri::command]
+pub async fn select_branch(state: tauri::State<'_, State>, messageid: u32) -> Result<(), Error> {
+    let db = &state.db;
+    let message = message::Entity::find_by_id(messageid)
+        .one(db)
+        .await?
+        .ok_or(Error::MissingMessage(messageid))?;
+    let txn = db.begin().await?;
+    branch::deactivate_children(&txn, message.conversation_id, message.parent_id).await?;
+    let mut message: message::ActiveModel = message.into();
+    message.active = Set(true);
+    message.update(&txn).await?;
+    txn.commit().await?;
+    Ok(())
+}
+
+/// Replaces the content of a message, this is how summaries are corrected.
+#[tauri::command]
+pub async fn update_message(
+    state: tauri::State<'_, State>,
+    messageid: u32,
+    content: String,
+) -> Result<(), Error> {
+    let db = &state.db;
+    let message = message::Entity::find_by_id(messageid)
+        .one(db)
+        .await?
+        .ok_or(Error::MissingMessage(messageid))?;
+    let mut message: message::ActiveModel = message.into();
+    message.content = Set(content);
+    message.updated_at = Set(Utc::now());
+    message.update(db).await?;
     Ok(())
 }
 
+/// A message of the branch which has siblings to switch to.
+#[derive(Serialize)]
+pub struct Branch {
+    message_id: u32,
+    siblings: Vec<u32>,
+}
+
 #[derive(Serialize)]
 pub struct ConvData {
+    /// The active branch only.
     messages: Vec<message::Model>,
     users: Vec<user::Model>,
+    branches: Vec<Branch>,
+    /// The documents sent with the messages of the branch.
+    attachments: Vec<attachment::Model>,
 }
 
 #[tauri::command]
@@ -171,17 +321,106 @@ pub async fn get_messages(
         .one(db)
         .await?
         .ok_or(Error::MissingModel(conversationid))?;
-    let messages: Vec<message::Model> = message::Entity::find()
-        .filter(message::Column::ConversationId.eq(conversation.id))
-        .all(db)
-        .await?;
+    let all = branch::load(db, conversation.id).await?;
+    let messages = branch::active_branch(&all);
+    let branches = messages
+        .iter()
+        .filter(|message| message.kind == MessageKind::Text)
+        .map(|message| Branch {
+            message_id: message.id,
+            siblings: branch::siblings(&all, message),
+        })
+        .filter(|branch| branch.siblings.len() > 1)
+        .collect();
     // TODO Get only the users from the conversation.
     // Add a link table
     let users: Vec<user::Model> = user::Entity::find().all(db).await?;
+    let ids: Vec<u32> = messages.iter().map(|message| message.id).collect();
+    let attachments = attachment::Entity::find()
+        .filter(attachment::Column::MessageId.is_in(ids))
+        .order_by_asc(attachment::Column::Id)
+        .all(db)
+        .await?;
     info!(
         "Got {} messages for conv {}",
         messages.len(),
         conversation.id
     );
-    Ok(ConvData { messages, users })
+    Ok(ConvData {
+        messages,
+        users,
+        branches,
+        attachments,
+    })
+}
+
+/// Overrides the model's generation parameters for this conversation only.
+#[tauri::command]
+pub async fn update_conversation_parameters(
+    state: tauri::State<'_, State>,
+    conversationid: u32,
+    parameters: ParametersOverride,
+) -> Result<(), Error> {
+    let db = &state.db;
+    let conversation = conversation::Entity::find_by_id(conversationid)
+        .one(db)
+        .await?
+        .ok_or(Error::MissingConversation(conversationid))?;
+    let mut conversation: conversation::ActiveModel = conversation.into();
+    conversation.parameters = Set(Some(parameters));
+    conversation.updated_at = Set(Utc::now());
+    conversation.update(db).await?;
+    Ok(())
+}
+
+/// What the settings panel edits, with the model's values the conversation
+/// falls back to.
+#[derive(Serialize)]
+pub struct ConversationSettings {
+    model_id: u32,
+    system_prompt: Option<String>,
+    parameters: ParametersOverride,
+    model_system_prompt: Option<String>,
+    model_parameters: Parameters,
+}
+
+#[tauri::command]
+pub async fn get_conversation_settings(
+    state: tauri::State<'_, State>,
+    conversationid: u32,
+) -> Result<ConversationSettings, Error> {
+    let db = &state.db;
+    let (conversation, model) = conversation::Entity::find_by_id(conversationid)
+        .find_also_related(model::Entity)
+        .one(db)
+        .await?
+        .ok_or(Error::MissingConversation(conversationid))?;
+    let model = model.ok_or(Error::MissingModel(conversation.model_id))?;
+    Ok(ConversationSettings {
+        model_id: model.id,
+        system_prompt: conversation.system_prompt,
+        parameters: conversation.parameters.unwrap_or_default(),
+        model_system_prompt: model.system_prompt,
+        model_parameters: model.parameters,
+    })
+}
+
+/// Replaces the model's system prompt for this conversation only, an empty
+/// prompt goes back to the model's.
+#[tauri::command]
+pub async fn update_conversation_system_prompt(
+    state: tauri::State<'_, State>,
+    conversationid: u32,
+    systemprompt: Option<String>,
+) -> Result<(), Error> {
+    let db = &state.db;
+    let conversation = conversation::Entity::find_by_id(conversationid)
+        .one(db)
+        .await?
+        .ok_or(Error::MissingConversation(conversationid))?;
+    let mut conversation: conversation::ActiveModel = conversation.into();
+    conversation.system_prompt = Set(systemprompt.filter(|prompt| !prompt.trim().is_empty()));
+    conversation.updated_at = Set(Utc::now());
+    conversation.update(db).await?;
+    Ok(())
 }
@@ -0,0 +1,175 @@
+//! Files sent with a message: their text is extracted once when attached,
+//! then added to the message in every prompt, cut to fit the budget.
+
+use crate::commands::api::Turn;
+use crate::commands::budget::Budget;
+use crate::commands::tools::cut;
+use crate::entities::attachment;
+use log::info;
+use std::collections::HashMap;
+
+/// Documents together take at most this share of the prompt budget, the
+/// conversation needs the rest.
+const DOCUMENTS_SHARE: usize = 2;
+
+/// The cut when the budget is unknown, so one document cannot take it all.
+const MAX_DOCUMENT_CHARS: usize = 32_000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0} is not a text file or a pdf")]
+    Unsupported(String),
+
+    #[error("Could not read the text of {name}: {message}")]
+    Pdf { name: String, message: String },
+}
+
+/// The text of the file `name`. Pdfs are extracted, anything else has to
+/// be utf-8 text: markdown, source code, csv...
+pub fn extract(name: &str, data: &[u8]) -> Result<String, Error> {
+    let is_pdf = name.to_lowercase().ends_with(".pdf") || data.starts_with(b"%PDF-");
+    let text = if is_pdf {
+        pdf_extract::extract_text_from_mem(data).map_err(|err| Error::Pdf {
+            name: name.to_string(),
+            message: err.to_string(),
+        })?
+    } else {
+        let text = std::str::from_utf8(data).map_err(|_| Error::Unsupported(name.to_string()))?;
+        text.trim_start_matches('\u{feff}').replace("\r\n", "\n")
+    };
+    Ok(text)
+}
+
+fn document(name: &str, text: &str) -> String {
+    format!("<document name=\"{name}\">\n{text}\n</document>")
+}
+
+/// Appends the documents sent with the messages of each turn to its text.
+/// Each gets an equal share of the documents' budget, longer ones are cut
+/// with a note.
+pub fn inline(budget: &Budget, turns: &mut [Turn], attachments: &[attachment::Model]) {
+    let ids: Vec<u32> = turns.iter().flat_map(|turn| turn.ids.clone()).collect();
+    let mut documents: HashMap<u32, Vec<&attachment::Model>> = HashMap::new();
+    for attachment in attachments {
+        if let Some(message_id) = attachment.message_id.filter(|id| ids.contains(id)) {
+            documents.entry(message_id).or_default().push(attachment);
+        }
+    }
+    let count: usize = documents.values().map(Vec::len).sum();
+    if count == 0 {
+        return;
+    }
+    let share = budget
+        .max_input_tokens
+        .map(|tokens| tokens / DOCUMENTS_SHARE / count);
+    for turn in turns {
+        for id in &turn.ids {
+            for attachment in documents.get(id).into_iter().flatten() {
+                let text = match share {
+                    Some(share) => {
+                        let tokens = budget.counter.tokens(&attachment.content);
+                        if tokens > share {
+                            info!("Cutting {} to {share} tokens", attachment.name);
+                        }
+                        // Tokens are not evenly spread, near enough.
+                        let chars = attachment.content.chars().count() * share / tokens.max(1);
+                        cut(&attachment.content, chars)
+                    }
+                    None => cut(&attachment.content, MAX_DOCUMENT_CHARS),
+                };
+                let content = &mut turn.message.content;
+                if !content.is_empty() {
+                    content.push_str("\n\n");
+                }
+                content.push_str(&document(&attachment.name, &text));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::api::{Message, Role};
+    use crate::commands::budget::TokenCounter;
+
+    fn attachment(
+        id: u32,
+        message_id: Option<u32>,
+        name: &str,
+        content: &str,
+    ) -> attachment::Model {
+        attachment::Model {
+            id,
+            conversation_id: 1,
+            message_id,
+            name: name.to_string(),
+            content: content.to_string(),
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    fn turn(id: u32, content: &str) -> Turn {
+        Turn {
+            ids: vec![id],
+            message: Message::new(Role::User, content.to_string()),
+        }
+    }
+
+    #[test]
+    fn text_files() {
+        assert_eq!(
+            extract("data.csv", b"\xef\xbb\xbfa,b\r\n1,2\r\n").unwrap(),
+            "a,b\n1,2\n"
+        );
+        assert_eq!(extract("main.rs", b"fn main() {}").unwrap(), "fn main() {}");
+        assert!(matches!(
+            extract("photo.jpg", b"\xff\xd8\xff\xe0\x00"),
+            Err(Error::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn documents_follow_their_message() {
+        let budget = Budget {
+            counter: TokenCounter::estimate(),
+            max_input_tokens: None,
+        };
+        let mut turns = vec![turn(1, "Summarize this"), turn(2, "Thanks")];
+        let attachments = [
+            attachment(1, Some(1), "notes.md", "# Notes"),
+            // Not sent with a message, only the tool reads it.
+            attachment(2, None, "other.md", "Other"),
+        ];
+        inline(&budget, &mut turns, &attachments);
+        assert_eq!(
+            turns[0].message.content,
+            "Summarize this\n\n<document name=\"notes.md\">\n# Notes\n</document>"
+        );
+        assert_eq!(turns[1].message.content, "Thanks");
+    }
+
+    #[test]
+    fn documents_share_the_budget() {
+        let budget = Budget {
+            counter: TokenCounter::estimate(),
+            max_input_tokens: Some(40),
+        };
+        let mut turns = vec![turn(1, "")];
+        // 80 characters are 20 estimated tokens, two documents get 10 each.
+        let long = "a".repeat(80);
+        let attachments = [
+            attachment(1, Some(1), "a.txt", &long),
+            attachment(2, Some(1), "b.txt", "short"),
+        ];
+        inline(&budget, &mut turns, &attachments);
+        assert_eq!(
+            turns[0].message.content,
+            format!(
+                "<document name=\"a.txt\">\n{}\n[40 more characters left out]\n</document>\n\n\
+                 <document name=\"b.txt\">\nshort\n</document>",
+                "a".repeat(40)
+            )
+        );
+    }
+}
@@ -0,0 +1,98 @@
+//! A small sentence embedding model run on the cpu, for the knowledge base.
+//! Its files come from the hub through the app's cache like any other model.
+
+use crate::commands::budget::fetch;
+use candle_core::{Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+use hf_hub::api::tokio::ApiError;
+use hf_hub::Cache;
+use log::info;
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+
+/// Fast on a cpu and good enough for english prose and code.
+pub const EMBEDDING_MODEL: &str = "sentence-transformers/all-MiniLM-L6-v2";
+
+/// Longer texts are cut, the model was trained on short ones.
+const MAX_TOKENS: usize = 256;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Could not fetch the embedding model: {0}")]
+    Fetch(#[from] ApiError),
+
+    #[error("Invalid embedding model config: {0}")]
+    Config(#[from] serde_json::Error),
+
+    #[error("Invalid embedding tokenizer: {0}")]
+    Tokenizer(String),
+
+    #[error("Embedding failed: {0}")]
+    Candle(#[from] candle_core::Error),
+
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
+pub struct Embedder {
+    model: BertModel,
+    tokenizer: Tokenizer,
+    device: Device,
+}
+
+impl Embedder {
+    pub async fn load(cache: &Cache) -> Result<Self, Error> {
+        let config = fetch(cache, EMBEDDING_MODEL, "config.json").await?;
+        let tokenizer = fetch(cache, EMBEDDING_MODEL, "tokenizer.json").await?;
+        let weights = fetch(cache, EMBEDDING_MODEL, "model.safetensors").await?;
+        info!("Loading {EMBEDDING_MODEL}");
+        let config: Config = serde_json::from_str(&std::fs::read_to_string(config)?)?;
+        let mut tokenizer =
+            Tokenizer::from_file(tokenizer).map_err(|err| Error::Tokenizer(err.to_string()))?;
+        tokenizer
+            .with_padding(Some(PaddingParams::default()))
+            .with_truncation(Some(TruncationParams {
+                max_length: MAX_TOKENS,
+                ..Default::default()
+            }))
+            .map_err(|err| Error::Tokenizer(err.to_string()))?;
+        let device = Device::Cpu;
+        // Safety: the file is in our cache and not modified while mapped.
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights], DTYPE, &device)? };
+        let model = BertModel::load(vb, &config)?;
+        Ok(Self {
+            model,
+            tokenizer,
+            device,
+        })
+    }
+
+    /// One normalized vector per text, the mean of its tokens'. This blocks,
+    /// call it from `spawn_blocking`.
+    pub fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, Error> {
+        if texts.is_empty() {
+            return Ok(vec![]);
+        }
+        let encodings = self
+            .tokenizer
+            .encode_batch(texts.to_vec(), true)
+            .map_err(|err| Error::Tokenizer(err.to_string()))?;
+        let tensor = |values: Vec<&[u32]>| -> Result<Tensor, Error> {
+            let rows = values
+                .into_iter()
+                .map(|row| Tensor::new(row, &self.device))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Tensor::stack(&rows, 0)?)
+        };
+        let ids = tensor(encodings.iter().map(|e| e.get_ids()).collect())?;
+        let mask = tensor(encodings.iter().map(|e| e.get_attention_mask()).collect())?;
+        let token_types = ids.zeros_like()?;
+        let hidden = self.model.forward(&ids, &token_types, Some(&mask))?;
+        // Padding is left out of the mean.
+        let mask = mask.to_dtype(DTYPE)?.unsqueeze(2)?;
+        let sum = hidden.broadcast_mul(&mask)?.sum(1)?;
+        let mean = sum.broadcast_div(&mask.sum(1)?)?;
+        let norm = mean.sqr()?.sum_keepdim(1)?.sqrt()?;
+        Ok(mean.broadcast_div(&norm)?.to_vec2::<f32>()?)
+    }
+}
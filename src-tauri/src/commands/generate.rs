@@ -0,0 +1,772 @@
+use crate::commands::api::{Error, ErrorKind, Message, Usage};
+use crate::commands::approval::{self, Approval};
+use crate::commands::backend::load_backend;
+use crate::commands::branch;
+use crate::commands::budget::Budget;
+use crate::commands::documents;
+use crate::commands::images;
+use crate::commands::knowledge;
+use crate::commands::mcp;
+use crate::commands::reasoning::Piece;
+use crate::commands::summary;
+use crate::commands::tools::{Registry, ToolContext};
+use crate::entities::message::{Metrics, Reasoning, Role, Sources, ToolCall, ToolCalls};
+use crate::entities::model::{ModelKind, Parameters};
+use crate::entities::{attachment, conversation, message, model};
+use crate::State;
+use chrono::Utc;
+use log::{error, info, warn};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    QueryOrder, TransactionTrait,
+};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tauri::ipc::Channel;
+use tauri::{AppHandle, Manager};
+use tokio::sync::oneshot;
+
+/// How often the partial reply is written back to the db while streaming.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many times in a row the model may call tools, it has to answer after.
+const MAX_TOOL_ROUNDS: usize = 5;
+
+/// What the model reads instead of the result of a call the user rejected.
+const REJECTED: &str = "The user rejected this tool call, do not try it again.";
+
+/// What the model reads for the calls left when the generation was stopped.
+const CANCELLED: &str = "The user stopped the generation before this tool call ran.";
+
+/// Frames pushed to the webview while a reply is being generated. `Summary`
+/// is a new summary of the older turns, `Context` lists the messages left out
+/// of the prompt to fit the model's context, `Waiting` means the endpoint is
+/// not ready yet and is retried in `seconds`. A reply calling tools is
+/// followed by `ToolCalls`, a `ToolResult` per call, then the next reply.
+/// Calls which need the user's approval are announced by a `ToolCallRequest`
+/// first and wait for `resolve_tool_call`. `Start` carries the knowledge base
+/// excerpts the reply was given. A reasoning model's thoughts stream as
+/// `Reasoning` before the answer's `Delta`s, `Thought` tells how long it
+/// thought once the answer starts.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase", tag = "event", content = "data")]
+pub enum GenerationEvent {
+    Summarizing,
+    Summary {
+        message: message::Model,
+    },
+    Context {
+        omitted: Vec<u32>,
+    },
+    Waiting {
+        kind: ErrorKind,
+        seconds: u64,
+    },
+    Start {
+        message_id: u32,
+        parent_id: Option<u32>,
+        sources: Option<Sources>,
+    },
+    Delta {
+        content: String,
+    },
+    Reasoning {
+        content: String,
+    },
+    Thought {
+        duration_ms: u64,
+    },
+    ToolCalls {
+        message_id: u32,
+        tool_calls: Vec<ToolCall>,
+    },
+    ToolCallRequest {
+        message_id: u32,
+        tool_call: ToolCall,
+    },
+    ToolResult {
+        message: message::Model,
+    },
+    Done {
+        truncated: bool,
+        metrics: Option<Metrics>,
+    },
+    Error {
+        kind: ErrorKind,
+        message: String,
+    },
+}
+
+/// The assistant message being streamed, lazily inserted on the first delta.
+struct Reply {
+    conversation_id: u32,
+    user_id: u32,
+    /// The message answered, the last one of the branch sent as context.
+    parent_id: Option<u32>,
+    message: Option<message::Model>,
+    content: String,
+    /// The model's thoughts, apart from the answer.
+    reasoning: String,
+    /// When the first thought arrived.
+    thinking_since: Option<Instant>,
+    /// Set once the answer started, or the stream ended.
+    reasoning_ms: Option<u64>,
+    truncated: bool,
+    flushed: Instant,
+    /// When the first delta arrived.
+    first_token: Option<Instant>,
+    metrics: Option<Metrics>,
+    tool_calls: Option<ToolCalls>,
+    sources: Option<Sources>,
+}
+
+impl Reply {
+    fn new(conversation_id: u32, user_id: u32, parent_id: Option<u32>) -> Self {
+        Self {
+            conversation_id,
+            user_id,
+            parent_id,
+            message: None,
+            content: String::new(),
+            reasoning: String::new(),
+            thinking_since: None,
+            reasoning_ms: None,
+            truncated: false,
+            flushed: Instant::now(),
+            first_token: None,
+            metrics: None,
+            tool_calls: None,
+            sources: None,
+        }
+    }
+
+    /// Appends a piece, returns the message id when the message was just created.
+    async fn push(&mut self, db: &DatabaseConnection, piece: &Piece) -> Result<Option<u32>, Error> {
+        if !piece.reasoning.is_empty() && self.thinking_since.is_none() {
+            self.thinking_since = Some(Instant::now());
+        }
+        self.content.push_str(&piece.content);
+        self.reasoning.push_str(&piece.reasoning);
+        if self.message.is_none() {
+            self.first_token = Some(Instant::now());
+            self.insert(db).await.map(Some)
+        } else {
+            if self.flushed.elapsed() > FLUSH_INTERVAL {
+                self.save(db).await?;
+            }
+            Ok(None)
+        }
+    }
+
+    /// Stops the thinking clock, returns how long the model thought when it
+    /// just stopped.
+    fn end_thoughts(&mut self) -> Option<u64> {
+        if self.reasoning_ms.is_some() {
+            return None;
+        }
+        let since = self.thinking_since?;
+        let duration_ms = since.elapsed().as_millis() as u64;
+        self.reasoning_ms = Some(duration_ms);
+        Some(duration_ms)
+    }
+
+    fn reasoning(&self) -> Option<Reasoning> {
+        let since = self.thinking_since?;
+        Some(Reasoning {
+            content: self.reasoning.clone(),
+            duration_ms: self
+                .reasoning_ms
+                .unwrap_or_else(|| since.elapsed().as_millis() as u64),
+        })
+    }
+
+    async fn insert(&mut self, db: &DatabaseConnection) -> Result<u32, Error> {
+        // The previous replies are only replaced once there is a new one.
+        let txn = db.begin().await?;
+        branch::deactivate_children(&txn, self.conversation_id, self.parent_id).await?;
+        let now = Utc::now();
+        let message = message::ActiveModel {
+            conversation_id: Set(self.conversation_id),
+            user_id: Set(self.user_id),
+            content: Set(self.content.clone()),
+            role: Set(Role::Assistant),
+            parent_id: Set(self.parent_id),
+            created_at: Set(now),
+            updated_at: Set(now),
+            tool_calls: Set(self.tool_calls.clone()),
+            sources: Set(self.sources.clone()),
+            reasoning: Set(self.reasoning()),
+            ..Default::default()
+        };
+        let message = message.insert(&txn).await?;
+        txn.commit().await?;
+        let id = message.id;
+        self.message = Some(message);
+        self.flushed = Instant::now();
+        Ok(id)
+    }
+
+    async fn save(&mut self, db: &DatabaseConnection) -> Result<(), Error> {
+        if let Some(message) = self.message.take() {
+            let mut message: message::ActiveModel = message.into();
+            message.content = Set(self.content.clone());
+            message.truncated = Set(self.truncated);
+            message.metrics = Set(self.metrics.clone());
+            message.tool_calls = Set(self.tool_calls.clone());
+            message.reasoning = Set(self.reasoning());
+            message.updated_at = Set(Utc::now());
+            self.message = Some(message.update(db).await?);
+        }
+        self.flushed = Instant::now();
+        Ok(())
+    }
+}
+
+/// The metrics of a reply whose first token came after `ttft` and the rest
+/// over `streaming`. `estimate` stands in for the backend's counts.
+fn metrics(usage: Option<Usage>, estimate: Usage, ttft: Duration, streaming: Duration) -> Metrics {
+    let estimated = usage.is_none();
+    let usage = usage.unwrap_or(estimate);
+    let seconds = streaming.as_secs_f32();
+    Metrics {
+        prompt_tokens: usage.prompt_tokens,
+        completion_tokens: usage.completion_tokens,
+        ttft_ms: ttft.as_millis() as u64,
+        tokens_per_second: if seconds > 0.0 {
+            usage.completion_tokens as f32 / seconds
+        } else {
+            0.0
+        },
+        estimated,
+    }
+}
+
+/// Asks the user whether `call` of message `message_id` may run, see
+/// `approval::request`.
+async fn ask_approval(
+    state: &State,
+    channel: &Channel<GenerationEvent>,
+    conversation_id: u32,
+    message_id: u32,
+    call: &ToolCall,
+    cancel: &mut oneshot::Receiver<()>,
+) -> Option<Approval> {
+    let notify = || {
+        channel
+            .send(GenerationEvent::ToolCallRequest {
+                message_id,
+                tool_call: call.clone(),
+            })
+            .is_ok()
+    };
+    approval::request(
+        &state.approvals,
+        conversation_id,
+        message_id,
+        call,
+        notify,
+        cancel,
+        approval::APPROVAL_TIMEOUT,
+    )
+    .await
+}
+
+/// Stores the result of the call `call_id`, after `parent_id`.
+async fn insert_tool_result(
+    db: &DatabaseConnection,
+    conversation_id: u32,
+    user_id: u32,
+    parent_id: Option<u32>,
+    call_id: String,
+    content: String,
+) -> Result<message::Model, Error> {
+    let now = Utc::now();
+    let result = message::ActiveModel {
+        conversation_id: Set(conversation_id),
+        user_id: Set(user_id),
+        content: Set(content),
+        role: Set(Role::Tool),
+        parent_id: Set(parent_id),
+        tool_call_id: Set(Some(call_id)),
+        created_at: Set(now),
+        updated_at: Set(now),
+        ..Default::default()
+    };
+    Ok(result.insert(db).await?)
+}
+
+/// Answers the calls left when the generation was stopped, every call sent
+/// back to the model needs its result.
+async fn cancel_tool_calls(
+    db: &DatabaseConnection,
+    channel: &Channel<GenerationEvent>,
+    conversation_id: u32,
+    user_id: u32,
+    mut parent_id: Option<u32>,
+    calls: impl Iterator<Item = ToolCall>,
+) -> Result<(), Error> {
+    for call in calls {
+        let content = CANCELLED.to_string();
+        let result =
+            insert_tool_result(db, conversation_id, user_id, parent_id, call.id, content).await?;
+        parent_id = Some(result.id);
+        channel
+            .send(GenerationEvent::ToolResult { message: result })
+            .ok();
+    }
+    Ok(())
+}
+
+/// Streams the reply to `parent_id` into the db, returns whether it was
+/// cancelled midway and the reply's metrics. Cancelling before the reply
+/// started is not a truncated reply, there is none. Only the branch leading to
+/// `parent_id` is sent. When the model calls tools, their results are stored
+/// after its message and the model is asked again, up to `MAX_TOOL_ROUNDS`.
+async fn generate(
+    app: &AppHandle,
+    conversation: conversation::Model,
+    model: model::Model,
+    parameters: Parameters,
+    parent_id: Option<u32>,
+    channel: &Channel<GenerationEvent>,
+    mut cancel: oneshot::Receiver<()>,
+) -> Result<(bool, Option<Metrics>), Error> {
+    let conversationid = conversation.id;
+    let state = app.state::<State>();
+    let db = &state.db;
+    let backend = load_backend(db, &state.cache, &model).await?;
+    let mut messages = branch::branch(&branch::load(db, conversationid).await?, parent_id);
+    let budget = Budget::for_model(&state, &model, &parameters).await;
+    if let Some((turns, until)) = summary::due(&messages, &parameters, &budget.counter) {
+        channel.send(GenerationEvent::Summarizing).ok();
+        let create = summary::create(&state, conversationid, &model, &parameters, &turns, until);
+        let summary = tokio::select! {
+            summary = create => summary,
+            _ = &mut cancel => return Ok((false, None)),
+        };
+        match summary {
+            Ok(summary) => {
+                channel
+                    .send(GenerationEvent::Summary {
+                        message: summary.clone(),
+                    })
+                    .ok();
+                messages.push(summary);
+            }
+            // The budget still applies, older turns are dropped instead.
+            Err(err) => warn!("Could not summarize conversation {conversationid}: {err}"),
+        }
+    }
+    // The conversation's system prompt replaces the model's.
+    let system_prompt = conversation.system_prompt.or(model.system_prompt.clone());
+    let tools = if parameters.tools {
+        mcp::with_servers(&state, Registry::builtin()).await
+    } else {
+        Registry::default()
+    };
+    let context = ToolContext {
+        db,
+        conversation_id: conversationid,
+    };
+    let attachments = attachment::Entity::find()
+        .filter(attachment::Column::ConversationId.eq(conversationid))
+        .filter(attachment::Column::MessageId.is_not_null())
+        .order_by_asc(attachment::Column::Id)
+        .all(db)
+        .await?;
+    // The knowledge base is searched once, for the user's last message.
+    let question = messages
+        .iter()
+        .rev()
+        .find(|message| message.role == Role::User);
+    let retrieved = match question {
+        Some(question) => {
+            let retrieve = knowledge::retrieve(&state, conversationid, &question.content);
+            let retrieved = tokio::select! {
+                retrieved = retrieve => retrieved,
+                _ = &mut cancel => return Ok((false, None)),
+            };
+            retrieved.unwrap_or_else(|err| {
+                warn!("Could not search the knowledge base: {err}");
+                vec![]
+            })
+        }
+        None => vec![],
+    };
+    let question_id = question.map(|question| question.id);
+    let sources = (!retrieved.is_empty()).then(|| knowledge::sources(&retrieved));
+    let mut parent_id = parent_id;
+    let mut round = 0;
+    loop {
+        let mut turns = Message::from_db(messages.clone(), system_prompt.clone());
+        images::inline(&state.cache, &mut turns, &messages);
+        documents::inline(&budget, &mut turns, &attachments);
+        if let Some(question_id) = question_id {
+            knowledge::inline(&mut turns, question_id, &retrieved);
+        }
+        // System messages are never dropped.
+        let pinned = turns
+            .iter()
+            .take_while(|turn| turn.message.role == Role::System)
+            .count();
+        let (prompt, omitted) = budget.apply(turns, pinned);
+        channel.send(GenerationEvent::Context { omitted }).ok();
+        let mut on_wait = |err: &Error, delay: Duration| {
+            let kind = err.kind();
+            let seconds = delay.as_secs_f64().ceil() as u64;
+            channel
+                .send(GenerationEvent::Waiting { kind, seconds })
+                .ok();
+        };
+        // The last round has to answer.
+        let definitions = if round < MAX_TOOL_ROUNDS {
+            tools.definitions()
+        } else {
+            vec![]
+        };
+        let prompt_tokens = prompt.iter().map(|m| budget.counter.count(m)).sum();
+        let started = Instant::now();
+        let mut stream = tokio::select! {
+            stream = backend.stream(prompt, &definitions, &parameters, &mut on_wait) => stream?,
+            _ = &mut cancel => return Ok((false, None)),
+        };
+        let mut usage = None;
+        let mut tool_calls = vec![];
+        let mut reply = Reply::new(conversationid, model.user_id, parent_id);
+        reply.sources = sources.clone();
+        loop {
+            let next = tokio::select! {
+                next = stream.next() => Some(next),
+                _ = &mut cancel => None,
+            };
+            let Some(next) = next else {
+                info!("Cancelled generation for conversation {conversationid}");
+                stream.cancel().await;
+                reply.truncated = true;
+                break;
+            };
+            match next {
+                Ok(Some(piece)) => {
+                    if piece.is_empty() {
+                        continue;
+                    }
+                    if !piece.content.is_empty() {
+                        if let Some(duration_ms) = reply.end_thoughts() {
+                            channel.send(GenerationEvent::Thought { duration_ms }).ok();
+                        }
+                    }
+                    if let Some(message_id) = reply.push(db, &piece).await? {
+                        channel
+                            .send(GenerationEvent::Start {
+                                message_id,
+                                parent_id,
+                                sources: sources.clone(),
+                            })
+                            .ok();
+                    }
+                    if !piece.reasoning.is_empty() {
+                        let content = piece.reasoning;
+                        channel.send(GenerationEvent::Reasoning { content }).ok();
+                    }
+                    if !piece.content.is_empty() {
+                        let content = piece.content;
+                        channel.send(GenerationEvent::Delta { content }).ok();
+                    }
+                }
+                Ok(None) => {
+                    usage = stream.usage();
+                    tool_calls = stream.tool_calls();
+                    break;
+                }
+                Err(err) => {
+                    // Keep whatever was already produced.
+                    reply.end_thoughts();
+                    reply.truncated = true;
+                    reply.save(db).await?;
+                    return Err(err);
+                }
+            }
+        }
+        if let Some(duration_ms) = reply.end_thoughts() {
+            // Only thoughts, no answer.
+            channel.send(GenerationEvent::Thought { duration_ms }).ok();
+        }
+        if !tool_calls.is_empty() && reply.first_token.is_none() {
+            // Nothing was streamed, the calls came in one piece at the end.
+            reply.first_token = Some(Instant::now());
+        }
+        reply.metrics = reply.first_token.map(|first_token| {
+            let estimate = Usage {
+                prompt_tokens,
+                completion_tokens: budget.counter.tokens(&reply.content)
+                    + budget.counter.tokens(&reply.reasoning),
+            };
+            metrics(
+                usage,
+                estimate,
+                first_token - started,
+                first_token.elapsed(),
+            )
+        });
+        if tool_calls.is_empty() || round == MAX_TOOL_ROUNDS {
+            reply.save(db).await?;
+            return Ok((reply.truncated, reply.metrics));
+        }
+        reply.tool_calls = Some(ToolCalls(tool_calls.clone()));
+        let message_id = match reply.message.as_ref() {
+            Some(message) => {
+                let id = message.id;
+                reply.save(db).await?;
+                id
+            }
+            None => {
+                let id = reply.insert(db).await?;
+                channel
+                    .send(GenerationEvent::Start {
+                        message_id: id,
+                        parent_id,
+                        sources: sources.clone(),
+                    })
+                    .ok();
+                id
+            }
+        };
+        channel
+            .send(GenerationEvent::ToolCalls {
+                message_id,
+                tool_calls: tool_calls.clone(),
+            })
+            .ok();
+        parent_id = Some(message_id);
+        let mut calls = tool_calls.into_iter();
+        while let Some(mut call) = calls.next() {
+            let approved = if approval::is_allowed(db, &call.name).await? {
+                true
+            } else {
+                let approval = ask_approval(
+                    &state,
+                    channel,
+                    conversationid,
+                    message_id,
+                    &call,
+                    &mut cancel,
+                )
+                .await;
+                match approval {
+                    None => {
+                        let calls = std::iter::once(call).chain(calls);
+                        let user_id = model.user_id;
+                        cancel_tool_calls(db, channel, conversationid, user_id, parent_id, calls)
+                            .await?;
+                        return Ok((true, reply.metrics));
+                    }
+                    Some(Approval::Reject) => false,
+                    Some(Approval::Approve { arguments, always }) => {
+                        if always {
+                            approval::set_allowed(db, &call.name, true).await?;
+                        }
+                        if arguments != call.arguments {
+                            // The stored call is the one which ran.
+                            call.arguments = arguments;
+                            if let Some(ToolCalls(calls)) = reply.tool_calls.as_mut() {
+                                if let Some(stored) = calls.iter_mut().find(|c| c.id == call.id) {
+                                    stored.arguments = call.arguments.clone();
+                                }
+                                channel
+                                    .send(GenerationEvent::ToolCalls {
+                                        message_id,
+                                        tool_calls: calls.clone(),
+                                    })
+                                    .ok();
+                            }
+                            reply.save(db).await?;
+                        }
+                        true
+                    }
+                }
+            };
+            let content = if approved {
+                tokio::select! {
+                    content = tools.call(&context, &call) => Some(content),
+                    _ = &mut cancel => None,
+                }
+            } else {
+                info!("Tool call {} rejected", call.id);
+                Some(REJECTED.to_string())
+            };
+            let user_id = model.user_id;
+            let Some(content) = content else {
+                let calls = std::iter::once(call).chain(calls);
+                cancel_tool_calls(db, channel, conversationid, user_id, parent_id, calls).await?;
+                return Ok((true, reply.metrics));
+            };
+            let result =
+                insert_tool_result(db, conversationid, user_id, parent_id, call.id, content)
+                    .await?;
+            parent_id = Some(result.id);
+            channel
+                .send(GenerationEvent::ToolResult { message: result })
+                .ok();
+        }
+        messages = branch::branch(&branch::load(db, conversationid).await?, parent_id);
+        round += 1;
+    }
+}
+
+/// Starts generating the model's reply to `conversationid` in the background,
+/// the reply is pushed through `channel` as it streams in.
+#[tauri::command]
+pub async fn start_generation(
+    app: AppHandle,
+    state: tauri::State<'_, State>,
+    conversationid: u32,
+    channel: Channel<GenerationEvent>,
+) -> Result<(), Error> {
+    let messages = branch::load(&state.db, conversationid).await?;
+    let parent_id = branch::active_leaf(&messages);
+    spawn_generation(app, &state, conversationid, parent_id, channel).await
+}
+
+/// Generates another answer to the message the last reply answers, the
+/// previous reply, tool calls included, is kept as a sibling of the new one.
+#[tauri::command]
+pub async fn regenerate(
+    app: AppHandle,
+    state: tauri::State<'_, State>,
+    conversationid: u32,
+    channel: Channel<GenerationEvent>,
+) -> Result<(), Error> {
+    let messages = branch::load(&state.db, conversationid).await?;
+    let find = |id: Option<u32>| messages.iter().find(|message| Some(message.id) == id);
+    let leaf = branch::active_leaf(&messages);
+    let mut parent_id = leaf;
+    if let Some(last) = find(leaf).filter(|last| last.role == Role::Assistant) {
+        parent_id = last.parent_id;
+        // Along with the tool calls it was made from.
+        while let Some(step) =
+            find(parent_id).filter(|step| step.role == Role::Tool || step.tool_calls.is_some())
+        {
+            parent_id = step.parent_id;
+        }
+    }
+    spawn_generation(app, &state, conversationid, parent_id, channel).await
+}
+
+async fn spawn_generation(
+    app: AppHandle,
+    state: &State,
+    conversationid: u32,
+    parent_id: Option<u32>,
+    channel: Channel<GenerationEvent>,
+) -> Result<(), Error> {
+    let db = &state.db;
+    let (conversation, model): (conversation::Model, Option<model::Model>) =
+        conversation::Entity::find_by_id(conversationid)
+            .find_also_related(model::Entity)
+            .one(db)
+            .await?
+            .ok_or(Error::MissingConversation(conversationid))?;
+    let model = model.expect("Associated model");
+    let parameters = model
+        .parameters
+        .with_overrides(&conversation.parameters.clone().unwrap_or_default());
+
+    // The lock is held until the task is registered, so a fast task cannot
+    // remove itself before being inserted.
+    let mut generations = state.generations.lock().await;
+    if generations.contains_key(&conversationid) {
+        return Err(Error::AlreadyGenerating(conversationid));
+    }
+    let (tx, rx) = oneshot::channel();
+    tauri::async_runtime::spawn(async move {
+        info!("Start generation for conversation {conversationid}");
+        let kind = model.kind;
+        let result = generate(
+            &app,
+            conversation,
+            model,
+            parameters,
+            parent_id,
+            &channel,
+            rx,
+        )
+        .await;
+        let state = app.state::<State>();
+        {
+            // A cancelled generation was already removed, and maybe replaced by
+            // a new one which must be kept. Ours is the one whose receiver is gone.
+            let mut generations = state.generations.lock().await;
+            if generations
+                .get(&conversationid)
+                .is_some_and(|tx| tx.is_closed())
+            {
+                generations.remove(&conversationid);
+            }
+        }
+        match result {
+            Ok((truncated, metrics)) => {
+                channel
+                    .send(GenerationEvent::Done { truncated, metrics })
+                    .ok();
+            }
+            Err(err) => {
+                error!("Generation failed for conversation {conversationid}: {err}");
+                // Only the hub token can be renewed by logging in again.
+                if kind == ModelKind::HuggingFace && err.is_invalid_token() {
+                    error!("Invalid token, deleting it");
+                    std::fs::remove_file(state.cache.token_path()).ok();
+                }
+                channel
+                    .send(GenerationEvent::Error {
+                        kind: err.kind(),
+                        message: err.to_string(),
+                    })
+                    .ok();
+            }
+        }
+    });
+    generations.insert(conversationid, tx);
+    Ok(())
+}
+
+/// Stops the reply being generated for `conversationid`, the partial reply is
+/// kept and marked as truncated.
+#[tauri::command]
+pub async fn cancel_generation(
+    state: tauri::State<'_, State>,
+    conversationid: u32,
+) -> Result<(), Error> {
+    if let Some(tx) = state.generations.lock().await.remove(&conversationid) {
+        info!("Cancelling generation for conversation {conversationid}");
+        tx.send(()).ok();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_prefer_backend_counts() {
+        let usage = Usage {
+            prompt_tokens: 120,
+            completion_tokens: 40,
+        };
+        let estimate = Usage {
+            prompt_tokens: 100,
+            completion_tokens: 50,
+        };
+        let second = Duration::from_secs(1);
+        let reported = metrics(Some(usage), estimate, second / 4, 2 * second);
+        assert_eq!(reported.prompt_tokens, 120);
+        assert_eq!(reported.ttft_ms, 250);
+        assert_eq!(reported.tokens_per_second, 20.0);
+        assert!(!reported.estimated);
+
+        let estimated = metrics(None, estimate, second, Duration::ZERO);
+        assert_eq!(estimated.completion_tokens, 50);
+        assert_eq!(estimated.tokens_per_second, 0.0);
+        assert!(estimated.estimated);
+    }
+}
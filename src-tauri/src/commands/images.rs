@@ -0,0 +1,209 @@
+//! Pictures the user sends to vision models. Files are stored once under
+//! their sha256 in the cache, messages only refer to them. They are read back
+//! and inlined right before the prompt is sent, see `inline`.
+
+use crate::commands::api::{InlineImage, Role, Turn};
+use crate::entities::message::{self, Image};
+use crate::State;
+use base64::Engine;
+use hf_hub::Cache;
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Larger pictures are refused, endpoints reject them anyway.
+const MAX_IMAGE_BYTES: usize = 20 << 20;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Only png, jpeg, gif and webp pictures can be sent")]
+    UnsupportedFormat,
+
+    #[error("The picture is too large, the limit is 20 MB")]
+    TooLarge,
+
+    #[error("Missing picture {0}")]
+    MissingImage(String),
+
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
+impl serde::Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
+
+/// The mime type of a picture, from its first bytes.
+fn sniff(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [0xff, 0xd8, 0xff, ..] => Some("image/jpeg"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        _ => None,
+    }
+}
+
+fn hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Where the picture is stored, `None` for anything but a sha256, so a hash
+/// coming from the webview cannot point outside of the cache.
+fn path(cache: &Cache, hash: &str) -> Option<PathBuf> {
+    if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut path = cache.path().clone();
+    path.push("images");
+    path.push(hash);
+    Some(path)
+}
+
+fn store(cache: &Cache, bytes: &[u8]) -> Result<Image, Error> {
+    if bytes.len() > MAX_IMAGE_BYTES {
+        return Err(Error::TooLarge);
+    }
+    let mime_type = sniff(bytes).ok_or(Error::UnsupportedFormat)?;
+    let hash = hash(bytes);
+    let path = path(cache, &hash).expect("A sha256");
+    // The same picture sent twice is stored once.
+    if !path.exists() {
+        std::fs::create_dir_all(path.parent().expect("Images dir"))?;
+        std::fs::write(&path, bytes)?;
+    }
+    Ok(Image {
+        hash,
+        mime_type: mime_type.to_string(),
+    })
+}
+
+fn read(cache: &Cache, hash: &str) -> Result<Vec<u8>, Error> {
+    let path = path(cache, hash).ok_or_else(|| Error::MissingImage(hash.to_string()))?;
+    std::fs::read(path).map_err(|_| Error::MissingImage(hash.to_string()))
+}
+
+/// Whether all of `images` are stored.
+pub fn exist(cache: &Cache, images: &[Image]) -> Result<(), Error> {
+    match images
+        .iter()
+        .find(|image| !path(cache, &image.hash).is_some_and(|path| path.exists()))
+    {
+        Some(image) => Err(Error::MissingImage(image.hash.clone())),
+        None => Ok(()),
+    }
+}
+
+/// The turn whose pictures are sent, the user's last. Earlier ones would be
+/// sent again on every turn, and keep failing with models which cannot see.
+fn picture_turn(turns: &[Turn]) -> Option<usize> {
+    turns
+        .iter()
+        .rposition(|turn| turn.message.role == Role::User)
+}
+
+/// Adds the pictures of `messages` to the user's last turn, see
+/// `picture_turn`. Pictures which went missing from the cache are left out.
+pub fn inline(cache: &Cache, turns: &mut [Turn], messages: &[message::Model]) {
+    let images: HashMap<u32, &[Image]> = messages
+        .iter()
+        .filter_map(|message| Some((message.id, message.images.as_ref()?.0.as_slice())))
+        .collect();
+    if let Some(turn) = picture_turn(turns).map(|i| &mut turns[i]) {
+        for id in &turn.ids {
+            for image in images.get(id).copied().unwrap_or_default() {
+                match read(cache, &image.hash) {
+                    Ok(data) => turn.message.images.push(InlineImage {
+                        mime_type: image.mime_type.clone(),
+                        data,
+                    }),
+                    Err(err) => warn!("Leaving out a picture of message {id}: {err}"),
+                }
+            }
+        }
+    }
+}
+
+/// Stores a picture, to be sent with `new_message`.
+#[tauri::command]
+pub async fn upload_image(state: tauri::State<'_, State>, data: Vec<u8>) -> Result<Image, Error> {
+    let image = store(&state.cache, &data)?;
+    info!("Stored picture {} ({} bytes)", image.hash, data.len());
+    Ok(image)
+}
+
+/// The picture as a data url, for the webview to show.
+#[tauri::command]
+pub async fn get_image(state: tauri::State<'_, State>, hash: String) -> Result<String, Error> {
+    let data = read(&state.cache, &hash)?;
+    let mime_type = sniff(&data).ok_or(Error::UnsupportedFormat)?;
+    let data = base64::engine::general_purpose::STANDARD.encode(data);
+    Ok(format!("data:{mime_type};base64,{data}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n"), Some("image/png"));
+        assert_eq!(sniff(b"\xff\xd8\xff\xe0"), Some("image/jpeg"));
+        assert_eq!(sniff(b"GIF89a"), Some("image/gif"));
+        assert_eq!(sniff(b"RIFF\x10\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff(b"%PDF-1.7"), None);
+    }
+
+    #[test]
+    fn content_addressed() {
+        let dir = std::env::temp_dir().join(format!("hf-chat-images-{}", std::process::id()));
+        let cache = Cache::new(dir.clone());
+        let png = b"\x89PNG\r\n\x1a\nnot really a picture";
+        let image = store(&cache, png).unwrap();
+        assert_eq!(image.hash, hash(png));
+        assert_eq!(image.mime_type, "image/png");
+        assert_eq!(store(&cache, png).unwrap(), image);
+        assert_eq!(read(&cache, &image.hash).unwrap(), png);
+        assert!(exist(&cache, &[image]).is_ok());
+        assert!(matches!(
+            store(&cache, b"plain text"),
+            Err(Error::UnsupportedFormat)
+        ));
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn pictures_of_the_last_question() {
+        let turn = |id: u32, role: Role| Turn {
+            ids: vec![id],
+            message: crate::commands::api::Message::new(role, String::new()),
+        };
+        let turns = [
+            turn(1, Role::User),
+            turn(2, Role::Assistant),
+            turn(3, Role::User),
+            turn(4, Role::Assistant),
+            turn(5, Role::Tool),
+        ];
+        assert_eq!(picture_turn(&turns), Some(2));
+        assert_eq!(picture_turn(&turns[..2]), Some(0));
+        assert_eq!(picture_turn(&[]), None);
+    }
+
+    #[test]
+    fn hashes_stay_in_the_cache() {
+        let cache = Cache::new(std::env::temp_dir());
+        assert!(path(&cache, "../../etc/passwd").is_none());
+        assert!(path(&cache, &hash(b"abc")).is_some());
+        assert_eq!(
+            hash(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -0,0 +1,507 @@
+//! A conversation's knowledge base: the files of a local folder, split into
+//! chunks of a few lines and embedded. The chunks closest to the user's last
+//! question are added to it in the prompt, the model cites them by number and
+//! the reply keeps where they come from.
+
+use crate::commands::api::Turn;
+use crate::commands::documents;
+use crate::commands::embeddings::{self, Embedder, EMBEDDING_MODEL};
+use crate::entities::message::{Source, Sources};
+use crate::entities::{chunk, conversation, knowledge_base};
+use crate::State;
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, ColumnTrait, DatabaseConnection, EntityTrait,
+    PaginatorTrait, QueryFilter,
+};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A chunk ends at the line reaching this many characters, which stays
+/// within the embedding model's 256 tokens.
+const CHUNK_CHARS: usize = 800;
+
+/// Lines repeated at the start of the next chunk, so a passage cut in two
+/// is still whole in one of them.
+const OVERLAP_LINES: usize = 2;
+
+/// Files larger than this are skipped.
+const MAX_FILE_BYTES: u64 = 20 << 20;
+
+/// Chunks embedded at once.
+const BATCH_SIZE: usize = 32;
+
+/// How many chunks go in the prompt at most.
+const TOP_K: usize = 4;
+
+/// Chunks less similar to the question are left out, the folder may not
+/// be about it at all.
+const MIN_SCORE: f32 = 0.25;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Missing conversation {0}")]
+    MissingConversation(u32),
+
+    #[error("Conversation {0} has no knowledge base")]
+    MissingKnowledgeBase(u32),
+
+    #[error("{0} is not a folder")]
+    NotAFolder(String),
+
+    #[error("Indexing was interrupted")]
+    Interrupted,
+
+    #[error(transparent)]
+    Embedding(#[from] embeddings::Error),
+
+    #[error("Db error {0}")]
+    DbError(#[from] sea_orm::DbErr),
+}
+
+impl serde::Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
+
+/// Lines `start_line` to `end_line` of a file, from 1.
+#[derive(Debug, PartialEq)]
+struct Piece {
+    start_line: u32,
+    end_line: u32,
+    content: String,
+}
+
+fn split(text: &str) -> Vec<Piece> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut pieces = vec![];
+    let mut start = 0;
+    while start < lines.len() {
+        let mut end = start;
+        let mut chars = 0;
+        // A single longer line is a chunk of its own.
+        while end < lines.len() && (end == start || chars + lines[end].len() <= CHUNK_CHARS) {
+            chars += lines[end].len() + 1;
+            end += 1;
+        }
+        let content = lines[start..end].join("\n");
+        if !content.trim().is_empty() {
+            pieces.push(Piece {
+                start_line: start as u32 + 1,
+                end_line: end as u32,
+                content,
+            });
+        }
+        if end == lines.len() {
+            break;
+        }
+        // Never more than half of the chunk again.
+        start = end
+            .saturating_sub(OVERLAP_LINES)
+            .max(start + (end - start).div_ceil(2));
+    }
+    pieces
+}
+
+/// The files under `root`, hidden ones and build outputs left out.
+fn files(root: &Path) -> Vec<PathBuf> {
+    let mut files = vec![];
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            warn!("Could not list {}", dir.display());
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') || name == "node_modules" || name == "target" {
+                continue;
+            }
+            // Links are not followed, they could loop.
+            match entry.file_type() {
+                Ok(kind) if kind.is_dir() => dirs.push(entry.path()),
+                Ok(kind) if kind.is_file() => files.push(entry.path()),
+                _ => {}
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// The text of the file at `path`, if it has some, with its path relative
+/// to `root`.
+fn read(root: &Path, path: &Path) -> Option<(String, String)> {
+    if !std::fs::metadata(path).is_ok_and(|metadata| metadata.len() <= MAX_FILE_BYTES) {
+        return None;
+    }
+    let relative = path.strip_prefix(root).ok()?.to_string_lossy().to_string();
+    let data = std::fs::read(path).ok()?;
+    match documents::extract(&relative, &data) {
+        Ok(text) => Some((relative, text)),
+        Err(err) => {
+            info!("Skipping {relative}: {err}");
+            None
+        }
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+/// The `TOP_K` chunks closest to `query`, best first. Embeddings are
+/// normalized so the dot product is the cosine similarity.
+fn top(query: &[f32], chunks: Vec<chunk::Model>) -> Vec<chunk::Model> {
+    let mut scored: Vec<(f32, chunk::Model)> = chunks
+        .into_iter()
+        .map(|chunk| (dot(query, &chunk.embedding()), chunk))
+        .filter(|(score, _)| *score >= MIN_SCORE)
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    scored
+        .into_iter()
+        .take(TOP_K)
+        .map(|(_, chunk)| chunk)
+        .collect()
+}
+
+/// The embedding model, loaded on first use and kept.
+async fn embedder(state: &State) -> Result<Arc<Embedder>, Error> {
+    let mut embedder = state.embedder.lock().await;
+    if let Some(embedder) = embedder.as_ref() {
+        return Ok(embedder.clone());
+    }
+    let loaded = Arc::new(Embedder::load(&state.cache).await?);
+    *embedder = Some(loaded.clone());
+    Ok(loaded)
+}
+
+async fn embed(embedder: Arc<Embedder>, texts: Vec<String>) -> Result<Vec<Vec<f32>>, Error> {
+    let embeddings = tauri::async_runtime::spawn_blocking(move || embedder.embed(&texts))
+        .await
+        .map_err(|_| Error::Interrupted)??;
+    Ok(embeddings)
+}
+
+/// The chunks of the conversation's knowledge base closest to `question`,
+/// none when it has no indexed knowledge base.
+pub async fn retrieve(
+    state: &State,
+    conversation_id: u32,
+    question: &str,
+) -> Result<Vec<chunk::Model>, Error> {
+    let Some(base) = knowledge_base::Entity::find()
+        .filter(knowledge_base::Column::ConversationId.eq(conversation_id))
+        .one(&state.db)
+        .await?
+        .filter(|base| base.indexed_at.is_some())
+    else {
+        return Ok(vec![]);
+    };
+    if base.embedding_model != EMBEDDING_MODEL {
+        warn!("{} was indexed with another model", base.path);
+        return Ok(vec![]);
+    }
+    let chunks = chunk::Entity::find()
+        .filter(chunk::Column::KnowledgeBaseId.eq(base.id))
+        .all(&state.db)
+        .await?;
+    if chunks.is_empty() {
+        return Ok(vec![]);
+    }
+    let query = embed(embedder(state).await?, vec![question.to_string()]).await?;
+    Ok(top(&query[0], chunks))
+}
+
+pub fn sources(chunks: &[chunk::Model]) -> Sources {
+    Sources(
+        chunks
+            .iter()
+            .map(|chunk| Source {
+                path: chunk.path.clone(),
+                start_line: chunk.start_line,
+                end_line: chunk.end_line,
+            })
+            .collect(),
+    )
+}
+
+/// Appends the chunks to the turn of the question `message_id`, numbered
+/// from 1 in the order of `sources`.
+pub fn inline(turns: &mut [Turn], message_id: u32, chunks: &[chunk::Model]) {
+    if chunks.is_empty() {
+        return;
+    }
+    let Some(turn) = turns.iter_mut().find(|turn| turn.ids.contains(&message_id)) else {
+        return;
+    };
+    let mut excerpts = String::from("<sources>\n");
+    for (i, chunk) in chunks.iter().enumerate() {
+        excerpts.push_str(&format!(
+            "<source id=\"{}\" file=\"{}\" lines=\"{}-{}\">\n{}\n</source>\n",
+            i + 1,
+            chunk.path,
+            chunk.start_line,
+            chunk.end_line,
+            chunk.content
+        ));
+    }
+    excerpts.push_str(
+        "</sources>\nAnswer from these excerpts where they help and cite them as [1], [2]...",
+    );
+    let content = &mut turn.message.content;
+    if !content.is_empty() {
+        content.push_str("\n\n");
+    }
+    content.push_str(&excerpts);
+}
+
+/// What the settings show of a knowledge base.
+#[derive(Serialize)]
+pub struct KnowledgeBase {
+    path: String,
+    indexed_at: Option<DateTime<Utc>>,
+    chunks: u64,
+}
+
+async fn describe(
+    db: &DatabaseConnection,
+    base: knowledge_base::Model,
+) -> Result<KnowledgeBase, Error> {
+    let chunks = chunk::Entity::find()
+        .filter(chunk::Column::KnowledgeBaseId.eq(base.id))
+        .count(db)
+        .await?;
+    Ok(KnowledgeBase {
+        path: base.path,
+        indexed_at: base.indexed_at,
+        chunks,
+    })
+}
+
+async fn find(
+    db: &DatabaseConnection,
+    conversationid: u32,
+) -> Result<Option<knowledge_base::Model>, Error> {
+    Ok(knowledge_base::Entity::find()
+        .filter(knowledge_base::Column::ConversationId.eq(conversationid))
+        .one(db)
+        .await?)
+}
+
+#[tauri::command]
+pub async fn get_knowledge_base(
+    state: tauri::State<'_, State>,
+    conversationid: u32,
+) -> Result<Option<KnowledgeBase>, Error> {
+    match find(&state.db, conversationid).await? {
+        Some(base) => Ok(Some(describe(&state.db, base).await?)),
+        None => Ok(None),
+    }
+}
+
+/// Points the conversation at the folder `path`, or at none. The folder is
+/// only read by `index_knowledge_base`.
+#[tauri::command]
+pub async fn set_knowledge_base(
+    state: tauri::State<'_, State>,
+    conversationid: u32,
+    path: Option<String>,
+) -> Result<(), Error> {
+    let db = &state.db;
+    conversation::Entity::find_by_id(conversationid)
+        .one(db)
+        .await?
+        .ok_or(Error::MissingConversation(conversationid))?;
+    // The chunks go along.
+    knowledge_base::Entity::delete_many()
+        .filter(knowledge_base::Column::ConversationId.eq(conversationid))
+        .exec(db)
+        .await?;
+    let Some(path) = path else {
+        info!("Removed the knowledge base of conversation {conversationid}");
+        return Ok(());
+    };
+    if !Path::new(&path).is_dir() {
+        return Err(Error::NotAFolder(path));
+    }
+    let base = knowledge_base::ActiveModel {
+        conversation_id: Set(conversationid),
+        path: Set(path),
+        embedding_model: Set(EMBEDDING_MODEL.to_string()),
+        indexed_at: Set(None),
+        created_at: Set(Utc::now()),
+        ..Default::default()
+    };
+    base.insert(db).await?;
+    Ok(())
+}
+
+/// Reads the folder again and replaces the chunks.
+#[tauri::command]
+pub async fn index_knowledge_base(
+    state: tauri::State<'_, State>,
+    conversationid: u32,
+) -> Result<KnowledgeBase, Error> {
+    let db = &state.db;
+    let base = find(db, conversationid)
+        .await?
+        .ok_or(Error::MissingKnowledgeBase(conversationid))?;
+    let root = PathBuf::from(&base.path);
+    if !root.is_dir() {
+        return Err(Error::NotAFolder(base.path));
+    }
+    let embedder = embedder(&state).await?;
+    info!("Indexing {}", base.path);
+    let paths = {
+        let root = root.clone();
+        tauri::async_runtime::spawn_blocking(move || files(&root))
+            .await
+            .map_err(|_| Error::Interrupted)?
+    };
+    // One file at a time, so a file the extractors choke on is skipped
+    // rather than failing the whole folder.
+    let mut documents = vec![];
+    for path in paths {
+        let (root, file) = (root.clone(), path.clone());
+        match tauri::async_runtime::spawn_blocking(move || read(&root, &file)).await {
+            Ok(document) => documents.extend(document),
+            Err(err) => warn!("Skipping {}: {err}", path.display()),
+        }
+    }
+    let pieces: Vec<(String, Piece)> = documents
+        .into_iter()
+        .flat_map(|(path, text)| {
+            split(&text)
+                .into_iter()
+                .map(move |piece| (path.clone(), piece))
+        })
+        .collect();
+    // Not searched until the new index is complete, a failure leaves it
+    // reported as not indexed rather than half done.
+    let mut base: knowledge_base::ActiveModel = base.into();
+    base.indexed_at = Set(None);
+    let base = base.update(db).await?;
+    chunk::Entity::delete_many()
+        .filter(chunk::Column::KnowledgeBaseId.eq(base.id))
+        .exec(db)
+        .await?;
+    for batch in pieces.chunks(BATCH_SIZE) {
+        let texts = batch
+            .iter()
+            .map(|(_, piece)| piece.content.clone())
+            .collect();
+        let embeddings = embed(embedder.clone(), texts).await?;
+        let chunks = batch
+            .iter()
+            .zip(embeddings)
+            .map(|((path, piece), embedding)| chunk::ActiveModel {
+                knowledge_base_id: Set(base.id),
+                path: Set(path.clone()),
+                start_line: Set(piece.start_line),
+                end_line: Set(piece.end_line),
+                content: Set(piece.content.clone()),
+                embedding: Set(chunk::encode(&embedding)),
+                ..Default::default()
+            });
+        chunk::Entity::insert_many(chunks).exec(db).await?;
+    }
+    info!("Indexed {} chunks of {}", pieces.len(), base.path);
+    let mut base: knowledge_base::ActiveModel = base.into();
+    base.embedding_model = Set(EMBEDDING_MODEL.to_string());
+    base.indexed_at = Set(Some(Utc::now()));
+    let base = base.update(db).await?;
+    describe(db, base).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::api::{Message, Role};
+
+    fn chunk(id: u32, embedding: &[f32]) -> chunk::Model {
+        chunk::Model {
+            id,
+            knowledge_base_id: 1,
+            path: format!("notes/{id}.md"),
+            start_line: 1,
+            end_line: 10,
+            content: format!("Chunk {id}"),
+            embedding: chunk::encode(embedding),
+        }
+    }
+
+    #[test]
+    fn chunks_overlap() {
+        let line = "a".repeat(265);
+        let text = vec![line.as_str(); 6].join("\n");
+        let lines: Vec<(u32, u32)> = split(&text)
+            .iter()
+            .map(|piece| (piece.start_line, piece.end_line))
+            .collect();
+        assert_eq!(lines, [(1, 3), (3, 5), (5, 6)]);
+        assert_eq!(
+            split("# Title\n\nSome text"),
+            [Piece {
+                start_line: 1,
+                end_line: 3,
+                content: "# Title\n\nSome text".to_string(),
+            }]
+        );
+        assert!(split("\n\n").is_empty());
+    }
+
+    #[test]
+    fn closest_chunks() {
+        let chunks = vec![
+            chunk(1, &[1.0, 0.0]),
+            chunk(2, &[0.6, 0.8]),
+            // Unrelated to the question.
+            chunk(3, &[-0.6, 0.8]),
+        ];
+        assert_eq!(chunks[1].embedding(), [0.6, 0.8]);
+        let ids: Vec<u32> = top(&[0.8, 0.6], chunks)
+            .iter()
+            .map(|chunk| chunk.id)
+            .collect();
+        assert_eq!(ids, [2, 1]);
+    }
+
+    #[test]
+    fn cited_excerpts() {
+        let mut turns = vec![
+            Turn {
+                ids: vec![1],
+                message: Message::new(Role::User, "Hello".to_string()),
+            },
+            Turn {
+                ids: vec![3],
+                message: Message::new(Role::User, "What do my notes say?".to_string()),
+            },
+        ];
+        let chunks = [chunk(7, &[1.0])];
+        inline(&mut turns, 3, &chunks);
+        assert_eq!(turns[0].message.content, "Hello");
+        assert_eq!(
+            turns[1].message.content,
+            "What do my notes say?\n\n<sources>\n\
+             <source id=\"1\" file=\"notes/7.md\" lines=\"1-10\">\nChunk 7\n</source>\n\
+             </sources>\nAnswer from these excerpts where they help and cite them as [1], [2]..."
+        );
+        assert_eq!(
+            sources(&chunks),
+            Sources(vec![Source {
+                path: "notes/7.md".to_string(),
+                start_line: 1,
+                end_line: 10,
+            }])
+        );
+    }
+}
@@ -1,8 +1,16 @@
-use crate::entities::message;
+use crate::commands::api::{self, Message, Role, Usage};
+use crate::commands::backend::ChatBackend;
+use crate::commands::tools::ToolDefinition;
+use crate::entities::message::ToolCall;
+use crate::entities::model::Parameters;
+use log::warn;
 use mistralrs::{
-    Model, NormalRequest, PagedAttentionMetaBuilder, Request, RequestLike, Response,
-    TextMessageRole, TextMessages, TextModelBuilder,
+    CalledFunction, Function, Model, NormalRequest, PagedAttentionMetaBuilder, Request,
+    RequestBuilder, RequestLike, Response, SamplingParams, StopTokens, TextMessageRole,
+    TextModelBuilder, Tool, ToolCallResponse, ToolCallType, ToolChoice, ToolType, VisionLoaderType,
+    VisionModelBuilder,
 };
+use std::time::Duration;
 use tauri::async_runtime::{channel, Receiver};
 
 #[derive(Debug, thiserror::Error)]
@@ -20,65 +28,227 @@ pub enum Error {
 pub struct Stream {
     model: Model,
     rx: Receiver<Response>,
+    usage: Option<Usage>,
+    tool_calls: Vec<ToolCall>,
 }
 
 impl Stream {
+    pub fn usage(&self) -> Option<Usage> {
+        self.usage
+    }
+
+    pub fn tool_calls(&mut self) -> Vec<ToolCall> {
+        std::mem::take(&mut self.tool_calls)
+    }
+
     pub async fn next(&mut self) -> Option<String> {
         let chunk = self.rx.recv().await?;
         if let Response::Chunk(chunk) = chunk {
-            Some(chunk.choices[0].delta.content.to_string())
+            // The last chunk carries the counts.
+            if let Some(usage) = &chunk.usage {
+                self.usage = Some(Usage {
+                    prompt_tokens: usage.prompt_tokens,
+                    completion_tokens: usage.completion_tokens,
+                });
+            }
+            let delta = &chunk.choices[0].delta;
+            // Parsed by mistral.rs once the model is done writing them.
+            for call in delta.tool_calls.iter().flatten() {
+                self.tool_calls.push(ToolCall {
+                    id: call.id.clone(),
+                    name: call.function.name.clone(),
+                    arguments: call.function.arguments.clone(),
+                });
+            }
+            Some(delta.content.to_string())
         } else {
             None
         }
     }
-}
 
-fn to_mistralrs(messages: Vec<message::Model>) -> TextMessages {
-    let mut newmessages = TextMessages::new();
-    let mut role = TextMessageRole::Assistant;
-    let mut last_user = None;
-    let mut last_message: Option<String> = None;
-    for message in messages {
-        if Some(message.user_id) != last_user {
-            if let Some(last_message) = last_message.take() {
-                newmessages = newmessages.add_message(role.clone(), last_message);
+    /// Stops the engine, this stream owns its model so nothing else is using it.
+    pub async fn cancel(self) {
+        match self.model.inner().get_sender() {
+            Ok(sender) => {
+                sender.send(Request::Terminate).await.ok();
             }
-            role = match role {
-                TextMessageRole::Custom(_) => TextMessageRole::User,
-                TextMessageRole::Tool => TextMessageRole::User,
-                TextMessageRole::System => TextMessageRole::User,
-                TextMessageRole::User => TextMessageRole::Assistant,
-                TextMessageRole::Assistant => TextMessageRole::User,
-            };
-            last_message = Some(message.content.clone());
-            last_user = Some(message.user_id);
-        } else {
-            last_message.as_mut().map(|m| {
-                m.push('\n');
-                m.push_str(&message.content);
-            });
+            Err(err) => log::warn!("Could not terminate local model {err}"),
+        }
+    }
+}
+
+/// The architecture of a vision model, guessed from its id. `None` for text
+/// only models.
+fn vision_loader(model_id: &str) -> Option<VisionLoaderType> {
+    let id = model_id.to_lowercase();
+    let id = id.rsplit('/').next().unwrap_or(&id);
+    if id.starts_with("phi-3") && id.contains("vision") {
+        Some(VisionLoaderType::Phi3V)
+    } else if id.starts_with("idefics2") {
+        Some(VisionLoaderType::Idefics2)
+    } else if id.starts_with("llava-v1.6") || id.contains("llava-next") {
+        Some(VisionLoaderType::LLaVANext)
+    } else if id.starts_with("llava") {
+        Some(VisionLoaderType::LLaVA)
+    } else if id.starts_with("llama-3.2") && id.contains("vision") {
+        Some(VisionLoaderType::VLlama)
+    } else if id.starts_with("qwen2-vl") {
+        Some(VisionLoaderType::Qwen2VL)
+    } else {
+        None
+    }
+}
+
+/// Vision models here take a single picture per message, and chat templates
+/// expect user and assistant turns to alternate so it cannot be sent as a
+/// turn of its own. Only the last picture is kept.
+fn keep_last_picture(messages: &mut [Message]) {
+    let Some(last) = messages
+        .iter()
+        .rposition(|message| !message.images.is_empty())
+    else {
+        return;
+    };
+    for (i, message) in messages.iter_mut().enumerate() {
+        let keep = if i == last { 1 } else { 0 };
+        let left_out = message.images.len().saturating_sub(keep);
+        if left_out > 0 {
+            log::info!("Leaving out {left_out} pictures, the model takes one");
+            let images = std::mem::take(&mut message.images);
+            message.images = images.into_iter().rev().take(keep).collect();
         }
     }
-    if let Some(last_message) = last_message.take() {
-        newmessages = newmessages.add_message(role, last_message);
+}
+
+/// Pictures are only sent to vision models, others get the text alone.
+fn to_mistralrs(messages: Vec<Message>, tools: &[ToolDefinition], vision: bool) -> RequestBuilder {
+    let request = messages
+        .into_iter()
+        .fold(RequestBuilder::new(), |request, message| {
+            let role = match message.role {
+                Role::System => TextMessageRole::System,
+                Role::User => TextMessageRole::User,
+                Role::Assistant => TextMessageRole::Assistant,
+                Role::Tool => {
+                    let id = message.tool_call_id.unwrap_or_default();
+                    return request.add_tool_message(message.content, id);
+                }
+            };
+            // At most one, see `keep_last_picture`.
+            if let Some(picture) = message.images.first().filter(|_| vision) {
+                match image::load_from_memory(&picture.data) {
+                    Ok(picture) => {
+                        return request.add_image_message(role, message.content, picture)
+                    }
+                    Err(err) => warn!("Leaving out an unreadable picture: {err}"),
+                }
+            }
+            if message.tool_calls.is_empty() {
+                return request.add_message(role, message.content);
+            }
+            let tool_calls = message
+                .tool_calls
+                .into_iter()
+                .map(|call| ToolCallResponse {
+                    id: call.id,
+                    tp: ToolCallType::Function,
+                    function: CalledFunction {
+                        name: call.name,
+                        arguments: call.arguments,
+                    },
+                })
+                .collect();
+            request.add_message_with_tool_call(role, message.content, tool_calls)
+        });
+    if tools.is_empty() {
+        return request;
+    }
+    let tools = tools
+        .iter()
+        .map(|tool| Tool {
+            tp: ToolType::Function,
+            function: Function {
+                description: Some(tool.description.clone()),
+                name: tool.name.clone(),
+                parameters: serde_json::from_value(tool.parameters.clone()).ok(),
+            },
+        })
+        .collect();
+    request.set_tools(tools).set_tool_choice(ToolChoice::Auto)
+}
+
+fn sampling_params(parameters: &Parameters) -> SamplingParams {
+    // Greedy unless a temperature was set, a zero temperature is not a valid
+    // divisor for mistral.rs.
+    let mut params = SamplingParams::deterministic();
+    if parameters.temperature > 0.0 {
+        params.temperature = Some(parameters.temperature.into());
+        params.top_k = parameters.top_k();
+        params.top_p = parameters.top_p().map(Into::into);
+    }
+    // mistral.rs has no multiplicative repetition penalty.
+    params.max_len = Some(parameters.max_new_tokens());
+    params.stop_toks = parameters.stop().map(StopTokens::Seqs);
+    params
+}
+
+/// Models run in process by mistral.rs.
+pub struct Local {
+    model_id: String,
+}
+
+impl Local {
+    pub fn new(model_id: String) -> Self {
+        Self { model_id }
+    }
+}
+
+#[async_trait::async_trait]
+impl ChatBackend for Local {
+    async fn stream(
+        &self,
+        messages: Vec<Message>,
+        tools: &[ToolDefinition],
+        parameters: &Parameters,
+        _on_wait: &mut (dyn FnMut(&api::Error, Duration) + Send),
+    ) -> Result<api::Stream, api::Error> {
+        let stream = local_stream(self.model_id.clone(), messages, tools, parameters).await?;
+        Ok(stream.into())
     }
-    newmessages
 }
 
-pub async fn local_stream(
+async fn local_stream(
     model_id: String,
-    messages: Vec<message::Model>,
+    messages: Vec<Message>,
+    tools: &[ToolDefinition],
+    parameters: &Parameters,
 ) -> Result<Stream, Error> {
-    let model = TextModelBuilder::new(model_id)
-        // .with_isq(IsqType::Q8_0)
-        // .with_logging()
-        .with_paged_attn(|| PagedAttentionMetaBuilder::default().build())?
-        .build()
-        .await?;
+    let vision = vision_loader(&model_id);
+    let mut messages = messages;
+    if vision.is_some() {
+        keep_last_picture(&mut messages);
+    } else if messages.iter().any(|message| !message.images.is_empty()) {
+        warn!("{model_id} is not a vision model, pictures are left out");
+    }
+    let model = match vision {
+        Some(loader) => VisionModelBuilder::new(model_id, loader).build().await?,
+        None => {
+            TextModelBuilder::new(model_id)
+                // .with_isq(IsqType::Q8_0)
+                // .with_logging()
+                .with_paged_attn(|| PagedAttentionMetaBuilder::default().build())?
+                .build()
+                .await?
+        }
+    };
 
     log::info!("Model started");
-    let messages = to_mistralrs(messages);
-    log::info!("Conversation {messages:?}");
+    log::info!(
+        "Conversation of {} messages, {} tools",
+        messages.len(),
+        tools.len()
+    );
+    let messages = to_mistralrs(messages, tools, vision.is_some());
 
     let (tx, rx) = channel(20);
 
@@ -90,7 +260,7 @@ pub async fn local_stream(
     };
     let request = Request::Normal(NormalRequest {
         messages: request.take_messages(),
-        sampling_params: request.take_sampling_params(),
+        sampling_params: sampling_params(parameters),
         response: tx,
         return_logprobs: request.return_logprobs(),
         is_streaming: true,
@@ -113,10 +283,64 @@ pub async fn local_stream(
         .await
         .unwrap();
 
-    Ok(Stream { model, rx })
+    Ok(Stream {
+        model,
+        rx,
+        usage: None,
+        tool_calls: vec![],
+    })
     // while let Some(chunk) = stream.next().await {
     //     if let Response::Chunk(chunk) = chunk {
     //         print!("{}", chunk.choices[0].delta.content);
     //     }
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::api::InlineImage;
+
+    #[test]
+    fn vision_models() {
+        assert!(matches!(
+            vision_loader("microsoft/Phi-3.5-vision-instruct"),
+            Some(VisionLoaderType::Phi3V)
+        ));
+        assert!(matches!(
+            vision_loader("meta-llama/Llama-3.2-11B-Vision-Instruct"),
+            Some(VisionLoaderType::VLlama)
+        ));
+        assert!(matches!(
+            vision_loader("llava-hf/llava-v1.6-mistral-7b-hf"),
+            Some(VisionLoaderType::LLaVANext)
+        ));
+        assert!(matches!(
+            vision_loader("Qwen/Qwen2-VL-2B-Instruct"),
+            Some(VisionLoaderType::Qwen2VL)
+        ));
+        assert!(vision_loader("microsoft/Phi-3.5-mini-instruct").is_none());
+        assert!(vision_loader("meta-llama/Llama-3.2-3B-Instruct").is_none());
+    }
+
+    #[test]
+    fn one_picture_per_request() {
+        let picture = |data: u8| InlineImage {
+            mime_type: "image/png".to_string(),
+            data: vec![data],
+        };
+        let mut first = Message::new(Role::User, "What is this?".to_string());
+        first.images = vec![picture(1)];
+        let answer = Message::new(Role::Assistant, "A cat.".to_string());
+        // Two messages in a row make a single turn with both pictures.
+        let mut second = Message::new(Role::User, "And these?".to_string());
+        second.images = vec![picture(2), picture(3)];
+        let mut messages = vec![first, answer, second];
+        keep_last_picture(&mut messages);
+        let images: Vec<_> = messages
+            .iter()
+            .map(|message| message.images.clone())
+            .collect();
+        assert_eq!(images, [vec![], vec![], vec![picture(3)]]);
+    }
+}
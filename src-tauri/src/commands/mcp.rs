@@ -0,0 +1,903 @@
+//! Client for Model Context Protocol servers: programs speaking json-rpc over
+//! their stdin and stdout, one message per line. Their tools are offered to
+//! the model next to the built-in ones, their resources are read through the
+//! `read_resource` tool. Servers are started on first use and kept running.
+
+use crate::commands::tools::{self, cut, Registry, Tool, ToolContext, ToolDefinition};
+use crate::entities::mcp_server::{self, Arguments, Environment};
+use crate::State;
+use chrono::Utc;
+use log::{info, warn};
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, ColumnTrait, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::Mutex;
+
+/// The revision of the protocol we speak, servers answer with theirs.
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// How long a server may take to answer, tools included.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Resources longer than this are cut, like attachments.
+const MAX_RESOURCE_CHARS: usize = 32_000;
+
+/// How long a server which failed is left alone, doubled on each failure in
+/// a row up to `MAX_BACKOFF`.
+const BACKOFF: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
+/// When a server last failed, and how many times in a row.
+pub struct Failure {
+    at: Instant,
+    count: u32,
+}
+
+/// Servers which failed, keyed by server id.
+pub type Failures = Mutex<HashMap<u32, Failure>>;
+
+fn backoff(count: u32) -> Duration {
+    BACKOFF
+        .saturating_mul(1 << count.saturating_sub(1).min(16))
+        .min(MAX_BACKOFF)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Missing MCP server {0}")]
+    MissingServer(u32),
+
+    #[error("Could not talk to the server: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid message from the server: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("The server failed {method}: {message} ({code})")]
+    Rpc {
+        method: String,
+        code: i64,
+        message: String,
+    },
+
+    #[error("The server exited")]
+    Closed,
+
+    #[error("{0}")]
+    ToolFailed(String),
+
+    #[error("The server did not answer {0} in time")]
+    Timeout(String),
+
+    #[error("The server failed recently, trying again in {0}s")]
+    Backoff(u64),
+
+    #[error("Db error {0}")]
+    DbError(#[from] sea_orm::DbErr),
+}
+
+impl serde::Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    jsonrpc: &'static str,
+    /// Notifications have none and get no answer.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
+    method: &'a str,
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    /// Set on the server's own requests, which share the id space.
+    method: Option<String>,
+    id: Option<Value>,
+    result: Option<Value>,
+    error: Option<RpcError>,
+}
+
+#[derive(Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InitializeResult {
+    #[serde(default)]
+    capabilities: Capabilities,
+}
+
+#[derive(Default, Deserialize)]
+struct Capabilities {
+    tools: Option<Value>,
+    resources: Option<Value>,
+}
+
+/// A tool as listed by a server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerTool {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub input_schema: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Resource {
+    pub uri: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub mime_type: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ToolList {
+    tools: Vec<ServerTool>,
+    next_cursor: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ResourceList {
+    resources: Vec<Resource>,
+    next_cursor: Option<String>,
+}
+
+/// Tool results and resources are lists of parts, only text is kept.
+#[derive(Deserialize)]
+struct Content {
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CallResult {
+    #[serde(default)]
+    content: Vec<Content>,
+    #[serde(default)]
+    is_error: bool,
+}
+
+#[derive(Deserialize)]
+struct ReadResult {
+    contents: Vec<Content>,
+}
+
+fn text(content: Vec<Content>) -> String {
+    content
+        .into_iter()
+        .filter_map(|content| content.text)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+struct Connection {
+    reader: Box<dyn AsyncBufRead + Unpin + Send>,
+    writer: Box<dyn AsyncWrite + Unpin + Send>,
+    next_id: u64,
+}
+
+impl Connection {
+    async fn send(&mut self, message: &impl Serialize) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(message)?;
+        line.push(b'\n');
+        self.writer.write_all(&line).await?;
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    /// Answers a request of the server, which must not go unanswered. Only
+    /// `ping` is supported.
+    async fn answer(&mut self, id: Value, method: &str) -> Result<(), Error> {
+        let answer = match method {
+            "ping" => json!({"jsonrpc": "2.0", "id": id, "result": {}}),
+            _ => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {"code": -32601, "message": format!("Method not found: {method}")},
+            }),
+        };
+        self.send(&answer).await
+    }
+
+    /// Sends `method` and reads until its answer, skipping the server's
+    /// notifications and answering its requests.
+    async fn request(&mut self, method: &str, params: Value) -> Result<Value, Error> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(&Request {
+            jsonrpc: "2.0",
+            id: Some(id),
+            method,
+            params,
+        })
+        .await?;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line).await? == 0 {
+                return Err(Error::Closed);
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response: Response = serde_json::from_str(&line)?;
+            if let Some(method) = response.method {
+                if let Some(id) = response.id {
+                    self.answer(id, &method).await?;
+                }
+                continue;
+            }
+            if response.id != Some(json!(id)) {
+                continue;
+            }
+            if let Some(error) = response.error {
+                return Err(Error::Rpc {
+                    method: method.to_string(),
+                    code: error.code,
+                    message: error.message,
+                });
+            }
+            return Ok(response.result.unwrap_or(Value::Null));
+        }
+    }
+}
+
+/// A connection to a running server. Requests are answered one at a time.
+pub struct Client {
+    name: String,
+    connection: Mutex<Connection>,
+    has_tools: bool,
+    has_resources: bool,
+    /// Killed when the client is dropped.
+    _child: Option<Child>,
+}
+
+impl Client {
+    /// Runs the protocol's handshake over `reader` and `writer`.
+    pub async fn connect(
+        name: String,
+        reader: impl AsyncBufRead + Unpin + Send + 'static,
+        writer: impl AsyncWrite + Unpin + Send + 'static,
+    ) -> Result<Self, Error> {
+        let mut connection = Connection {
+            reader: Box::new(reader),
+            writer: Box::new(writer),
+            next_id: 0,
+        };
+        let params = json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": {},
+            "clientInfo": {"name": "hf-chat", "version": env!("CARGO_PKG_VERSION")},
+        });
+        let result = tokio::time::timeout(TIMEOUT, connection.request("initialize", params))
+            .await
+            .map_err(|_| Error::Timeout("initialize".to_string()))??;
+        let InitializeResult { capabilities } = serde_json::from_value(result)?;
+        connection
+            .send(&Request {
+                jsonrpc: "2.0",
+                id: None,
+                method: "notifications/initialized",
+                params: json!({}),
+            })
+            .await?;
+        Ok(Self {
+            name,
+            connection: Mutex::new(connection),
+            has_tools: capabilities.tools.is_some(),
+            has_resources: capabilities.resources.is_some(),
+            _child: None,
+        })
+    }
+
+    /// Starts `server` and connects to it.
+    pub async fn spawn(server: &mcp_server::Model) -> Result<Self, Error> {
+        info!("Starting MCP server {} ({})", server.name, server.command);
+        let mut child = Command::new(&server.command)
+            .args(&server.args.0)
+            .envs(&server.env.0)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+        let stdin = child.stdin.take().expect("Piped stdin");
+        let stdout = BufReader::new(child.stdout.take().expect("Piped stdout"));
+        let mut client = Self::connect(server.name.clone(), stdout, stdin).await?;
+        client._child = Some(child);
+        Ok(client)
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<Value, Error> {
+        let mut connection = self.connection.lock().await;
+        tokio::time::timeout(TIMEOUT, connection.request(method, params))
+            .await
+            .map_err(|_| Error::Timeout(method.to_string()))?
+    }
+
+    pub async fn list_tools(&self) -> Result<Vec<ServerTool>, Error> {
+        if !self.has_tools {
+            return Ok(vec![]);
+        }
+        let mut tools = vec![];
+        let mut cursor = None;
+        loop {
+            let params = cursor.map(|cursor| json!({"cursor": cursor}));
+            let list: ToolList = serde_json::from_value(
+                self.request("tools/list", params.unwrap_or(json!({})))
+                    .await?,
+            )?;
+            tools.extend(list.tools);
+            cursor = list.next_cursor;
+            if cursor.is_none() {
+                return Ok(tools);
+            }
+        }
+    }
+
+    pub async fn list_resources(&self) -> Result<Vec<Resource>, Error> {
+        if !self.has_resources {
+            return Ok(vec![]);
+        }
+        let mut resources = vec![];
+        let mut cursor = None;
+        loop {
+            let params = cursor.map(|cursor| json!({"cursor": cursor}));
+            let list: ResourceList = serde_json::from_value(
+                self.request("resources/list", params.unwrap_or(json!({})))
+                    .await?,
+            )?;
+            resources.extend(list.resources);
+            cursor = list.next_cursor;
+            if cursor.is_none() {
+                return Ok(resources);
+            }
+        }
+    }
+
+    /// The text the tool returned. Tools reporting an error are `ToolFailed`,
+    /// with their message.
+    pub async fn call_tool(&self, name: &str, arguments: Value) -> Result<String, Error> {
+        let params = json!({"name": name, "arguments": arguments});
+        let result: CallResult = serde_json::from_value(self.request("tools/call", params).await?)?;
+        if result.is_error {
+            return Err(Error::ToolFailed(text(result.content)));
+        }
+        Ok(text(result.content))
+    }
+
+    pub async fn read_resource(&self, uri: &str) -> Result<String, Error> {
+        let result = self.request("resources/read", json!({"uri": uri})).await?;
+        let result: ReadResult = serde_json::from_value(result)?;
+        Ok(text(result.contents))
+    }
+}
+
+/// A server's tool, as offered to the model.
+struct McpTool {
+    client: Arc<Client>,
+    /// Prefixed with the server's name, see `qualified`.
+    name: String,
+    tool: ServerTool,
+}
+
+/// The name a server's tool is offered and allowed under. Servers do not
+/// know about each other nor about the built-in tools, their own names may
+/// clash. Function names may only hold letters, digits, `_` and `-`.
+fn qualified(server: &str, tool: &str) -> String {
+    format!("{server}__{tool}")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Adds the tools of `client` to `registry`, those whose name is taken are
+/// left out.
+fn with_tools(mut registry: Registry, client: &Arc<Client>, tools: Vec<ServerTool>) -> Registry {
+    for tool in tools {
+        let name = qualified(&client.name, &tool.name);
+        if registry.has(&name) {
+            warn!("Leaving out MCP tool {name}, the name is taken");
+            continue;
+        }
+        registry = registry.with(McpTool {
+            client: client.clone(),
+            name,
+            tool,
+        });
+    }
+    registry
+}
+
+#[async_trait::async_trait]
+impl Tool for McpTool {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            name: self.name.clone(),
+            description: self.tool.description.clone(),
+            parameters: self.tool.input_schema.clone(),
+        }
+    }
+
+    async fn call(
+        &self,
+        _context: &ToolContext<'_>,
+        arguments: Value,
+    ) -> Result<String, tools::Error> {
+        self.client
+            .call_tool(&self.tool.name, arguments)
+            .await
+            .map_err(|err| tools::Error::Failed(err.to_string()))
+    }
+}
+
+/// The resources of every server offering some.
+struct ReadResource {
+    clients: Vec<Arc<Client>>,
+}
+
+#[derive(Deserialize)]
+struct ReadResourceArguments {
+    #[serde(default)]
+    uri: Option<String>,
+}
+
+#[async_trait::async_trait]
+impl Tool for ReadResource {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            name: "read_resource".to_string(),
+            description: "Reads a resource of the connected MCP servers. \
+                Without a uri, lists the resources."
+                .to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "uri": {
+                        "type": "string",
+                        "description": "The resource's uri, as listed",
+                    },
+                },
+            }),
+        }
+    }
+
+    async fn call(
+        &self,
+        _context: &ToolContext<'_>,
+        arguments: Value,
+    ) -> Result<String, tools::Error> {
+        let ReadResourceArguments { uri } = serde_json::from_value(arguments)?;
+        let failed = |err: Error| tools::Error::Failed(err.to_string());
+        let mut listing = vec![];
+        for client in &self.clients {
+            for resource in client.list_resources().await.map_err(failed)? {
+                if uri.as_ref() == Some(&resource.uri) {
+                    let content = client.read_resource(&resource.uri).await.map_err(failed)?;
+                    return Ok(cut(&content, MAX_RESOURCE_CHARS));
+                }
+                let mut line =
+                    format!("{} ({}, from {})", resource.uri, resource.name, client.name);
+                if let Some(description) = resource.description {
+                    line = format!("{line}: {description}");
+                }
+                listing.push(line);
+            }
+        }
+        match uri {
+            Some(uri) => Err(tools::Error::Failed(format!("No resource {uri}"))),
+            None if listing.is_empty() => Ok("There are no resources.".to_string()),
+            None => Ok(listing.join("\n")),
+        }
+    }
+}
+
+/// The running client of `server`, started when needed unless it failed
+/// recently. The lock is not held while starting, a slow server does not
+/// hold up the others.
+async fn client(state: &State, server: &mcp_server::Model) -> Result<Arc<Client>, Error> {
+    if let Some(client) = state.mcp.lock().await.get(&server.id) {
+        return Ok(client.clone());
+    }
+    if let Some(failure) = state.mcp_failures.lock().await.get(&server.id) {
+        let wait = backoff(failure.count).saturating_sub(failure.at.elapsed());
+        if !wait.is_zero() {
+            return Err(Error::Backoff(wait.as_secs().max(1)));
+        }
+    }
+    let client = match Client::spawn(server).await {
+        Ok(client) => Arc::new(client),
+        Err(err) => {
+            failed(state, server.id).await;
+            return Err(err);
+        }
+    };
+    state.mcp_failures.lock().await.remove(&server.id);
+    // Another caller may have started it meanwhile, theirs is kept.
+    Ok(state
+        .mcp
+        .lock()
+        .await
+        .entry(server.id)
+        .or_insert(client)
+        .clone())
+}
+
+/// Stops the server and leaves it alone for a while.
+async fn failed(state: &State, server_id: u32) {
+    state.mcp.lock().await.remove(&server_id);
+    let mut failures = state.mcp_failures.lock().await;
+    let count = failures.get(&server_id).map_or(0, |failure| failure.count) + 1;
+    failures.insert(
+        server_id,
+        Failure {
+            at: Instant::now(),
+            count,
+        },
+    );
+}
+
+/// Adds the tools of the enabled servers to `registry`. Servers which fail
+/// are left out, and started again once their backoff has passed.
+pub async fn with_servers(state: &State, mut registry: Registry) -> Registry {
+    let servers = match mcp_server::Entity::find()
+        .filter(mcp_server::Column::Enabled.eq(true))
+        .all(&state.db)
+        .await
+    {
+        Ok(servers) => servers,
+        Err(err) => {
+            warn!("Could not load MCP servers: {err}");
+            return registry;
+        }
+    };
+    let mut with_resources = vec![];
+    for server in servers {
+        let tools = match client(state, &server).await {
+            Ok(client) => client.list_tools().await.map(|tools| (client, tools)),
+            Err(err) => Err(err),
+        };
+        match tools {
+            Ok((client, tools)) => {
+                registry = with_tools(registry, &client, tools);
+                if client.has_resources {
+                    with_resources.push(client);
+                }
+            }
+            Err(err) => {
+                warn!("Leaving out MCP server {}: {err}", server.name);
+                if !matches!(err, Error::Backoff(_)) {
+                    failed(state, server.id).await;
+                }
+            }
+        }
+    }
+    if !with_resources.is_empty() {
+        registry = registry.with(ReadResource {
+            clients: with_resources,
+        });
+    }
+    registry
+}
+
+#[tauri::command]
+pub async fn get_mcp_servers(
+    state: tauri::State<'_, State>,
+) -> Result<Vec<mcp_server::Model>, Error> {
+    Ok(mcp_server::Entity::find().all(&state.db).await?)
+}
+
+/// Registers a server started as `command` with `args`. It is started when
+/// the model is first offered tools.
+#[tauri::command]
+pub async fn create_mcp_server(
+    state: tauri::State<'_, State>,
+    name: String,
+    command: String,
+    args: Option<Vec<String>>,
+    env: Option<BTreeMap<String, String>>,
+) -> Result<mcp_server::Model, Error> {
+    info!("Adding MCP server {name}");
+    let server = mcp_server::ActiveModel {
+        name: Set(name),
+        command: Set(command),
+        args: Set(Arguments(args.unwrap_or_default())),
+        env: Set(Environment(env.unwrap_or_default())),
+        enabled: Set(true),
+        created_at: Set(Utc::now()),
+        ..Default::default()
+    };
+    Ok(server.insert(&state.db).await?)
+}
+
+#[tauri::command]
+pub async fn set_mcp_server_enabled(
+    state: tauri::State<'_, State>,
+    serverid: u32,
+    enabled: bool,
+) -> Result<(), Error> {
+    let db = &state.db;
+    let server = mcp_server::Entity::find_by_id(serverid)
+        .one(db)
+        .await?
+        .ok_or(Error::MissingServer(serverid))?;
+    let mut server: mcp_server::ActiveModel = server.into();
+    server.enabled = Set(enabled);
+    server.update(db).await?;
+    if !enabled {
+        state.mcp.lock().await.remove(&serverid);
+    }
+    state.mcp_failures.lock().await.remove(&serverid);
+    Ok(())
+}
+
+/// Stops the server if it is running.
+#[tauri::command]
+pub async fn delete_mcp_server(state: tauri::State<'_, State>, serverid: u32) -> Result<(), Error> {
+    state.mcp.lock().await.remove(&serverid);
+    state.mcp_failures.lock().await.remove(&serverid);
+    mcp_server::Entity::delete_by_id(serverid)
+        .exec(&state.db)
+        .await?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct ServerCapabilities {
+    tools: Vec<ServerTool>,
+    resources: Vec<Resource>,
+}
+
+/// What the server offers, starting it if needed. Asked for by the user, so
+/// a recent failure is not waited out.
+#[tauri::command]
+pub async fn get_mcp_server_capabilities(
+    state: tauri::State<'_, State>,
+    serverid: u32,
+) -> Result<ServerCapabilities, Error> {
+    let server = mcp_server::Entity::find_by_id(serverid)
+        .one(&state.db)
+        .await?
+        .ok_or(Error::MissingServer(serverid))?;
+    state.mcp_failures.lock().await.remove(&serverid);
+    let client = client(&state, &server).await?;
+    let capabilities = async {
+        Ok::<_, Error>(ServerCapabilities {
+            tools: client.list_tools().await?,
+            resources: client.list_resources().await?,
+        })
+    }
+    .await;
+    if capabilities.is_err() {
+        failed(&state, serverid).await;
+    }
+    capabilities
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::message::ToolCall;
+    use sea_orm::DatabaseConnection;
+    use tokio::io::{duplex, split, DuplexStream};
+
+    /// A server with an `echo` tool and a single resource. It pings the
+    /// client before each answer, and its `pongs` tool tells how many pings
+    /// were answered.
+    async fn echo_server(stream: DuplexStream) {
+        let (reader, mut writer) = split(stream);
+        let mut lines = BufReader::new(reader).lines();
+        let mut pongs = 0;
+        while let Ok(Some(line)) = lines.next_line().await {
+            let request: Value = serde_json::from_str(&line).unwrap();
+            let Some(id) = request.get("id").cloned() else {
+                // A notification.
+                continue;
+            };
+            if request.get("method").is_none() {
+                // The client's answer to a ping.
+                if request["result"] == json!({}) {
+                    pongs += 1;
+                }
+                continue;
+            }
+            let params = &request["params"];
+            let mut answer = match request["method"].as_str().unwrap() {
+                "initialize" => json!({"result": {
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "capabilities": {"tools": {}, "resources": {}},
+                    "serverInfo": {"name": "echo", "version": "1"},
+                }}),
+                "tools/list" => json!({"result": {"tools": [{
+                    "name": "echo",
+                    "description": "Says it back.",
+                    "inputSchema": {"type": "object", "properties": {"text": {"type": "string"}}},
+                }]}}),
+                "tools/call" if params["name"] == "echo" => json!({"result": {
+                    "content": [{"type": "text", "text": params["arguments"]["text"]}],
+                }}),
+                "tools/call" if params["name"] == "pongs" => json!({"result": {
+                    "content": [{"type": "text", "text": pongs.to_string()}],
+                }}),
+                "tools/call" => json!({"result": {
+                    "content": [{"type": "text", "text": "Unknown tool"}],
+                    "isError": true,
+                }}),
+                "resources/list" => json!({"result": {"resources": [{
+                    "uri": "echo://readme",
+                    "name": "readme",
+                }]}}),
+                "resources/read" => json!({"result": {"contents": [{
+                    "uri": "echo://readme",
+                    "text": "Hello from echo",
+                }]}}),
+                _ => json!({"error": {"code": -32601, "message": "Method not found"}}),
+            };
+            answer["jsonrpc"] = json!("2.0");
+            answer["id"] = id;
+            // A log notification and a ping first, which the client has to
+            // skip and answer.
+            let log = json!({"jsonrpc": "2.0", "method": "notifications/message", "params": {}});
+            let ping = json!({"jsonrpc": "2.0", "id": format!("ping-{id}"), "method": "ping"});
+            for message in [log, ping, answer] {
+                writer
+                    .write_all(format!("{message}\n").as_bytes())
+                    .await
+                    .unwrap();
+            }
+        }
+    }
+
+    async fn connect() -> Client {
+        let (client, server) = duplex(4096);
+        tokio::spawn(echo_server(server));
+        let (reader, writer) = split(client);
+        Client::connect("echo".to_string(), BufReader::new(reader), writer)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn tools_and_resources() {
+        let client = connect().await;
+        let tools = client.list_tools().await.unwrap();
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0].name, "echo");
+        assert_eq!(
+            client
+                .call_tool("echo", json!({"text": "hi"}))
+                .await
+                .unwrap(),
+            "hi"
+        );
+        assert!(client.call_tool("missing", json!({})).await.is_err());
+        let resources = client.list_resources().await.unwrap();
+        assert_eq!(resources[0].uri, "echo://readme");
+        assert_eq!(
+            client.read_resource("echo://readme").await.unwrap(),
+            "Hello from echo"
+        );
+        assert!(matches!(
+            client.request("prompts/list", json!({})).await,
+            Err(Error::Rpc { code: -32601, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn offered_to_the_model() {
+        let client = Arc::new(connect().await);
+        let tools = client.list_tools().await.unwrap();
+        let mut registry = with_tools(Registry::default(), &client, tools);
+        registry = registry.with(ReadResource {
+            clients: vec![client],
+        });
+        let names: Vec<_> = registry
+            .definitions()
+            .into_iter()
+            .map(|definition| definition.name)
+            .collect();
+        assert_eq!(names, ["echo__echo", "read_resource"]);
+
+        let db = DatabaseConnection::Disconnected;
+        let context = ToolContext {
+            db: &db,
+            conversation_id: 1,
+        };
+        let call = |name: &str, arguments: &str| ToolCall {
+            id: "call_0".to_string(),
+            name: name.to_string(),
+            arguments: arguments.to_string(),
+        };
+        assert_eq!(
+            registry
+                .call(&context, &call("echo__echo", r#"{"text": "ping"}"#))
+                .await,
+            "ping"
+        );
+        assert_eq!(
+            registry.call(&context, &call("read_resource", "")).await,
+            "echo://readme (readme, from echo)"
+        );
+        assert_eq!(
+            registry
+                .call(
+                    &context,
+                    &call("read_resource", r#"{"uri": "echo://readme"}"#)
+                )
+                .await,
+            "Hello from echo"
+        );
+    }
+
+    #[tokio::test]
+    async fn clashing_names_are_left_out() {
+        assert_eq!(qualified("My files", "read.file"), "My_files__read_file");
+        let first = Arc::new(connect().await);
+        let second = Arc::new(connect().await);
+        let registry = with_tools(
+            Registry::builtin(),
+            &first,
+            first.list_tools().await.unwrap(),
+        );
+        let registry = with_tools(registry, &second, second.list_tools().await.unwrap());
+        let names: Vec<_> = registry
+            .definitions()
+            .into_iter()
+            .map(|definition| definition.name)
+            .filter(|name| name.starts_with("echo"))
+            .collect();
+        assert_eq!(names, ["echo__echo"]);
+    }
+
+    #[tokio::test]
+    async fn pings_are_answered() {
+        let client = connect().await;
+        // The ping sent with the initialize answer.
+        assert_eq!(client.call_tool("pongs", json!({})).await.unwrap(), "1");
+        assert_eq!(client.call_tool("pongs", json!({})).await.unwrap(), "2");
+    }
+
+    #[test]
+    fn backoff_grows() {
+        assert_eq!(backoff(1), BACKOFF);
+        assert_eq!(backoff(2), BACKOFF * 2);
+        assert_eq!(backoff(100), MAX_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn exited_servers_are_reported() {
+        let (client, server) = duplex(4096);
+        drop(server);
+        let (reader, writer) = split(client);
+        let result = Client::connect("gone".to_string(), BufReader::new(reader), writer).await;
+        assert!(matches!(result, Err(Error::Closed) | Err(Error::Io(_))));
+    }
+}
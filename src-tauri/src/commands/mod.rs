@@ -1,6 +1,26 @@
 pub mod api;
+pub mod approval;
+pub mod attachments;
+pub mod backend;
+pub mod branch;
+pub mod budget;
 pub mod conversation;
+pub mod documents;
+pub mod embeddings;
+pub mod generate;
+pub mod images;
+pub mod knowledge;
 pub mod load;
 pub mod local;
 pub mod login;
+pub mod mcp;
 pub mod models;
+pub mod ollama;
+pub mod providers;
+pub mod reasoning;
+pub mod retry;
+pub mod sse;
+pub mod summary;
+pub mod template;
+pub mod tgi;
+pub mod tools;
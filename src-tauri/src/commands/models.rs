@@ -1,4 +1,5 @@
 use crate::{
+    commands::ollama,
     entities::{model, user},
     State,
 };
@@ -8,9 +9,10 @@ use hf_hub::{
     api::tokio::{ApiBuilder, ApiError},
     Cache,
 };
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use sea_orm::{prelude::*, ActiveValue::Set};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::io::Write;
 
 #[derive(Debug, thiserror::Error)]
@@ -18,6 +20,9 @@ pub enum Error {
     #[error("No models")]
     NoModels,
 
+    #[error("Missing model {0}")]
+    MissingModel(u32),
+
     #[error("Missing token")]
     MissingToken,
 
@@ -197,6 +202,7 @@ pub async fn suggest_models(cache: &Cache, db: &DatabaseConnection) -> Result<()
         .take(10)
         .collect();
 
+    let mut profiles = vec![];
     for sugg in models {
         let user = user::ActiveModel {
             name: Set(sugg.name.clone()),
@@ -211,29 +217,115 @@ pub async fn suggest_models(cache: &Cache, db: &DatabaseConnection) -> Result<()
                 sugg.full_name
             )),
             parameters: Set(model::Parameters::default()),
+            kind: Set(model::ModelKind::HuggingFace),
             ..Default::default()
         };
         model.insert(db).await.unwrap();
-        let name = sugg.name.clone();
-        let cache = cache.clone();
-
-        let db_clone = db.clone();
-        tokio::spawn(async move {
-            let profile = create_profile(&name, &cache)
-                .await
-                .expect("Creating profile");
-            let user: user::Model = user::Entity::find_by_id(user.id)
-                .one(&db_clone)
-                .await
-                .expect("Valid query")
-                .expect("Model user for profile");
-            // Into ActiveModel
-            let mut user: user::ActiveModel = user.into();
-            // Update name attribute
-            user.profile = Set(profile);
-            user.update(&db_clone).await.expect("Profile update");
-        });
+        profiles.push((user.id, sugg.name.clone()));
+    }
+    spawn_profiles(cache, db, profiles);
+
+    Ok(())
+}
+
+/// Generates the avatars of `users` (id and name) in the background, one at
+/// a time so the hub does not rate limit them. Users whose avatar fails keep
+/// the default one.
+fn spawn_profiles(cache: &Cache, db: &DatabaseConnection, users: Vec<(u32, String)>) {
+    let cache = cache.clone();
+    let db = db.clone();
+    tokio::spawn(async move {
+        for (user_id, name) in users {
+            if let Err(err) = update_profile(&cache, &db, user_id, &name).await {
+                warn!("Keeping the default avatar of {name}: {err}");
+            }
+        }
+    });
+}
+
+async fn update_profile(
+    cache: &Cache,
+    db: &DatabaseConnection,
+    user_id: u32,
+    name: &str,
+) -> Result<(), Error> {
+    let profile = create_profile(name, cache).await?;
+    let Some(user) = user::Entity::find_by_id(user_id).one(db).await? else {
+        // Removed meanwhile.
+        return Ok(());
+    };
+    let mut user: user::ActiveModel = user.into();
+    user.profile = Set(profile);
+    user.update(db).await?;
+    Ok(())
+}
+
+/// Adds the models installed in the local Ollama, returns how many were new.
+#[tauri::command]
+pub async fn import_ollama_models(state: tauri::State<'_, State>) -> Result<usize, Error> {
+    let db = &state.db;
+    let base_url = ollama::base_url();
+    let names = ollama::installed_models(&base_url).await?;
+    info!("Got {} models from Ollama at {base_url}", names.len());
+    let known: HashSet<String> = model::Entity::find()
+        .filter(model::Column::Kind.eq(model::ModelKind::Ollama))
+        .filter(model::Column::Endpoint.eq(&base_url))
+        .all(db)
+        .await?
+        .into_iter()
+        .filter_map(|model| model.model_name)
+        .collect();
+    let mut profiles = vec![];
+    for model_name in names {
+        if known.contains(&model_name) {
+            continue;
+        }
+        let name = model_name
+            .trim_end_matches(":latest")
+            .rsplit('/')
+            .next()
+            .unwrap_or(&model_name)
+            .replace(['-', ':'], " ");
+        let user = user::ActiveModel {
+            name: Set(name.clone()),
+            profile: Set("public/default_profile.png".to_string()),
+            ..Default::default()
+        };
+        let user: user::Model = user.insert(db).await?;
+        let model = model::ActiveModel {
+            user_id: Set(user.id),
+            endpoint: Set(base_url.clone()),
+            parameters: Set(model::Parameters::default()),
+            kind: Set(model::ModelKind::Ollama),
+            model_name: Set(Some(model_name)),
+            ..Default::default()
+        };
+        model.insert(db).await?;
+        profiles.push((user.id, name));
     }
+    let imported = profiles.len();
+    // Avatars are generated on the hub, keep the default one when logged out.
+    if state.cache.token().is_some() {
+        spawn_profiles(&state.cache, db, profiles);
+    }
+    Ok(imported)
+}
 
+/// Sets the instructions sent first in every conversation with the model, an
+/// empty prompt removes them.
+#[tauri::command]
+pub async fn update_model_system_prompt(
+    state: tauri::State<'_, State>,
+    modelid: u32,
+    systemprompt: Option<String>,
+) -> Result<(), Error> {
+    let db = &state.db;
+    let model = model::Entity::find_by_id(modelid)
+        .one(db)
+        .await?
+        .ok_or(Error::MissingModel(modelid))?;
+    let mut model: model::ActiveModel = model.into();
+    model.system_prompt = Set(systemprompt.filter(|prompt| !prompt.trim().is_empty()));
+    model.update(db).await?;
     Ok(())
 }
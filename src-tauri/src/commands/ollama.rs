@@ -0,0 +1,470 @@
+//! Ollama's native API, `/api/chat` streams newline delimited json objects.
+
+use crate::commands::api::{
+    self, check_status, function_tools, with_ids, Error, FunctionTool, Message, Role, SseError,
+    Usage,
+};
+use crate::commands::backend::ChatBackend;
+use crate::commands::tools::ToolDefinition;
+use crate::entities::message::ToolCall;
+use crate::entities::model::Parameters;
+use ::reqwest::Response;
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Duration;
+
+/// Where `ollama serve` listens unless `OLLAMA_HOST` says otherwise.
+pub const DEFAULT_URL: &str = "http://localhost:11434";
+
+pub fn base_url() -> String {
+    match std::env::var("OLLAMA_HOST") {
+        Ok(host) if host.starts_with("http://") || host.starts_with("https://") => host,
+        Ok(host) if !host.is_empty() => format!("http://{host}"),
+        _ => DEFAULT_URL.to_string(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Options {
+    num_predict: usize,
+    temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_k: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repeat_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop: Option<Vec<String>>,
+}
+
+/// Ollama's calls carry no id and their arguments as an object.
+#[derive(Debug, Serialize, Deserialize)]
+struct OllamaToolCall {
+    function: Function,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Function {
+    name: String,
+    #[serde(default)]
+    arguments: Value,
+}
+
+#[derive(Debug, Serialize)]
+struct RequestMessage {
+    role: Role,
+    content: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<OllamaToolCall>,
+    /// Base64 pictures, for vision models.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    images: Vec<String>,
+}
+
+impl From<Message> for RequestMessage {
+    fn from(message: Message) -> Self {
+        let tool_calls = message
+            .tool_calls
+            .into_iter()
+            .map(|call| OllamaToolCall {
+                function: Function {
+                    arguments: serde_json::from_str(&call.arguments)
+                        .unwrap_or(Value::String(call.arguments)),
+                    name: call.name,
+                },
+            })
+            .collect();
+        Self {
+            role: message.role,
+            content: message.content,
+            tool_calls,
+            images: message.images.iter().map(|image| image.base64()).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ChatRequest {
+    model: String,
+    messages: Vec<RequestMessage>,
+    stream: bool,
+    options: Options,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<FunctionTool>,
+}
+
+impl ChatRequest {
+    fn new(
+        model: String,
+        messages: Vec<Message>,
+        tools: &[ToolDefinition],
+        parameters: &Parameters,
+    ) -> Self {
+        Self {
+            model,
+            messages: messages.into_iter().map(RequestMessage::from).collect(),
+            stream: true,
+            options: Options {
+                num_predict: parameters.max_new_tokens(),
+                temperature: parameters.temperature,
+                top_p: parameters.top_p(),
+                top_k: parameters.top_k(),
+                repeat_penalty: parameters.repetition_penalty(),
+                stop: parameters.stop(),
+            },
+            tools: function_tools(tools),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatMessage {
+    #[serde(default)]
+    content: String,
+    /// Sent whole, in a single line.
+    #[serde(default)]
+    tool_calls: Option<Vec<OllamaToolCall>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatLine {
+    message: Option<ChatMessage>,
+    #[serde(default)]
+    done: bool,
+    /// Only on the last line, the prompt count is left out when it was cached.
+    prompt_eval_count: Option<usize>,
+    eval_count: Option<usize>,
+}
+
+/// Splits the body into json lines, chunk boundaries can fall anywhere.
+#[derive(Debug, Default)]
+struct Decoder {
+    line: Vec<u8>,
+    done: bool,
+    usage: Option<Usage>,
+    tool_calls: Vec<ToolCall>,
+}
+
+impl Decoder {
+    fn feed(&mut self, mut bytes: &[u8]) -> Result<String, Error> {
+        let mut content = String::new();
+        while let Some(end) = bytes.iter().position(|&c| c == b'\n') {
+            self.line.extend_from_slice(&bytes[..end]);
+            bytes = &bytes[end + 1..];
+            let line = std::mem::take(&mut self.line);
+            if !self.done {
+                content.push_str(&self.process_line(&line)?);
+            }
+        }
+        self.line.extend_from_slice(bytes);
+        Ok(content)
+    }
+
+    fn finish(&mut self) -> Result<String, Error> {
+        let line = std::mem::take(&mut self.line);
+        self.process_line(&line)
+    }
+
+    fn process_line(&mut self, line: &[u8]) -> Result<String, Error> {
+        let line = line.trim_ascii();
+        if line.is_empty() {
+            return Ok(String::new());
+        }
+        // Errors are sent as `{"error": "..."}`, like on the other backends.
+        if let Ok(err) = serde_json::from_slice::<SseError>(line) {
+            error!("Ollama error {err}");
+            return Err(Error::SseError(err));
+        }
+        match serde_json::from_slice::<ChatLine>(line) {
+            Ok(chat) => {
+                self.done = chat.done;
+                if let Some(completion_tokens) = chat.eval_count {
+                    self.usage = Some(Usage {
+                        prompt_tokens: chat.prompt_eval_count.unwrap_or_default(),
+                        completion_tokens,
+                    });
+                }
+                let Some(message) = chat.message else {
+                    return Ok(String::new());
+                };
+                let tool_calls = message.tool_calls.unwrap_or_default();
+                self.tool_calls
+                    .extend(tool_calls.into_iter().map(|call| ToolCall {
+                        id: String::new(),
+                        name: call.function.name,
+                        arguments: call.function.arguments.to_string(),
+                    }));
+                Ok(message.content)
+            }
+            Err(_) => Err(Error::InvalidChunkError(
+                String::from_utf8_lossy(line).into(),
+            )),
+        }
+    }
+}
+
+pub struct Stream {
+    res: Response,
+    decoder: Decoder,
+}
+
+impl Stream {
+    pub fn usage(&self) -> Option<Usage> {
+        self.decoder.usage
+    }
+
+    pub fn tool_calls(&mut self) -> Vec<ToolCall> {
+        with_ids(std::mem::take(&mut self.decoder.tool_calls))
+    }
+
+    pub async fn next(&mut self) -> Result<Option<String>, Error> {
+        while !self.decoder.done {
+            let content = match self.res.chunk().await? {
+                Some(chunk) => self.decoder.feed(&chunk)?,
+                None => {
+                    let content = self.decoder.finish()?;
+                    self.decoder.done = true;
+                    content
+                }
+            };
+            if !content.is_empty() {
+                return Ok(Some(content));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// A model served by Ollama, `model` is its tag (`llama3.2:latest`).
+pub struct Ollama {
+    base_url: String,
+    model: String,
+}
+
+impl Ollama {
+    pub fn new(base_url: String, model: String) -> Self {
+        Self { base_url, model }
+    }
+}
+
+#[async_trait::async_trait]
+impl ChatBackend for Ollama {
+    async fn stream(
+        &self,
+        messages: Vec<Message>,
+        tools: &[ToolDefinition],
+        parameters: &Parameters,
+        _on_wait: &mut (dyn FnMut(&Error, Duration) + Send),
+    ) -> Result<api::Stream, Error> {
+        let url = format!("{}/api/chat", self.base_url.trim_end_matches('/'));
+        info!("Query {url} {} messages", messages.len());
+        let request = ChatRequest::new(self.model.clone(), messages, tools, parameters);
+        let res = ::reqwest::Client::new()
+            .post(url)
+            .json(&request)
+            .send()
+            .await?;
+        debug!("Ollama response received");
+        let res = check_status(res, &self.model).await?;
+        Ok(Stream {
+            res,
+            decoder: Decoder::default(),
+        }
+        .into())
+    }
+}
+
+#[derive(Deserialize)]
+struct Tags {
+    models: Vec<Tag>,
+}
+
+#[derive(Deserialize)]
+struct Tag {
+    name: String,
+}
+
+/// The models pulled into the Ollama at `base_url`.
+pub async fn installed_models(base_url: &str) -> Result<Vec<String>, ::reqwest::Error> {
+    let url = format!("{}/api/tags", base_url.trim_end_matches('/'));
+    let tags: Tags = ::reqwest::Client::new()
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(tags.models.into_iter().map(|tag| tag.name).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::api::InlineImage;
+
+    fn decode(chunks: &[&[u8]]) -> Result<String, Error> {
+        let mut decoder = Decoder::default();
+        let mut content = String::new();
+        for chunk in chunks {
+            content.push_str(&decoder.feed(chunk)?);
+        }
+        content.push_str(&decoder.finish()?);
+        Ok(content)
+    }
+
+    #[test]
+    fn decode_split_lines() {
+        let stream = concat!(
+            r#"{"model":"llama3.2","message":{"role":"assistant","content":"Hel"},"done":false}"#,
+            "\n",
+            r#"{"model":"llama3.2","message":{"role":"assistant","content":"lo 🤗"},"done":false}"#,
+            "\n",
+            r#"{"model":"llama3.2","message":{"role":"assistant","content":""},"done":true,"done_reason":"stop"}"#,
+            "\n",
+        )
+        .as_bytes();
+        for i in 0..=stream.len() {
+            let (a, b) = stream.split_at(i);
+            assert_eq!(decode(&[a, b]).unwrap(), "Hello 🤗", "split at {i}");
+        }
+    }
+
+    #[test]
+    fn decode_usage() {
+        let mut decoder = Decoder::default();
+        decoder
+            .feed(
+                concat!(
+                    r#"{"message":{"role":"assistant","content":"Hi"},"done":false}"#,
+                    "\n"
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+        assert_eq!(decoder.usage, None);
+        decoder
+            .feed(concat!(
+                r#"{"message":{"role":"assistant","content":""},"done":true,"prompt_eval_count":26,"eval_count":2}"#,
+                "\n"
+            ).as_bytes())
+            .unwrap();
+        assert_eq!(
+            decoder.usage,
+            Some(Usage {
+                prompt_tokens: 26,
+                completion_tokens: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn decode_tool_calls() {
+        let mut decoder = Decoder::default();
+        let content = decoder
+            .feed(concat!(
+                r#"{"message":{"role":"assistant","content":"","tool_calls":[{"function":{"name":"calculator","arguments":{"expression":"6*7"}}}]},"done":false}"#,
+                "\n",
+                r#"{"message":{"role":"assistant","content":""},"done":true}"#,
+                "\n"
+            ).as_bytes())
+            .unwrap();
+        assert_eq!(content, "");
+        assert_eq!(
+            with_ids(decoder.tool_calls),
+            vec![ToolCall {
+                id: "call_0".to_string(),
+                name: "calculator".to_string(),
+                arguments: r#"{"expression":"6*7"}"#.to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn tool_messages() {
+        let call = Message {
+            tool_calls: vec![ToolCall {
+                id: "call_0".to_string(),
+                name: "calculator".to_string(),
+                arguments: r#"{"expression":"6*7"}"#.to_string(),
+            }],
+            ..Message::new(Role::Assistant, String::new())
+        };
+        let result = Message {
+            tool_call_id: Some("call_0".to_string()),
+            ..Message::new(Role::Tool, "42".to_string())
+        };
+        let request = ChatRequest::new(
+            "llama3.2".to_string(),
+            vec![call, result],
+            &[],
+            &Parameters::default(),
+        );
+        assert_eq!(
+            serde_json::to_value(&request.messages).unwrap(),
+            serde_json::json!([
+                {
+                    "role": "assistant",
+                    "content": "",
+                    "tool_calls": [
+                        {"function": {"name": "calculator", "arguments": {"expression": "6*7"}}}
+                    ],
+                },
+                {"role": "tool", "content": "42"},
+            ])
+        );
+
+        let picture = Message {
+            images: vec![InlineImage {
+                mime_type: "image/png".to_string(),
+                data: b"png".to_vec(),
+            }],
+            ..Message::new(Role::User, "What is this?".to_string())
+        };
+        assert_eq!(
+            serde_json::to_value(RequestMessage::from(picture)).unwrap(),
+            serde_json::json!({"role": "user", "content": "What is this?", "images": ["cG5n"]})
+        );
+    }
+
+    #[test]
+    fn decode_error_line() {
+        let stream = concat!(
+            r#"{"message":{"role":"assistant","content":"Hi"},"done":false}"#,
+            "\n",
+            r#"{"error":"model requires more system memory"}"#,
+        );
+        match decode(&[stream.as_bytes()]) {
+            Err(Error::SseError(err)) => {
+                assert_eq!(err.to_string(), "model requires more system memory")
+            }
+            other => panic!("Expected an error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn request_options() {
+        let parameters = Parameters {
+            temperature: 0.5,
+            top_k: 40,
+            stop: vec!["<|eot_id|>".to_string()],
+            ..Default::default()
+        };
+        let request = ChatRequest::new("llama3.2".to_string(), vec![], &[], &parameters);
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "model": "llama3.2",
+                "messages": [],
+                "stream": true,
+                "options": {
+                    "num_predict": 1024,
+                    "temperature": 0.5,
+                    "top_k": 40,
+                    "stop": ["<|eot_id|>"],
+                }
+            })
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use crate::entities::model::{self, ModelKind};
+use crate::entities::provider::{self, Headers, ModelNames};
+use crate::entities::user;
+use crate::State;
+use ::reqwest::header::AUTHORIZATION;
+use log::info;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    TransactionTrait,
+};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Missing provider {0}")]
+    MissingProvider(u32),
+
+    #[error("{0} lists no models, add them by name")]
+    NoModels(String),
+
+    #[error("Providers cannot serve {0:?} models")]
+    UnsupportedKind(ModelKind),
+
+    #[error("Reqwest error {0}")]
+    ReqwestError(#[from] ::reqwest::Error),
+
+    #[error("Db error {0}")]
+    DbError(#[from] sea_orm::DbErr),
+}
+
+impl serde::Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
+
+#[derive(Deserialize)]
+struct ModelList {
+    data: Vec<ModelEntry>,
+}
+
+#[derive(Deserialize)]
+struct ModelEntry {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct TgiInfo {
+    model_id: String,
+}
+
+fn get(provider: &provider::Model, path: &str) -> ::reqwest::RequestBuilder {
+    let mut request = ::reqwest::Client::new().get(provider.url(path));
+    for (name, value) in &provider.headers.0 {
+        request = request.header(name, value);
+    }
+    if let Some(api_key) = &provider.api_key {
+        request = request.header(AUTHORIZATION, format!("Bearer {api_key}"));
+    }
+    request
+}
+
+/// Asks the provider which models it serves, `GET {base_url}/models` or
+/// `GET {base_url}/info` for TGI which serves a single one.
+pub async fn list_models(
+    provider: &provider::Model,
+    kind: ModelKind,
+) -> Result<Vec<String>, Error> {
+    if kind == ModelKind::Tgi {
+        let info: TgiInfo = get(provider, "info")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        return Ok(vec![info.model_id]);
+    }
+    let list: ModelList = get(provider, "models")
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(list.data.into_iter().map(|model| model.id).collect())
+}
+
+/// Creates a model row, and the user it speaks as, for every model of `provider`.
+async fn create_models(
+    db: &DatabaseConnection,
+    provider: &provider::Model,
+    kind: ModelKind,
+) -> Result<(), Error> {
+    let endpoint = match kind {
+        ModelKind::Tgi => provider.base_url.clone(),
+        _ => provider.url("chat/completions"),
+    };
+    for model_name in &provider.models.0 {
+        let name = model_name
+            .rsplit('/')
+            .next()
+            .unwrap_or(model_name)
+            .replace('-', " ");
+        let user = user::ActiveModel {
+            name: Set(name),
+            profile: Set("public/default_profile.png".to_string()),
+            ..Default::default()
+        };
+        let user = user.insert(db).await?;
+        let model = model::ActiveModel {
+            user_id: Set(user.id),
+            endpoint: Set(endpoint.clone()),
+            parameters: Set(model::Parameters::default()),
+            kind: Set(kind),
+            provider_id: Set(Some(provider.id)),
+            model_name: Set(Some(model_name.clone())),
+            ..Default::default()
+        };
+        model.insert(db).await?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_providers(state: tauri::State<'_, State>) -> Result<Vec<provider::Model>, Error> {
+    Ok(provider::Entity::find().all(&state.db).await?)
+}
+
+/// Registers an OpenAI compatible (or TGI) server, `models` are discovered
+/// when not given.
+#[tauri::command]
+pub async fn create_provider(
+    state: tauri::State<'_, State>,
+    name: String,
+    baseurl: String,
+    apikey: Option<String>,
+    headers: Option<BTreeMap<String, String>>,
+    models: Option<Vec<String>>,
+    kind: Option<ModelKind>,
+) -> Result<provider::Model, Error> {
+    let db = &state.db;
+    let kind = kind.unwrap_or(ModelKind::OpenAi);
+    if !matches!(kind, ModelKind::OpenAi | ModelKind::Tgi) {
+        return Err(Error::UnsupportedKind(kind));
+    }
+    let mut provider = provider::Model {
+        id: 0,
+        name,
+        base_url: baseurl,
+        api_key: apikey.filter(|key| !key.is_empty()),
+        headers: Headers(headers.unwrap_or_default()),
+        models: ModelNames(models.unwrap_or_default()),
+    };
+    if provider.models.0.is_empty() {
+        provider.models = ModelNames(list_models(&provider, kind).await?);
+        if provider.models.0.is_empty() {
+            return Err(Error::NoModels(provider.name));
+        }
+    }
+    info!(
+        "Adding provider {} with {} models",
+        provider.name,
+        provider.models.0.len()
+    );
+    let provider = provider::ActiveModel {
+        name: Set(provider.name),
+        base_url: Set(provider.base_url),
+        api_key: Set(provider.api_key),
+        headers: Set(provider.headers),
+        models: Set(provider.models),
+        ..Default::default()
+    };
+    let provider = provider.insert(db).await?;
+    create_models(db, &provider, kind).await?;
+    Ok(provider)
+}
+
+/// Removes the provider, its models and the users standing for them. The
+/// models' conversations and messages go with them by cascade. Nothing is
+/// removed if any step fails.
+#[tauri::command]
+pub async fn delete_provider(state: tauri::State<'_, State>, providerid: u32) -> Result<(), Error> {
+    let txn = state.db.begin().await?;
+    let db = &txn;
+    let provider = provider::Entity::find_by_id(providerid)
+        .one(db)
+        .await?
+        .ok_or(Error::MissingProvider(providerid))?;
+    let models = model::Entity::find()
+        .filter(model::Column::ProviderId.eq(provider.id))
+        .all(db)
+        .await?;
+    model::Entity::delete_many()
+        .filter(model::Column::ProviderId.eq(provider.id))
+        .exec(db)
+        .await?;
+    user::Entity::delete_many()
+        .filter(user::Column::Id.is_in(models.into_iter().map(|model| model.user_id)))
+        .exec(db)
+        .await?;
+    provider::Entity::delete_by_id(provider.id).exec(db).await?;
+    txn.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,198 @@
+//! Reasoning models think before they answer. Their thoughts come as a
+//! separate `reasoning_content` delta or inline between `<think>` tags, either
+//! way they are kept apart from the answer and never sent back to the model.
+
+const OPEN: &str = "<think>";
+const CLOSE: &str = "</think>";
+
+/// A piece of the reply as it streams in.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Piece {
+    pub content: String,
+    pub reasoning: String,
+}
+
+impl Piece {
+    pub fn content(content: String) -> Self {
+        Self {
+            content,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.content.is_empty() && self.reasoning.is_empty()
+    }
+}
+
+/// Where the reply is at.
+#[derive(Debug, Default, PartialEq)]
+enum Phase {
+    /// Nothing but blanks yet, the reply may still open with `<think>`.
+    #[default]
+    Start,
+    Thinking,
+    Answer,
+}
+
+/// Moves the thoughts of a reply opening with a `<think>` tag to the
+/// reasoning. The tags are only looked for there, an answer may well mention
+/// them. A reply may also open with `</think>` alone when the chat template
+/// opened the thoughts itself and the model skipped them. A tag may be cut
+/// across deltas, the start of one is held back until the next delta tells.
+#[derive(Debug, Default)]
+pub struct Thoughts {
+    phase: Phase,
+    /// The answer usually starts with blank lines after the thoughts.
+    after_thoughts: bool,
+    pending: String,
+}
+
+impl Thoughts {
+    pub fn split(&mut self, piece: Piece) -> Piece {
+        let mut split = Piece {
+            content: String::new(),
+            reasoning: piece.reasoning,
+        };
+        self.pending.push_str(&piece.content);
+        if self.phase == Phase::Start {
+            let start = self.pending.trim_start();
+            if let Some(thoughts) = start.strip_prefix(OPEN) {
+                self.pending = thoughts.to_string();
+                self.phase = Phase::Thinking;
+            } else if let Some(answer) = start.strip_prefix(CLOSE) {
+                self.pending = answer.to_string();
+                self.phase = Phase::Answer;
+                self.after_thoughts = true;
+            } else if OPEN.starts_with(start) || CLOSE.starts_with(start) {
+                return split;
+            } else {
+                self.phase = Phase::Answer;
+            }
+        }
+        if self.phase == Phase::Thinking {
+            let Some(end) = self.pending.find(CLOSE) else {
+                let held = (1..CLOSE.len())
+                    .rev()
+                    .find(|len| self.pending.ends_with(&CLOSE[..*len]))
+                    .unwrap_or(0);
+                let thoughts: String = self.pending.drain(..self.pending.len() - held).collect();
+                split.reasoning.push_str(&thoughts);
+                return split;
+            };
+            split.reasoning.push_str(&self.pending[..end]);
+            self.pending.drain(..end + CLOSE.len());
+            self.phase = Phase::Answer;
+            self.after_thoughts = true;
+        }
+        let text = std::mem::take(&mut self.pending);
+        self.answer(&mut split, &text);
+        split
+    }
+
+    /// What was held back, once the stream is over.
+    pub fn finish(&mut self) -> Piece {
+        let mut split = Piece::default();
+        let text = std::mem::take(&mut self.pending);
+        if self.phase == Phase::Thinking {
+            split.reasoning = text;
+        } else {
+            self.answer(&mut split, &text);
+        }
+        split
+    }
+
+    fn answer(&mut self, piece: &mut Piece, text: &str) {
+        let text = if self.after_thoughts {
+            text.trim_start()
+        } else {
+            text
+        };
+        if !text.is_empty() {
+            self.after_thoughts = false;
+            piece.content.push_str(text);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(deltas: &[&str]) -> Piece {
+        let mut thoughts = Thoughts::default();
+        let mut all = Piece::default();
+        for delta in deltas {
+            let piece = thoughts.split(Piece::content(delta.to_string()));
+            all.content.push_str(&piece.content);
+            all.reasoning.push_str(&piece.reasoning);
+        }
+        let rest = thoughts.finish();
+        all.content.push_str(&rest.content);
+        all.reasoning.push_str(&rest.reasoning);
+        all
+    }
+
+    #[test]
+    fn think_tags() {
+        let reply = "<think>The user greets me.</think>\n\nHello!";
+        let expected = Piece {
+            content: "Hello!".to_string(),
+            reasoning: "The user greets me.".to_string(),
+        };
+        assert_eq!(split(&[reply]), expected);
+        // Tags cut anywhere.
+        for i in 1..reply.len() {
+            let (a, b) = reply.split_at(i);
+            assert_eq!(split(&[a, b]), expected, "split at {i}");
+        }
+        let tokens: Vec<String> = reply.chars().map(String::from).collect();
+        let tokens: Vec<&str> = tokens.iter().map(String::as_str).collect();
+        assert_eq!(split(&tokens), expected);
+    }
+
+    #[test]
+    fn tags_only_open_the_reply() {
+        let reply = split(&["\n<think>Short.</think>", "Hi"]);
+        assert_eq!(reply.reasoning, "Short.");
+        assert_eq!(reply.content, "Hi");
+        // Mentioned in the answer, not thoughts.
+        let answer = "Reasoning models write <think>, then </think> and the answer.";
+        for i in 1..answer.len() {
+            let (a, b) = answer.split_at(i);
+            assert_eq!(split(&[a, b]).content, answer, "split at {i}");
+        }
+    }
+
+    #[test]
+    fn thoughts_opened_by_the_template() {
+        let reply = "\n</think>\n\nHello!";
+        for i in 1..reply.len() {
+            let (a, b) = reply.split_at(i);
+            assert_eq!(
+                split(&[a, b]),
+                Piece::content("Hello!".to_string()),
+                "split at {i}"
+            );
+        }
+        // Only at the start.
+        assert_eq!(split(&["Hi </think>"]).content, "Hi </think>");
+    }
+
+    #[test]
+    fn plain_answers() {
+        assert_eq!(
+            split(&["a < b", " and <b>bold</b>"]).content,
+            "a < b and <b>bold</b>"
+        );
+        // Not a tag after all, held back then given out.
+        assert_eq!(split(&["1 <", "thin"]).content, "1 <thin");
+        // Separate reasoning deltas pass through.
+        let mut thoughts = Thoughts::default();
+        let piece = Piece {
+            content: String::new(),
+            reasoning: "Hmm".to_string(),
+        };
+        assert_eq!(thoughts.split(piece.clone()), piece);
+    }
+}
@@ -0,0 +1,118 @@
+use crate::commands::api::Error;
+use log::warn;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// Retries requests to hosted endpoints which are cold-loading the model,
+/// rate limiting us, or temporarily unavailable.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Overall time we are willing to wait before giving up.
+    pub max_wait: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 10,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            max_wait: Duration::from_secs(300),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait after the failed `attempt` (0 based), `None` if the
+    /// error is not worth retrying or we have tried enough.
+    pub fn delay(&self, attempt: u32, err: &Error) -> Option<Duration> {
+        if attempt + 1 >= self.max_attempts {
+            return None;
+        }
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        let delay = match err {
+            Error::RateLimited { retry_after } => retry_after.unwrap_or(backoff),
+            Error::ModelLoading { estimated_time } => estimated_time.unwrap_or(backoff),
+            Error::Http {
+                status: 502..=504, ..
+            } => backoff,
+            Error::Reqwest(err) if err.is_connect() || err.is_timeout() => backoff,
+            _ => return None,
+        };
+        Some(delay.min(self.max_delay))
+    }
+
+    /// Runs `attempt` until it succeeds or fails for good, `on_wait` is called
+    /// before sleeping. Dropping the future cancels the retries.
+    pub async fn run<T, F, Fut>(
+        &self,
+        mut attempt: F,
+        mut on_wait: impl FnMut(&Error, Duration),
+    ) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let start = Instant::now();
+        let mut n = 0;
+        loop {
+            let err = match attempt().await {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            let Some(delay) = self.delay(n, &err) else {
+                return Err(err);
+            };
+            if start.elapsed() + delay > self.max_wait {
+                return Err(err);
+            }
+            warn!("Attempt {} failed ({err}), retrying in {delay:?}", n + 1);
+            on_wait(&err, delay);
+            tokio::time::sleep(delay).await;
+            n += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays() {
+        let policy = RetryPolicy::default();
+        let unavailable = Error::Http {
+            status: 503,
+            message: String::new(),
+        };
+        assert_eq!(policy.delay(0, &unavailable), Some(Duration::from_secs(1)));
+        assert_eq!(policy.delay(3, &unavailable), Some(Duration::from_secs(8)));
+        assert_eq!(policy.delay(8, &unavailable), Some(Duration::from_secs(60)));
+        assert_eq!(policy.delay(9, &unavailable), None);
+
+        let loading = Error::ModelLoading {
+            estimated_time: Some(Duration::from_secs(20)),
+        };
+        assert_eq!(policy.delay(0, &loading), Some(Duration::from_secs(20)));
+        let loading = Error::ModelLoading {
+            estimated_time: Some(Duration::from_secs(600)),
+        };
+        assert_eq!(policy.delay(0, &loading), Some(Duration::from_secs(60)));
+
+        let limited = Error::RateLimited {
+            retry_after: Some(Duration::from_secs(5)),
+        };
+        assert_eq!(policy.delay(2, &limited), Some(Duration::from_secs(5)));
+        let limited = Error::RateLimited { retry_after: None };
+        assert_eq!(policy.delay(2, &limited), Some(Duration::from_secs(4)));
+
+        assert_eq!(policy.delay(0, &Error::InvalidToken), None);
+        assert_eq!(policy.delay(0, &Error::Validation("bad".to_string())), None);
+    }
+}
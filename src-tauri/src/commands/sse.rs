@@ -0,0 +1,263 @@
+//! Server-Sent Events decoder, following
+//! https://html.spec.whatwg.org/multipage/server-sent-events.html#event-stream-interpretation
+//!
+//! Bytes are fed as they come off the wire, chunk boundaries can fall anywhere
+//! (even between the `\r` and `\n` of a line ending).
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// `message` unless the server set an `event:` field.
+    pub event: String,
+    pub data: String,
+    /// Last event id seen on the stream, including this event.
+    pub id: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Decoder {
+    /// Bytes of the line currently being received.
+    line: Vec<u8>,
+    /// The previous chunk ended with `\r`, a leading `\n` belongs to that line ending.
+    pending_cr: bool,
+    started: bool,
+    event: Option<String>,
+    data: String,
+    has_data: bool,
+    last_id: Option<String>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds raw bytes, returns the events completed by them.
+    pub fn feed(&mut self, mut bytes: &[u8]) -> Vec<Event> {
+        let mut events = vec![];
+        if self.pending_cr {
+            self.pending_cr = false;
+            bytes = bytes.strip_prefix(b"\n").unwrap_or(bytes);
+        }
+        while let Some(end) = bytes.iter().position(|&c| c == b'\r' || c == b'\n') {
+            self.line.extend_from_slice(&bytes[..end]);
+            let line = self.take_line();
+            if let Some(event) = self.process_line(&line) {
+                events.push(event);
+            }
+            let crlf = bytes[end] == b'\r';
+            bytes = &bytes[end + 1..];
+            if crlf {
+                if bytes.is_empty() {
+                    self.pending_cr = true;
+                } else {
+                    bytes = bytes.strip_prefix(b"\n").unwrap_or(bytes);
+                }
+            }
+        }
+        self.line.extend_from_slice(bytes);
+        events
+    }
+
+    /// Ends the stream, an event that was not terminated by a blank line is
+    /// discarded. Returns the unterminated line if any, servers that don't
+    /// speak SSE send their errors this way.
+    pub fn finish(&mut self) -> Option<Vec<u8>> {
+        self.event = None;
+        self.data.clear();
+        self.has_data = false;
+        let line = self.take_line();
+        (!line.is_empty()).then_some(line)
+    }
+
+    fn take_line(&mut self) -> Vec<u8> {
+        let mut line = std::mem::take(&mut self.line);
+        if !self.started {
+            // The stream may start with a byte order mark.
+            self.started = true;
+            if line.starts_with(b"\xEF\xBB\xBF") {
+                line.drain(..3);
+            }
+        }
+        line
+    }
+
+    fn process_line(&mut self, line: &[u8]) -> Option<Event> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(b":") {
+            // Comment, usually a keep-alive.
+            return None;
+        }
+        let line = String::from_utf8_lossy(line);
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (&line[..], ""),
+        };
+        match field {
+            "event" => self.event = Some(value.to_string()),
+            "data" => {
+                if self.has_data {
+                    self.data.push('\n');
+                }
+                self.data.push_str(value);
+                self.has_data = true;
+            }
+            "id" => {
+                if !value.contains('\0') {
+                    self.last_id = Some(value.to_string());
+                }
+            }
+            // We never reconnect, so the reconnection time is of no use.
+            "retry" => {}
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<Event> {
+        let event = self.event.take();
+        if !self.has_data {
+            return None;
+        }
+        self.has_data = false;
+        Some(Event {
+            event: event
+                .filter(|event| !event.is_empty())
+                .unwrap_or_else(|| "message".to_string()),
+            data: std::mem::take(&mut self.data),
+            id: self.last_id.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(data: &str) -> Event {
+        Event {
+            event: "message".to_string(),
+            data: data.to_string(),
+            id: None,
+        }
+    }
+
+    fn decode_all(chunks: &[&[u8]]) -> Vec<Event> {
+        let mut decoder = Decoder::new();
+        chunks
+            .iter()
+            .flat_map(|chunk| decoder.feed(chunk))
+            .collect()
+    }
+
+    /// Every possible split point must yield the same events.
+    fn assert_splits(stream: &[u8], expected: &[Event]) {
+        assert_eq!(decode_all(&[stream]), expected);
+        for i in 0..=stream.len() {
+            let (a, b) = stream.split_at(i);
+            assert_eq!(decode_all(&[a, b]), expected, "split at {i}");
+        }
+        let bytes: Vec<&[u8]> = stream.chunks(1).collect();
+        assert_eq!(decode_all(&bytes), expected, "byte by byte");
+    }
+
+    #[test]
+    fn single_event() {
+        assert_splits(b"data: hello\n\n", &[message("hello")]);
+    }
+
+    #[test]
+    fn openai_chunks() {
+        let stream = b"data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n\
+            data: {\"choices\":[{\"delta\":{\"content\":\"lo\"}}]}\n\n\
+            data: [DONE]\n\n";
+        assert_splits(
+            stream,
+            &[
+                message(r#"{"choices":[{"delta":{"content":"Hel"}}]}"#),
+                message(r#"{"choices":[{"delta":{"content":"lo"}}]}"#),
+                message("[DONE]"),
+            ],
+        );
+    }
+
+    #[test]
+    fn line_endings() {
+        let expected = [message("a"), message("b")];
+        assert_splits(b"data: a\r\n\r\ndata: b\r\n\r\n", &expected);
+        assert_splits(b"data: a\r\rdata: b\r\r", &expected);
+        assert_splits(b"data: a\n\r\ndata: b\r\n\n", &expected);
+    }
+
+    #[test]
+    fn multiline_data() {
+        assert_splits(
+            b"data: first\ndata:second\ndata\ndata:  third\n\n",
+            &[message("first\nsecond\n\n third")],
+        );
+    }
+
+    #[test]
+    fn comments_and_unknown_fields() {
+        assert_splits(
+            b": keep-alive\n\n:another\nfoo: bar\ndata: a\n: inside\n\n",
+            &[message("a")],
+        );
+    }
+
+    #[test]
+    fn event_id_and_retry() {
+        let stream =
+            b"retry: 1000\nevent: error\nid: 7\ndata: {\"error\":\"boom\"}\n\ndata: next\n\n";
+        assert_splits(
+            stream,
+            &[
+                Event {
+                    event: "error".to_string(),
+                    data: r#"{"error":"boom"}"#.to_string(),
+                    id: Some("7".to_string()),
+                },
+                Event {
+                    event: "message".to_string(),
+                    data: "next".to_string(),
+                    id: Some("7".to_string()),
+                },
+            ],
+        );
+    }
+
+    #[test]
+    fn no_data_no_event() {
+        // The event type is reset even if nothing was dispatched.
+        assert_splits(b"event: ping\n\ndata: a\n\n", &[message("a")]);
+        assert_splits(b"id: 1\n\n\n\n", &[]);
+    }
+
+    #[test]
+    fn empty_data_is_dispatched() {
+        assert_splits(b"data\n\ndata:\n\n", &[message(""), message("")]);
+    }
+
+    #[test]
+    fn byte_order_mark() {
+        assert_splits(b"\xEF\xBB\xBFdata: a\n\n", &[message("a")]);
+    }
+
+    #[test]
+    fn utf8_split_across_chunks() {
+        assert_splits("data: héllo 🤗\n\n".as_bytes(), &[message("héllo 🤗")]);
+    }
+
+    #[test]
+    fn unterminated_event_is_discarded() {
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.feed(b"data: a\n"), vec![]);
+        assert_eq!(decoder.finish(), None);
+
+        let mut decoder = Decoder::new();
+        let error = br#"{"error": "Authorization header is correct, but the token seems invalid"}"#;
+        assert_eq!(decoder.feed(error), vec![]);
+        assert_eq!(decoder.finish(), Some(error.to_vec()));
+    }
+}
@@ -0,0 +1,222 @@
+//! Rolling summaries, an alternative to dropping old turns: once the prompt
+//! outgrows `summarize_after` tokens the older turns are summarized and the
+//! summary stands in for them, see `Message::from_db`.
+
+use crate::commands::api::{Error, Message, Role, Turn};
+use crate::commands::backend::{load_backend, ChatBackend};
+use crate::commands::branch;
+use crate::commands::budget::{fit_to_budget, TokenCounter};
+use crate::entities::conversation;
+use crate::entities::message::{self, MessageKind};
+use crate::entities::model::{self, Parameters};
+use crate::State;
+use chrono::Utc;
+use log::{info, warn};
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, EntityTrait};
+use std::time::Duration;
+
+const INSTRUCTION: &str = "Summarize the conversation below so it can be continued \
+without it. Keep names, facts, decisions and open questions, leave out pleasantries. \
+Answer with the summary only.";
+
+/// How many leading turns to summarize, zero while the conversation fits in
+/// `threshold` tokens. Enough are taken for the rest to fit in half of it, so
+/// the next summary is not due right away.
+pub fn turns_to_summarize(
+    messages: &[Message],
+    threshold: usize,
+    count: impl Fn(&Message) -> usize,
+) -> usize {
+    let total: usize = messages.iter().map(&count).sum();
+    if total <= threshold {
+        return 0;
+    }
+    fit_to_budget(messages, 0, threshold / 2, count).len()
+}
+
+fn transcript(turns: &[Turn]) -> String {
+    turns
+        .iter()
+        .map(|turn| {
+            let speaker = match turn.message.role {
+                Role::System => "System",
+                Role::User => "User",
+                Role::Assistant => "Assistant",
+                Role::Tool => "Tool",
+            };
+            format!("{speaker}: {}", turn.message.content)
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Asks `backend` for a summary of `turns`, a previous summary among them is
+/// folded into the new one.
+pub async fn summarize(
+    backend: &dyn ChatBackend,
+    turns: &[Turn],
+    parameters: &Parameters,
+) -> Result<String, Error> {
+    let messages = vec![Message::new(
+        Role::User,
+        format!("{INSTRUCTION}\n\n{}", transcript(turns)),
+    )];
+    let mut on_wait = |_: &Error, _: Duration| {};
+    let mut stream = backend
+        .stream(messages, &[], parameters, &mut on_wait)
+        .await?;
+    let mut summary = String::new();
+    while let Some(delta) = stream.next().await? {
+        summary.push_str(&delta.content);
+    }
+    Ok(summary.trim().to_string())
+}
+
+/// The backend writing summaries for `model`'s conversations, with the
+/// parameters to use: those of `summary_model_id` when one is set.
+async fn summary_backend(
+    state: &State,
+    model: &model::Model,
+    parameters: &Parameters,
+) -> Result<(Box<dyn ChatBackend>, Parameters), Error> {
+    let db = &state.db;
+    if let Some(model_id) = parameters.summary_model_id() {
+        match model::Entity::find_by_id(model_id).one(db).await? {
+            Some(summary_model) => {
+                let backend = load_backend(db, &state.cache, &summary_model).await?;
+                return Ok((backend, summary_model.parameters));
+            }
+            None => warn!("Summary model {model_id} is missing, using {}", model.id),
+        }
+    }
+    let backend = load_backend(db, &state.cache, model).await?;
+    Ok((backend, parameters.clone()))
+}
+
+/// The turns of `messages` to summarize, and the last message they cover,
+/// once the conversation outgrew `parameters.summarize_after`.
+pub fn due(
+    messages: &[message::Model],
+    parameters: &Parameters,
+    counter: &TokenCounter,
+) -> Option<(Vec<Turn>, u32)> {
+    let threshold = parameters.summarize_after()?;
+    let mut turns = Message::from_db(messages.to_vec(), None);
+    let prompt: Vec<Message> = turns.iter().map(|turn| turn.message.clone()).collect();
+    let n = turns_to_summarize(&prompt, threshold, |message| counter.count(message));
+    turns.truncate(n);
+    // Nothing new when only the previous summary would be summarized.
+    let until = turns.iter().flat_map(|turn| &turn.ids).max().copied()?;
+    Some((turns, until))
+}
+
+/// Summarizes `turns` into a new summary message of the conversation.
+pub async fn create(
+    state: &State,
+    conversation_id: u32,
+    model: &model::Model,
+    parameters: &Parameters,
+    turns: &[Turn],
+    until: u32,
+) -> Result<message::Model, Error> {
+    info!(
+        "Summarizing {} turns of conversation {conversation_id}",
+        turns.len()
+    );
+    let (backend, parameters) = summary_backend(state, model, parameters).await?;
+    let content = summarize(backend.as_ref(), turns, &parameters).await?;
+    let now = Utc::now();
+    let summary = message::ActiveModel {
+        conversation_id: Set(conversation_id),
+        user_id: Set(model.user_id),
+        content: Set(content),
+        role: Set(Role::Assistant),
+        created_at: Set(now),
+        updated_at: Set(now),
+        kind: Set(MessageKind::Summary),
+        summary_until: Set(Some(until)),
+        parent_id: Set(Some(until)),
+        ..Default::default()
+    };
+    Ok(summary.insert(&state.db).await?)
+}
+
+/// Writes the summary `messageid` again from the messages it covers.
+#[tauri::command]
+pub async fn regenerate_summary(
+    state: tauri::State<'_, State>,
+    messageid: u32,
+) -> Result<message::Model, Error> {
+    let db = &state.db;
+    let summary = message::Entity::find_by_id(messageid)
+        .one(db)
+        .await?
+        .filter(|message| message.kind == MessageKind::Summary)
+        .ok_or(Error::MissingSummary(messageid))?;
+    let conversation_id = summary.conversation_id;
+    let (conversation, model) = conversation::Entity::find_by_id(conversation_id)
+        .find_also_related(model::Entity)
+        .one(db)
+        .await?
+        .ok_or(Error::MissingConversation(conversation_id))?;
+    let model = model.expect("Associated model");
+    let parameters = model
+        .parameters
+        .with_overrides(&conversation.parameters.unwrap_or_default());
+    let until = summary.summary_until.unwrap_or(summary.id);
+    // The summary before this one is what it was built upon.
+    let mut messages = branch::branch(&branch::load(db, conversation_id).await?, Some(until));
+    messages.retain(|message| message.id < summary.id);
+    let turns = Message::from_db(messages, None);
+    let (backend, parameters) = summary_backend(&state, &model, &parameters).await?;
+    let content = summarize(backend.as_ref(), &turns, &parameters).await?;
+    let mut summary: message::ActiveModel = summary.into();
+    summary.content = Set(content);
+    summary.updated_at = Set(Utc::now());
+    Ok(summary.update(db).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(sizes: &[usize]) -> Vec<Message> {
+        sizes
+            .iter()
+            .map(|&size| Message::new(Role::User, "x".repeat(size)))
+            .collect()
+    }
+
+    fn summarized(sizes: &[usize], threshold: usize) -> usize {
+        turns_to_summarize(&messages(sizes), threshold, |m| m.content.len())
+    }
+
+    #[test]
+    fn below_threshold() {
+        assert_eq!(summarized(&[10, 10, 10], 30), 0);
+        assert_eq!(summarized(&[], 30), 0);
+    }
+
+    #[test]
+    fn keeps_half_the_threshold() {
+        assert_eq!(summarized(&[10, 10, 10, 10], 30), 3);
+        assert_eq!(summarized(&[10, 10, 10, 10], 39), 3);
+        assert_eq!(summarized(&[10, 10, 10, 10], 20), 3);
+        assert_eq!(summarized(&[10, 10, 5, 5], 29), 2);
+    }
+
+    #[test]
+    fn transcript_names_speakers() {
+        let turns = vec![
+            Turn {
+                ids: vec![1],
+                message: Message::new(Role::User, "Hi".to_string()),
+            },
+            Turn {
+                ids: vec![2],
+                message: Message::new(Role::Assistant, "Hello".to_string()),
+            },
+        ];
+        assert_eq!(transcript(&turns), "User: Hi\n\nAssistant: Hello");
+    }
+}
@@ -0,0 +1,191 @@
+//! Renders a conversation into a prompt with the model's jinja `chat_template`,
+//! for servers which only complete raw text.
+
+use crate::commands::api::Message;
+use hf_hub::api::tokio::{ApiBuilder, ApiError};
+use hf_hub::Cache;
+use minijinja::{context, Environment, ErrorKind};
+use serde::Deserialize;
+use std::path::Path;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0} has no chat template")]
+    MissingTemplate(String),
+
+    #[error("Could not fetch the tokenizer config: {0}")]
+    Hub(#[from] ApiError),
+
+    #[error("Invalid tokenizer config: {0}")]
+    Config(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error("Chat template error: {0}")]
+    Render(#[from] minijinja::Error),
+}
+
+/// Tokens are either plain strings or serialized `AddedToken`s.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Token {
+    Text(String),
+    Added { content: String },
+}
+
+impl Token {
+    fn content(self) -> String {
+        match self {
+            Token::Text(content) | Token::Added { content } => content,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct NamedTemplate {
+    name: String,
+    template: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Template {
+    Single(String),
+    Named(Vec<NamedTemplate>),
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenizerConfig {
+    chat_template: Option<Template>,
+    bos_token: Option<Token>,
+    eos_token: Option<Token>,
+}
+
+#[derive(Debug)]
+pub struct ChatTemplate {
+    template: String,
+    bos_token: String,
+    eos_token: String,
+}
+
+impl ChatTemplate {
+    /// Parses a `tokenizer_config.json`, `model_id` is only used for errors.
+    pub fn from_config(model_id: &str, config: &str) -> Result<Self, Error> {
+        let config: TokenizerConfig = serde_json::from_str(config)?;
+        let template = match config.chat_template {
+            Some(Template::Single(template)) => template,
+            Some(Template::Named(templates)) => {
+                templates
+                    .into_iter()
+                    .find(|template| template.name == "default")
+                    .ok_or_else(|| Error::MissingTemplate(model_id.to_string()))?
+                    .template
+            }
+            None => return Err(Error::MissingTemplate(model_id.to_string())),
+        };
+        Ok(Self {
+            template,
+            bos_token: config.bos_token.map(Token::content).unwrap_or_default(),
+            eos_token: config.eos_token.map(Token::content).unwrap_or_default(),
+        })
+    }
+
+    pub fn from_file(model_id: &str, path: &Path) -> Result<Self, Error> {
+        Self::from_config(model_id, &std::fs::read_to_string(path)?)
+    }
+
+    /// Fetches `tokenizer_config.json` of `model_id` from the hub, files are
+    /// kept in the cache so this only hits the network once.
+    pub async fn fetch(cache: &Cache, model_id: &str) -> Result<Self, Error> {
+        let api = ApiBuilder::new()
+            .with_cache_dir(cache.path().clone())
+            .with_token(cache.token())
+            .build()?;
+        let path = api
+            .model(model_id.to_string())
+            .get("tokenizer_config.json")
+            .await?;
+        Self::from_file(model_id, &path)
+    }
+
+    /// The prompt for `messages`, ending where the assistant's reply starts.
+    pub fn render(&self, messages: &[Message]) -> Result<String, Error> {
+        let mut env = Environment::new();
+        // Templates are written for python's jinja, `.strip()` and friends.
+        env.set_unknown_method_callback(minijinja_contrib::pycompat::unknown_method_callback);
+        env.add_function(
+            "raise_exception",
+            |message: String| -> Result<String, minijinja::Error> {
+                Err(minijinja::Error::new(ErrorKind::InvalidOperation, message))
+            },
+        );
+        env.add_function("strftime_now", |format: String| {
+            chrono::Local::now().format(&format).to_string()
+        });
+        env.add_template("chat", &self.template)?;
+        let prompt = env.get_template("chat")?.render(context! {
+            messages => messages,
+            add_generation_prompt => true,
+            bos_token => &self.bos_token,
+            eos_token => &self.eos_token,
+        })?;
+        Ok(prompt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::api::Role;
+
+    fn conversation() -> Vec<Message> {
+        vec![
+            Message::new(Role::User, " Hi ".to_string()),
+            Message::new(Role::Assistant, "Hello!".to_string()),
+            Message::new(Role::User, "How are you?".to_string()),
+        ]
+    }
+
+    #[test]
+    fn chatml() {
+        let config = r#"{
+            "bos_token": {"content": "<s>", "lstrip": false},
+            "eos_token": "<|im_end|>",
+            "chat_template": "{{ bos_token }}{% for message in messages %}<|im_start|>{{ message['role'] }}\n{{ message['content'] | trim }}<|im_end|>\n{% endfor %}{% if add_generation_prompt %}<|im_start|>assistant\n{% endif %}"
+        }"#;
+        let template = ChatTemplate::from_config("chatml", config).unwrap();
+        assert_eq!(
+            template.render(&conversation()).unwrap(),
+            "<s><|im_start|>user\nHi<|im_end|>\n<|im_start|>assistant\nHello!<|im_end|>\n<|im_start|>user\nHow are you?<|im_end|>\n<|im_start|>assistant\n"
+        );
+    }
+
+    #[test]
+    fn python_methods_and_exceptions() {
+        let config = r#"{
+            "chat_template": [
+                {"name": "tool_use", "template": "unused"},
+                {"name": "default", "template": "{% for message in messages %}{% if (message['role'] == 'user') != (loop.index0 % 2 == 0) %}{{ raise_exception('Conversation roles must alternate') }}{% endif %}[{{ message.role.upper() }}] {{ message.content.strip() }}{{ eos_token }}{% endfor %}"}
+            ],
+            "eos_token": "</s>"
+        }"#;
+        let template = ChatTemplate::from_config("alternating", config).unwrap();
+        assert_eq!(
+            template.render(&conversation()).unwrap(),
+            "[USER] Hi</s>[ASSISTANT] Hello!</s>[USER] How are you?</s>"
+        );
+        let err = template.render(&conversation()[1..]).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Conversation roles must alternate"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn missing_template() {
+        let err = ChatTemplate::from_config("gpt2", r#"{"eos_token": "<|endoftext|>"}"#);
+        assert!(matches!(err, Err(Error::MissingTemplate(_))));
+    }
+}
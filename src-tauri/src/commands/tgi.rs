@@ -0,0 +1,235 @@
+//! Text Generation Inference's native `/generate_stream`, for deployments
+//! without the OpenAI compatible route. The conversation is rendered with the
+//! model's chat template and completed as raw text.
+
+use crate::commands::api::{self, Endpoint, Error, Message, SseError};
+use crate::commands::backend::ChatBackend;
+use crate::commands::retry::RetryPolicy;
+use crate::commands::sse;
+use crate::commands::template::ChatTemplate;
+use crate::commands::tools::ToolDefinition;
+use crate::entities::model::Parameters;
+use ::reqwest::Response;
+use hf_hub::Cache;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Debug, Serialize)]
+struct GenerateParameters {
+    max_new_tokens: usize,
+    return_full_text: bool,
+    details: bool,
+    do_sample: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_k: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repetition_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    truncate: Option<usize>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct GenerateRequest {
+    inputs: String,
+    parameters: GenerateParameters,
+    stream: bool,
+}
+
+impl GenerateRequest {
+    fn new(inputs: String, parameters: &Parameters) -> Self {
+        // TGI rejects a zero temperature, greedy decoding is `do_sample: false`.
+        let do_sample = parameters.temperature > 0.0;
+        Self {
+            inputs,
+            parameters: GenerateParameters {
+                max_new_tokens: parameters.max_new_tokens(),
+                return_full_text: parameters.return_full_text,
+                details: false,
+                do_sample,
+                temperature: do_sample.then_some(parameters.temperature),
+                top_p: parameters.top_p(),
+                top_k: parameters.top_k(),
+                repetition_penalty: parameters.repetition_penalty(),
+                truncate: parameters.truncate(),
+                stop: parameters.stop.clone(),
+            },
+            stream: true,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Token {
+    text: String,
+    #[serde(default)]
+    special: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamResponse {
+    token: Token,
+    /// Only set on the last token.
+    generated_text: Option<String>,
+}
+
+/// Turns the server-sent events of `/generate_stream` into text deltas.
+#[derive(Default)]
+struct Decoder {
+    decoder: sse::Decoder,
+    done: bool,
+}
+
+impl Decoder {
+    fn feed(&mut self, bytes: &[u8]) -> Result<String, Error> {
+        let mut content = String::new();
+        for event in self.decoder.feed(bytes) {
+            if self.done {
+                break;
+            }
+            if let Ok(response) = serde_json::from_str::<StreamResponse>(&event.data) {
+                if !response.token.special {
+                    content.push_str(&response.token.text);
+                }
+                self.done = response.generated_text.is_some();
+            } else if let Ok(parsed) = serde_json::from_str::<SseError>(&event.data) {
+                error!("Chunk Error {parsed:?}");
+                return Err(Error::SseError(parsed));
+            } else {
+                return Err(Error::InvalidChunkError(event.data));
+            }
+        }
+        Ok(content)
+    }
+}
+
+pub struct Stream {
+    res: Response,
+    decoder: Decoder,
+}
+
+impl Stream {
+    pub async fn next(&mut self) -> Result<Option<String>, Error> {
+        while !self.decoder.done {
+            let Some(chunk) = self.res.chunk().await? else {
+                if let Some(line) = self.decoder.decoder.finish() {
+                    return Err(match serde_json::from_slice::<SseError>(&line) {
+                        Ok(parsed) => Error::SseError(parsed),
+                        Err(_) => Error::InvalidChunkError(String::from_utf8_lossy(&line).into()),
+                    });
+                }
+                break;
+            };
+            let content = self.decoder.feed(&chunk)?;
+            if !content.is_empty() {
+                return Ok(Some(content));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// A TGI server, `model_id` is the hub repository its chat template comes from.
+pub struct Tgi {
+    endpoint: Endpoint,
+    model_id: String,
+    cache: Cache,
+}
+
+impl Tgi {
+    pub fn new(endpoint: Endpoint, model_id: String, cache: Cache) -> Self {
+        Self {
+            endpoint,
+            model_id,
+            cache,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ChatBackend for Tgi {
+    async fn stream(
+        &self,
+        messages: Vec<Message>,
+        // Raw completions have no way to call tools.
+        _tools: &[ToolDefinition],
+        parameters: &Parameters,
+        on_wait: &mut (dyn FnMut(&Error, Duration) + Send),
+    ) -> Result<api::Stream, Error> {
+        let template = ChatTemplate::fetch(&self.cache, &self.model_id).await?;
+        let request = GenerateRequest::new(template.render(&messages)?, parameters);
+        info!("Query {} {} messages", self.endpoint.url(), messages.len());
+        let res = RetryPolicy::default()
+            .run(|| self.endpoint.post(&request), on_wait)
+            .await?;
+        Ok(Stream {
+            res,
+            decoder: Decoder::default(),
+        }
+        .into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_tokens() {
+        let stream = concat!(
+            "data:{\"token\":{\"id\":1,\"text\":\"Hel\",\"logprob\":-0.1,\"special\":false},\"generated_text\":null,\"details\":null}\n\n",
+            "data:{\"token\":{\"id\":2,\"text\":\"lo\",\"logprob\":-0.1,\"special\":false},\"generated_text\":null,\"details\":null}\n\n",
+            "data:{\"token\":{\"id\":3,\"text\":\"</s>\",\"logprob\":-0.1,\"special\":true},\"generated_text\":\"Hello\",\"details\":null}\n\n",
+        )
+        .as_bytes();
+        for i in 0..=stream.len() {
+            let (a, b) = stream.split_at(i);
+            let mut decoder = Decoder::default();
+            let mut content = decoder.feed(a).unwrap();
+            content.push_str(&decoder.feed(b).unwrap());
+            assert_eq!(content, "Hello", "split at {i}");
+            assert!(decoder.done);
+        }
+    }
+
+    #[test]
+    fn decode_error() {
+        let mut decoder = Decoder::default();
+        let err = decoder
+            .feed(b"data:{\"error\":\"Input validation error\",\"error_type\":\"validation\"}\n\n")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Sse Error Input validation error");
+    }
+
+    #[test]
+    fn request_parameters() {
+        let parameters = Parameters {
+            truncate: 3000,
+            return_full_text: true,
+            stop: vec!["</s>".to_string()],
+            ..Default::default()
+        };
+        let request = GenerateRequest::new("<s>[INST] Hi [/INST]".to_string(), &parameters);
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "inputs": "<s>[INST] Hi [/INST]",
+                "parameters": {
+                    "max_new_tokens": 1024,
+                    "return_full_text": true,
+                    "details": false,
+                    "do_sample": false,
+                    "truncate": 3000,
+                    "stop": ["</s>"],
+                },
+                "stream": true,
+            })
+        );
+    }
+}
@@ -0,0 +1,443 @@
+//! Functions the model can call. Each tool declares its arguments as a json
+//! schema, the model answers with calls instead of text, gets the results
+//! back as `Role::Tool` messages and then replies, see `generate`.
+
+use crate::commands::branch;
+use crate::entities::attachment;
+use crate::entities::message::ToolCall;
+use log::info;
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+
+/// Attachments longer than this are cut, so a single call cannot fill the context.
+const MAX_ATTACHMENT_CHARS: usize = 32_000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("There is no tool called {0}")]
+    UnknownTool(String),
+
+    #[error("Invalid arguments: {0}")]
+    InvalidArguments(#[from] serde_json::Error),
+
+    #[error("{0}")]
+    Failed(String),
+
+    #[error("Db error {0}")]
+    DbError(#[from] DbErr),
+}
+
+/// How a tool is described to the model.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: String,
+    /// Json schema of the arguments object.
+    pub parameters: Value,
+}
+
+/// What a tool may look at: the conversation it was called from.
+pub struct ToolContext<'a> {
+    pub db: &'a DatabaseConnection,
+    pub conversation_id: u32,
+}
+
+#[async_trait::async_trait]
+pub trait Tool: Send + Sync {
+    fn definition(&self) -> ToolDefinition;
+
+    /// Runs the tool, the text returned is what the model reads.
+    async fn call(&self, context: &ToolContext<'_>, arguments: Value) -> Result<String, Error>;
+}
+
+/// The tools offered to the model.
+#[derive(Default)]
+pub struct Registry {
+    tools: Vec<Box<dyn Tool>>,
+}
+
+impl Registry {
+    /// The tools shipped with the app, they only compute or read what the
+    /// user already shared.
+    pub fn builtin() -> Self {
+        Self::default()
+            .with(Calculator)
+            .with(CurrentTime)
+            .with(ReadAttachment)
+    }
+
+    pub fn with(mut self, tool: impl Tool + 'static) -> Self {
+        self.tools.push(Box::new(tool));
+        self
+    }
+
+    /// Whether a tool called `name` is already offered.
+    pub fn has(&self, name: &str) -> bool {
+        self.tools.iter().any(|tool| tool.definition().name == name)
+    }
+
+    pub fn definitions(&self) -> Vec<ToolDefinition> {
+        self.tools.iter().map(|tool| tool.definition()).collect()
+    }
+
+    /// Runs `call`. Failures are returned as text too, the model can fix its
+    /// arguments or answer without the tool.
+    pub async fn call(&self, context: &ToolContext<'_>, call: &ToolCall) -> String {
+        info!("Calling tool {} with {}", call.name, call.arguments);
+        let result = match self
+            .tools
+            .iter()
+            .find(|tool| tool.definition().name == call.name)
+        {
+            Some(tool) => match arguments(&call.arguments) {
+                Ok(arguments) => tool.call(context, arguments).await,
+                Err(err) => Err(err),
+            },
+            None => Err(Error::UnknownTool(call.name.clone())),
+        };
+        result.unwrap_or_else(|err| format!("Error: {err}"))
+    }
+}
+
+/// Some models send nothing at all for tools without arguments.
+fn arguments(arguments: &str) -> Result<Value, Error> {
+    if arguments.trim().is_empty() {
+        return Ok(json!({}));
+    }
+    Ok(serde_json::from_str(arguments)?)
+}
+
+struct Calculator;
+
+#[derive(Deserialize)]
+struct CalculatorArguments {
+    expression: String,
+}
+
+#[async_trait::async_trait]
+impl Tool for Calculator {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            name: "calculator".to_string(),
+            description: "Evaluates an arithmetic expression with + - * / % ^ and parentheses."
+                .to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "expression": {
+                        "type": "string",
+                        "description": "The expression, e.g. `(3 + 4) * 2 ^ 10`",
+                    },
+                },
+                "required": ["expression"],
+            }),
+        }
+    }
+
+    async fn call(&self, _context: &ToolContext<'_>, arguments: Value) -> Result<String, Error> {
+        let CalculatorArguments { expression } = serde_json::from_value(arguments)?;
+        let value = evaluate(&expression).map_err(Error::Failed)?;
+        Ok(format_number(value))
+    }
+}
+
+/// Integers are shown without a fractional part.
+fn format_number(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        value.to_string()
+    }
+}
+
+fn evaluate(expression: &str) -> Result<f64, String> {
+    let mut parser = Parser {
+        input: expression.as_bytes(),
+        pos: 0,
+    };
+    let value = parser.sum()?;
+    if let Some(c) = parser.peek() {
+        return Err(format!("Unexpected `{}` at {}", c as char, parser.pos));
+    }
+    if !value.is_finite() {
+        return Err("The result is not a finite number".to_string());
+    }
+    Ok(value)
+}
+
+/// Recursive descent over the usual precedences, `^` binds tighter than a
+/// leading minus and is right associative: `-2 ^ 2` is -4, `2 ^ 3 ^ 2` is 512.
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&mut self) -> Option<u8> {
+        while self
+            .input
+            .get(self.pos)
+            .is_some_and(u8::is_ascii_whitespace)
+        {
+            self.pos += 1;
+        }
+        self.input.get(self.pos).copied()
+    }
+
+    fn sum(&mut self) -> Result<f64, String> {
+        let mut value = self.product()?;
+        while let Some(op @ (b'+' | b'-')) = self.peek() {
+            self.pos += 1;
+            let rhs = self.product()?;
+            value = if op == b'+' { value + rhs } else { value - rhs };
+        }
+        Ok(value)
+    }
+
+    fn product(&mut self) -> Result<f64, String> {
+        let mut value = self.unary()?;
+        while let Some(op @ (b'*' | b'/' | b'%')) = self.peek() {
+            self.pos += 1;
+            let rhs = self.unary()?;
+            if op != b'*' && rhs == 0.0 {
+                return Err("Division by zero".to_string());
+            }
+            value = match op {
+                b'*' => value * rhs,
+                b'/' => value / rhs,
+                _ => value % rhs,
+            };
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<f64, String> {
+        match self.peek() {
+            Some(b'-') => {
+                self.pos += 1;
+                Ok(-self.unary()?)
+            }
+            Some(b'+') => {
+                self.pos += 1;
+                self.unary()
+            }
+            _ => self.power(),
+        }
+    }
+
+    fn power(&mut self) -> Result<f64, String> {
+        let base = self.atom()?;
+        if self.peek() == Some(b'^') {
+            self.pos += 1;
+            let exponent = self.unary()?;
+            return Ok(base.powf(exponent));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<f64, String> {
+        match self.peek() {
+            Some(b'(') => {
+                self.pos += 1;
+                let value = self.sum()?;
+                if self.peek() != Some(b')') {
+                    return Err(format!("Missing `)` at {}", self.pos));
+                }
+                self.pos += 1;
+                Ok(value)
+            }
+            Some(c) if c.is_ascii_digit() || c == b'.' => {
+                let start = self.pos;
+                while self
+                    .input
+                    .get(self.pos)
+                    .is_some_and(|c| c.is_ascii_digit() || *c == b'.')
+                {
+                    self.pos += 1;
+                }
+                let number = std::str::from_utf8(&self.input[start..self.pos]).unwrap_or_default();
+                number
+                    .parse()
+                    .map_err(|_| format!("Invalid number `{number}`"))
+            }
+            Some(c) => Err(format!("Unexpected `{}` at {}", c as char, self.pos)),
+            None => Err("Unexpected end of the expression".to_string()),
+        }
+    }
+}
+
+struct CurrentTime;
+
+#[async_trait::async_trait]
+impl Tool for CurrentTime {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            name: "current_time".to_string(),
+            description: "The current date and time in the user's time zone.".to_string(),
+            parameters: json!({"type": "object", "properties": {}}),
+        }
+    }
+
+    async fn call(&self, _context: &ToolContext<'_>, _arguments: Value) -> Result<String, Error> {
+        Ok(chrono::Local::now()
+            .format("%A %Y-%m-%d %H:%M:%S (UTC%:z)")
+            .to_string())
+    }
+}
+
+struct ReadAttachment;
+
+#[derive(Deserialize)]
+struct ReadAttachmentArguments {
+    #[serde(default)]
+    name: Option<String>,
+}
+
+#[async_trait::async_trait]
+impl Tool for ReadAttachment {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            name: "read_attachment".to_string(),
+            description: "Reads a file the user attached to this conversation. \
+                Without a name, lists the attached files."
+                .to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "name": {
+                        "type": "string",
+                        "description": "The file name, as listed",
+                    },
+                },
+            }),
+        }
+    }
+
+    async fn call(&self, context: &ToolContext<'_>, arguments: Value) -> Result<String, Error> {
+        let ReadAttachmentArguments { name } = serde_json::from_value(arguments)?;
+        // Files sent on another branch are not part of this conversation.
+        let messages = branch::load(context.db, context.conversation_id).await?;
+        let branch: HashSet<u32> = branch::active_branch(&messages)
+            .iter()
+            .map(|message| message.id)
+            .collect();
+        let attachments: Vec<_> = attachment::Entity::find()
+            .filter(attachment::Column::ConversationId.eq(context.conversation_id))
+            .order_by_asc(attachment::Column::Id)
+            .all(context.db)
+            .await?
+            .into_iter()
+            .filter(|attachment| match attachment.message_id {
+                Some(message_id) => branch.contains(&message_id),
+                None => true,
+            })
+            .collect();
+        let names = || {
+            attachments
+                .iter()
+                .map(|attachment| attachment.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        if attachments.is_empty() {
+            return Ok("No file is attached to this conversation.".to_string());
+        }
+        let Some(name) = name else {
+            return Ok(format!("Attached files: {}", names()));
+        };
+        // The latest one wins when the same file was attached twice.
+        let attachment = attachments
+            .iter()
+            .rev()
+            .find(|attachment| attachment.name == name)
+            .ok_or_else(|| Error::Failed(format!("No file named {name}, attached: {}", names())))?;
+        Ok(cut(&attachment.content, MAX_ATTACHMENT_CHARS))
+    }
+}
+
+/// The first `max` characters of `content`, with a note when some were left out.
+pub(crate) fn cut(content: &str, max: usize) -> String {
+    match content.char_indices().nth(max) {
+        Some((end, _)) => {
+            let rest = content[end..].chars().count();
+            format!("{}\n[{rest} more characters left out]", &content[..end])
+        }
+        None => content.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precedence() {
+        assert_eq!(evaluate("1 + 2 * 3").unwrap(), 7.0);
+        assert_eq!(evaluate("(1 + 2) * 3").unwrap(), 9.0);
+        assert_eq!(evaluate("10 - 4 - 3").unwrap(), 3.0);
+        assert_eq!(evaluate("2 ^ 3 ^ 2").unwrap(), 512.0);
+        assert_eq!(evaluate("-2 ^ 2").unwrap(), -4.0);
+        assert_eq!(evaluate("2 ^ -1").unwrap(), 0.5);
+        assert_eq!(evaluate("7 % 4 + .5").unwrap(), 3.5);
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        assert_eq!(evaluate("1 / 0").unwrap_err(), "Division by zero");
+        assert_eq!(evaluate("(1 + 2").unwrap_err(), "Missing `)` at 6");
+        assert_eq!(
+            evaluate("2 +").unwrap_err(),
+            "Unexpected end of the expression"
+        );
+        assert_eq!(evaluate("2 x 3").unwrap_err(), "Unexpected `x` at 2");
+        assert_eq!(evaluate("1.2.3").unwrap_err(), "Invalid number `1.2.3`");
+        assert!(evaluate("10 ^ 400").is_err());
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(format_number(42.0), "42");
+        assert_eq!(format_number(-0.25), "-0.25");
+    }
+
+    #[test]
+    fn long_attachments_are_cut() {
+        assert_eq!(cut("héllo", 10), "héllo");
+        assert_eq!(cut("héllo", 2), "hé\n[3 more characters left out]");
+    }
+
+    #[tokio::test]
+    async fn failures_are_told_to_the_model() {
+        let db = DatabaseConnection::Disconnected;
+        let context = ToolContext {
+            db: &db,
+            conversation_id: 1,
+        };
+        let registry = Registry::builtin();
+        let call = |name: &str, arguments: &str| ToolCall {
+            id: "call_0".to_string(),
+            name: name.to_string(),
+            arguments: arguments.to_string(),
+        };
+        assert_eq!(
+            registry
+                .call(&context, &call("calculator", r#"{"expression": "6 * 7"}"#))
+                .await,
+            "42"
+        );
+        assert_eq!(
+            registry.call(&context, &call("weather", "{}")).await,
+            "Error: There is no tool called weather"
+        );
+        assert!(registry
+            .call(&context, &call("calculator", "{}"))
+            .await
+            .starts_with("Error: Invalid arguments"));
+        assert!(!registry
+            .call(&context, &call("current_time", ""))
+            .await
+            .starts_with("Error"));
+    }
+}
@@ -0,0 +1,56 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A file the user attached to a conversation, as text. The model reads it
+/// with the `read_attachment` tool, or in the prompt when it was sent with a
+/// message.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "attachment")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: u32,
+    pub conversation_id: u32,
+    /// The message it was sent with, `None` until then or for files only the
+    /// tool reads.
+    pub message_id: Option<u32>,
+    /// The file name, as picked by the user.
+    pub name: String,
+    /// Extracted from the file, see `documents::extract`.
+    #[serde(skip_serializing)]
+    pub content: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::conversation::Entity",
+        from = "Column::ConversationId",
+        to = "super::conversation::Column::Id",
+        on_update = "NoAction",
+        on_delete = "Cascade"
+    )]
+    Conversation,
+    #[sea_orm(
+        belongs_to = "super::message::Entity",
+        from = "Column::MessageId",
+        to = "super::message::Column::Id",
+        on_update = "Cascade",
+        on_delete = "Cascade"
+    )]
+    Message,
+}
+
+impl Related<super::conversation::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Conversation.def()
+    }
+}
+
+impl Related<super::message::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Message.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,55 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A few lines of a file of a `knowledge_base`, with their embedding.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "chunk")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: u32,
+    pub knowledge_base_id: u32,
+    /// The file, relative to the knowledge base's folder.
+    pub path: String,
+    /// The first line, from 1.
+    pub start_line: u32,
+    /// The last line, included.
+    pub end_line: u32,
+    pub content: String,
+    /// Little endian f32s, normalized, see `encode`.
+    #[serde(skip)]
+    pub embedding: Vec<u8>,
+}
+
+impl Model {
+    pub fn embedding(&self) -> Vec<f32> {
+        self.embedding
+            .chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().expect("4 bytes")))
+            .collect()
+    }
+}
+
+/// How an embedding is stored, sqlite has no vector type.
+pub fn encode(embedding: &[f32]) -> Vec<u8> {
+    embedding.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::knowledge_base::Entity",
+        from = "Column::KnowledgeBaseId",
+        to = "super::knowledge_base::Column::Id",
+        on_update = "NoAction",
+        on_delete = "Cascade"
+    )]
+    KnowledgeBase,
+}
+
+impl Related<super::knowledge_base::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::KnowledgeBase.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
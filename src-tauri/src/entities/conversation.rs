@@ -12,6 +12,9 @@ pub struct Model {
     #[sea_orm(updated_at)]
     pub updated_at: chrono::DateTime<chrono::Utc>,
     pub model_id: u32,
+    pub parameters: Option<super::model::ParametersOverride>,
+    /// Replaces the model's system prompt.
+    pub system_prompt: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -26,6 +29,8 @@ pub enum Relation {
         on_delete = "Cascade"
     )]
     Model,
+    #[sea_orm(has_many = "super::attachment::Entity")]
+    Attachment,
 }
 
 impl Related<super::message::Entity> for Entity {
@@ -38,5 +43,10 @@ impl Related<super::model::Entity> for Entity {
         Relation::Model.def()
     }
 }
+impl Related<super::attachment::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Attachment.def()
+    }
+}
 
 impl ActiveModelBehavior for ActiveModel {}
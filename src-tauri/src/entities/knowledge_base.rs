@@ -0,0 +1,49 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A folder of documents a conversation draws on, see `knowledge`. Its files
+/// are split into `chunk`s, the closest ones to each question go in the prompt.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "knowledge_base")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: u32,
+    #[sea_orm(unique)]
+    pub conversation_id: u32,
+    /// The folder, absolute.
+    pub path: String,
+    /// The hub model the chunks were embedded with, they have to be indexed
+    /// again when it changes.
+    pub embedding_model: String,
+    /// `None` until the folder is indexed.
+    pub indexed_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::conversation::Entity",
+        from = "Column::ConversationId",
+        to = "super::conversation::Column::Id",
+        on_update = "NoAction",
+        on_delete = "Cascade"
+    )]
+    Conversation,
+    #[sea_orm(has_many = "super::chunk::Entity")]
+    Chunk,
+}
+
+impl Related<super::conversation::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Conversation.def()
+    }
+}
+
+impl Related<super::chunk::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Chunk.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,36 @@
+use sea_orm::entity::prelude::*;
+use sea_orm::FromJsonQueryResult;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Command line arguments of the server.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Deserialize, Serialize, FromJsonQueryResult)]
+pub struct Arguments(pub Vec<String>);
+
+/// Environment variables set for the server, on top of the app's.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Deserialize, Serialize, FromJsonQueryResult)]
+pub struct Environment(pub BTreeMap<String, String>);
+
+/// A Model Context Protocol server, started as a child process speaking over
+/// its stdin and stdout.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, DeriveEntityModel)]
+#[sea_orm(table_name = "mcp_server")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: u32,
+    pub name: String,
+    /// The program to run, looked up in the `PATH` when not a path.
+    pub command: String,
+    pub args: Arguments,
+    /// May hold secrets, never sent to the webview.
+    #[serde(skip_serializing)]
+    pub env: Environment,
+    /// Disabled servers are not started and their tools not offered.
+    pub enabled: bool,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
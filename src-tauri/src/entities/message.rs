@@ -1,6 +1,93 @@
 use sea_orm::entity::prelude::*;
+use sea_orm::FromJsonQueryResult;
 use serde::{Deserialize, Serialize};
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Deserialize, Serialize)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::None)")]
+#[serde(rename_all = "camelCase")]
+pub enum MessageKind {
+    #[sea_orm(string_value = "text")]
+    Text,
+    /// Written by a model, stands in for the messages up to `summary_until`
+    /// in later prompts.
+    #[sea_orm(string_value = "summary")]
+    Summary,
+}
+
+/// Who a message is from as far as the model is concerned, whichever user wrote it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Deserialize, Serialize)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::None)")]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    #[sea_orm(string_value = "system")]
+    System,
+    #[sea_orm(string_value = "user")]
+    User,
+    #[sea_orm(string_value = "assistant")]
+    Assistant,
+    /// The result of a tool the assistant called, see `tool_call_id`.
+    #[sea_orm(string_value = "tool")]
+    Tool,
+}
+
+/// A function the assistant asked to run, `arguments` is the json it wrote.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize, Serialize, FromJsonQueryResult)]
+pub struct ToolCalls(pub Vec<ToolCall>);
+
+/// A picture attached to a message, the file is kept in the cache under its
+/// hash, see `images`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Image {
+    /// Sha256 of the file, in hex.
+    pub hash: String,
+    pub mime_type: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Deserialize, Serialize, FromJsonQueryResult)]
+pub struct Images(pub Vec<Image>);
+
+/// Lines of a knowledge base file the reply was given, the model cites them
+/// by their position, from 1.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Source {
+    /// Relative to the knowledge base's folder.
+    pub path: String,
+    pub start_line: u32,
+    pub end_line: u32,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Deserialize, Serialize, FromJsonQueryResult)]
+pub struct Sources(pub Vec<Source>);
+
+/// What a reasoning model thought before it answered, shown apart and left
+/// out of later prompts.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Deserialize, Serialize, FromJsonQueryResult)]
+pub struct Reasoning {
+    pub content: String,
+    /// From the first thought to the first word of the answer.
+    pub duration_ms: u64,
+}
+
+/// How a reply was generated. Token counts are our own estimate when the
+/// backend does not report them.
+#[derive(Default, Debug, Clone, PartialEq, Deserialize, Serialize, FromJsonQueryResult)]
+pub struct Metrics {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    /// Time to first token, from sending the request.
+    pub ttft_ms: u64,
+    /// Completion tokens over the time from the first token to the last.
+    pub tokens_per_second: f32,
+    pub estimated: bool,
+}
+
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
 #[sea_orm(table_name = "message")]
 pub struct Model {
@@ -8,9 +95,31 @@ pub struct Model {
     pub id: u32,
     pub user_id: u32,
     pub content: String,
+    pub role: Role,
     pub conversation_id: u32,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
+    /// The generation was stopped before the model finished its reply.
+    pub truncated: bool,
+    pub kind: MessageKind,
+    /// The last message a `Summary` covers.
+    pub summary_until: Option<u32>,
+    /// The message this one answers, `None` for the first one. Summaries hang
+    /// off the last message they cover.
+    pub parent_id: Option<u32>,
+    /// Whether this is the sibling the conversation goes on with.
+    pub active: bool,
+    /// Only set on generated replies.
+    pub metrics: Option<Metrics>,
+    /// The tools an assistant message called instead of answering.
+    pub tool_calls: Option<ToolCalls>,
+    /// The call a `Role::Tool` message answers.
+    pub tool_call_id: Option<String>,
+    /// Pictures the user sent along the text.
+    pub images: Option<Images>,
+    /// The knowledge base excerpts a reply was given.
+    pub sources: Option<Sources>,
+    pub reasoning: Option<Reasoning>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
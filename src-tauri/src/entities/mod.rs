@@ -1,4 +1,10 @@
+pub mod attachment;
+pub mod chunk;
 pub mod conversation;
+pub mod knowledge_base;
+pub mod mcp_server;
 pub mod message;
 pub mod model;
+pub mod provider;
+pub mod tool_permission;
 pub mod user;
@@ -12,6 +12,115 @@ pub struct Parameters {
     pub top_k: usize,
     pub repetition_penalty: f32,
     pub return_full_text: bool,
+    /// Older turns are summarized once the prompt grows past this many tokens.
+    #[serde(default)]
+    pub summarize_after: usize,
+    /// The model writing those summaries, the conversation's own when unset.
+    #[serde(default)]
+    pub summary_model_id: u32,
+    /// Offers the built-in tools to the model, for models trained to call them.
+    #[serde(default)]
+    pub tools: bool,
+}
+
+/// Used when `max_new_tokens` was never set.
+pub const DEFAULT_MAX_NEW_TOKENS: usize = 1024;
+
+// Zeroes are what `Default` stores, they mean "let the backend decide".
+impl Parameters {
+    pub fn max_new_tokens(&self) -> usize {
+        if self.max_new_tokens > 0 {
+            self.max_new_tokens
+        } else {
+            DEFAULT_MAX_NEW_TOKENS
+        }
+    }
+
+    pub fn truncate(&self) -> Option<usize> {
+        (self.truncate > 0).then_some(self.truncate)
+    }
+
+    pub fn top_p(&self) -> Option<f32> {
+        (self.top_p > 0.0 && self.top_p < 1.0).then_some(self.top_p)
+    }
+
+    pub fn top_k(&self) -> Option<usize> {
+        (self.top_k > 0).then_some(self.top_k)
+    }
+
+    pub fn repetition_penalty(&self) -> Option<f32> {
+        (self.repetition_penalty > 0.0).then_some(self.repetition_penalty)
+    }
+
+    pub fn stop(&self) -> Option<Vec<String>> {
+        (!self.stop.is_empty()).then(|| self.stop.clone())
+    }
+
+    pub fn summarize_after(&self) -> Option<usize> {
+        (self.summarize_after > 0).then_some(self.summarize_after)
+    }
+
+    pub fn summary_model_id(&self) -> Option<u32> {
+        (self.summary_model_id > 0).then_some(self.summary_model_id)
+    }
+
+    /// The model's parameters with the conversation's overrides applied.
+    pub fn with_overrides(&self, overrides: &ParametersOverride) -> Self {
+        Self {
+            temperature: overrides.temperature.unwrap_or(self.temperature),
+            truncate: overrides.truncate.unwrap_or(self.truncate),
+            max_new_tokens: overrides.max_new_tokens.unwrap_or(self.max_new_tokens),
+            stop: overrides.stop.clone().unwrap_or_else(|| self.stop.clone()),
+            top_p: overrides.top_p.unwrap_or(self.top_p),
+            top_k: overrides.top_k.unwrap_or(self.top_k),
+            repetition_penalty: overrides
+                .repetition_penalty
+                .unwrap_or(self.repetition_penalty),
+            return_full_text: overrides.return_full_text.unwrap_or(self.return_full_text),
+            summarize_after: overrides.summarize_after.unwrap_or(self.summarize_after),
+            summary_model_id: overrides.summary_model_id.unwrap_or(self.summary_model_id),
+            tools: overrides.tools.unwrap_or(self.tools),
+        }
+    }
+}
+
+/// Per conversation tweaks of the model's `Parameters`, unset fields keep the model value.
+#[derive(Default, Debug, Clone, PartialEq, Deserialize, Serialize, FromJsonQueryResult)]
+pub struct ParametersOverride {
+    pub temperature: Option<f32>,
+    pub truncate: Option<usize>,
+    pub max_new_tokens: Option<usize>,
+    pub stop: Option<Vec<String>>,
+    pub top_p: Option<f32>,
+    pub top_k: Option<usize>,
+    pub repetition_penalty: Option<f32>,
+    pub return_full_text: Option<bool>,
+    pub summarize_after: Option<usize>,
+    pub summary_model_id: Option<u32>,
+    pub tools: Option<bool>,
+}
+
+/// Which protocol the model's endpoint speaks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Deserialize, Serialize)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::None)")]
+#[serde(rename_all = "camelCase")]
+pub enum ModelKind {
+    /// Hugging Face inference API or router, authenticated with the hub token.
+    #[sea_orm(string_value = "hf")]
+    HuggingFace,
+    /// Any server exposing `/v1/chat/completions`.
+    #[sea_orm(string_value = "openai")]
+    OpenAi,
+    /// Text Generation Inference's `/generate_stream`, `endpoint` is the
+    /// server's base url and `model_name` the hub repository of its template.
+    #[sea_orm(string_value = "tgi")]
+    Tgi,
+    /// Ollama's native api, `endpoint` is its base url.
+    #[sea_orm(string_value = "ollama")]
+    Ollama,
+    /// Run in process by mistral.rs, `endpoint` is the model id.
+    #[sea_orm(string_value = "local")]
+    Local,
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize, DeriveEntityModel)]
@@ -23,6 +132,13 @@ pub struct Model {
     pub user_id: u32,
     pub endpoint: String,
     pub parameters: Parameters,
+    pub kind: ModelKind,
+    /// Set for `OpenAi` models served by a configured provider.
+    pub provider_id: Option<u32>,
+    /// The name the provider knows the model by.
+    pub model_name: Option<String>,
+    /// Sent first in every conversation, unless the conversation has its own.
+    pub system_prompt: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -33,6 +149,12 @@ pub enum Relation {
         to = "super::user::Column::Id"
     )]
     User,
+    #[sea_orm(
+        belongs_to = "super::provider::Entity",
+        from = "Column::ProviderId",
+        to = "super::provider::Column::Id"
+    )]
+    Provider,
 }
 
 impl Related<super::user::Entity> for Entity {
@@ -41,4 +163,10 @@ impl Related<super::user::Entity> for Entity {
     }
 }
 
+impl Related<super::provider::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Provider.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,49 @@
+use sea_orm::entity::prelude::*;
+use sea_orm::FromJsonQueryResult;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Extra headers sent with every request, e.g. an organization id.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Deserialize, Serialize, FromJsonQueryResult)]
+pub struct Headers(pub BTreeMap<String, String>);
+
+/// Model names served by the provider.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Deserialize, Serialize, FromJsonQueryResult)]
+pub struct ModelNames(pub Vec<String>);
+
+/// An OpenAI compatible (or TGI) server, with its own credentials.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, DeriveEntityModel)]
+#[sea_orm(table_name = "provider")]
+#[serde(rename_all = "camelCase")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: u32,
+    pub name: String,
+    /// Up to and including the version, e.g. `http://localhost:8000/v1`, the
+    /// server root for TGI.
+    pub base_url: String,
+    #[serde(skip_serializing)]
+    pub api_key: Option<String>,
+    pub headers: Headers,
+    pub models: ModelNames,
+}
+
+impl Model {
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/{path}", self.base_url.trim_end_matches('/'))
+    }
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(has_many = "super::model::Entity")]
+    Model,
+}
+
+impl Related<super::model::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Model.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
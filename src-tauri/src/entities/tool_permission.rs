@@ -0,0 +1,21 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Whether a tool may run without asking the user first. Tools without a row
+/// always ask.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "tool_permission")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: u32,
+    /// The tool's name, as offered to the model.
+    #[sea_orm(unique)]
+    pub name: String,
+    pub always_allow: bool,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
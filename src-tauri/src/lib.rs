@@ -2,12 +2,16 @@ mod commands;
 mod entities;
 pub mod migrations;
 
-use crate::commands::api::Stream;
+use crate::commands::approval::Approvals;
+use crate::commands::embeddings::Embedder;
 use crate::commands::login::Openid;
+use crate::commands::mcp;
 use hf_hub::Cache;
 use log::{debug, info, warn};
 use sea_orm::{Database, DatabaseConnection};
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
+use std::sync::Arc;
 use tauri::Manager;
 use tokio::sync::Mutex;
 
@@ -17,7 +21,18 @@ struct State {
     // device: Device,
     openid: Mutex<Option<Openid>>,
     // tx: Mutex<Option<tokio::sync::oneshot::Sender<()>>>,
-    stream: Mutex<Option<Stream>>,
+    /// In-flight generations, keyed by conversation id.
+    generations: Mutex<HashMap<u32, tokio::sync::oneshot::Sender<()>>>,
+    /// Tool calls waiting for the user, keyed by message id and call id.
+    approvals: Approvals,
+    /// Running MCP servers, keyed by server id.
+    mcp: Mutex<HashMap<u32, Arc<mcp::Client>>>,
+    /// MCP servers which failed, left alone for a while.
+    mcp_failures: mcp::Failures,
+    /// The knowledge bases' embedding model, loaded on first use.
+    embedder: Mutex<Option<Arc<Embedder>>>,
+    /// Hub files which could not be fetched, not asked for again until restart.
+    not_on_hub: Mutex<HashSet<(String, String)>>,
 }
 
 fn cache(path: &Path) -> Cache {
@@ -63,10 +78,41 @@ pub fn run() {
             commands::login::login,
             commands::login::login_callback,
             commands::models::get_models,
+            commands::models::import_ollama_models,
+            commands::providers::get_providers,
+            commands::providers::create_provider,
+            commands::providers::delete_provider,
             commands::conversation::create_conversation,
             commands::conversation::new_message,
             commands::conversation::get_messages,
-            commands::api::get_chunk,
+            commands::conversation::update_message,
+            commands::conversation::edit_message,
+            commands::conversation::select_branch,
+            commands::conversation::update_conversation_parameters,
+            commands::conversation::get_conversation_settings,
+            commands::conversation::update_conversation_system_prompt,
+            commands::models::update_model_system_prompt,
+            commands::generate::start_generation,
+            commands::generate::cancel_generation,
+            commands::generate::regenerate,
+            commands::summary::regenerate_summary,
+            commands::attachments::attach_file,
+            commands::attachments::get_attachments,
+            commands::attachments::delete_attachment,
+            commands::approval::resolve_tool_call,
+            commands::approval::get_pending_tool_calls,
+            commands::approval::get_tool_permissions,
+            commands::approval::set_tool_permission,
+            commands::mcp::get_mcp_servers,
+            commands::mcp::create_mcp_server,
+            commands::mcp::set_mcp_server_enabled,
+            commands::mcp::delete_mcp_server,
+            commands::mcp::get_mcp_server_capabilities,
+            commands::images::upload_image,
+            commands::images::get_image,
+            commands::knowledge::get_knowledge_base,
+            commands::knowledge::set_knowledge_base,
+            commands::knowledge::index_knowledge_base,
         ])
         .setup(move |app| {
             info!("Start the run");
@@ -109,7 +155,12 @@ pub fn run() {
                 cache,
                 // device,
                 openid: Mutex::new(None),
-                stream: Mutex::new(None),
+                generations: Mutex::new(HashMap::new()),
+                approvals: Mutex::new(HashMap::new()),
+                mcp: Mutex::new(HashMap::new()),
+                mcp_failures: Mutex::new(HashMap::new()),
+                embedder: Mutex::new(None),
+                not_on_hub: Mutex::new(HashSet::new()),
                 // tx: Mutex::new(None),
             });
             // if let Some(setup) = setup {
@@ -0,0 +1,61 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Model::Table)
+                    .add_column(
+                        ColumnDef::new(Model::Kind)
+                            .string()
+                            .not_null()
+                            .default("local"),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // The kind used to be guessed from the endpoint, https ones were sent
+        // the hub token, Inference Endpoints and other gated hosts need it.
+        manager
+            .exec_stmt(
+                Query::update()
+                    .table(Model::Table)
+                    .value(Model::Kind, "openai")
+                    .and_where(Expr::col(Model::Endpoint).like("http://%"))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .exec_stmt(
+                Query::update()
+                    .table(Model::Table)
+                    .value(Model::Kind, "hf")
+                    .and_where(Expr::col(Model::Endpoint).like("https://%"))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Model::Table)
+                    .drop_column(Model::Kind)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Model {
+    Table,
+    Endpoint,
+    Kind,
+}
@@ -0,0 +1,87 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Provider::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Provider::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Provider::Name).string().not_null())
+                    .col(ColumnDef::new(Provider::BaseUrl).string().not_null())
+                    .col(ColumnDef::new(Provider::ApiKey).string())
+                    .col(ColumnDef::new(Provider::Headers).json().not_null())
+                    .col(ColumnDef::new(Provider::Models).json().not_null())
+                    .to_owned(),
+            )
+            .await?;
+        // Sqlite only supports one change per alter statement.
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Model::Table)
+                    .add_column(ColumnDef::new(Model::ProviderId).integer())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Model::Table)
+                    .add_column(ColumnDef::new(Model::ModelName).string())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Model::Table)
+                    .drop_column(Model::ModelName)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Model::Table)
+                    .drop_column(Model::ProviderId)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Provider::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Provider {
+    Table,
+    Id,
+    Name,
+    BaseUrl,
+    ApiKey,
+    Headers,
+    Models,
+}
+
+#[derive(DeriveIden)]
+enum Model {
+    Table,
+    ProviderId,
+    ModelName,
+}
@@ -0,0 +1,57 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Model::Table)
+                    .add_column(ColumnDef::new(Model::SystemPrompt).text())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Conversation::Table)
+                    .add_column(ColumnDef::new(Conversation::SystemPrompt).text())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Conversation::Table)
+                    .drop_column(Conversation::SystemPrompt)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Model::Table)
+                    .drop_column(Model::SystemPrompt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Model {
+    Table,
+    SystemPrompt,
+}
+
+#[derive(DeriveIden)]
+enum Conversation {
+    Table,
+    SystemPrompt,
+}
@@ -0,0 +1,64 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Message::Table)
+                    .add_column(
+                        ColumnDef::new(Message::Role)
+                            .string()
+                            .not_null()
+                            .default("user"),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // Every model speaks as its own user, everything else was typed by hand.
+        manager
+            .exec_stmt(
+                Query::update()
+                    .table(Message::Table)
+                    .value(Message::Role, "assistant")
+                    .and_where(
+                        Expr::col(Message::UserId).in_subquery(
+                            Query::select()
+                                .column(Model::UserId)
+                                .from(Model::Table)
+                                .to_owned(),
+                        ),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Message::Table)
+                    .drop_column(Message::Role)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Message {
+    Table,
+    Role,
+    UserId,
+}
+
+#[derive(DeriveIden)]
+enum Model {
+    Table,
+    UserId,
+}
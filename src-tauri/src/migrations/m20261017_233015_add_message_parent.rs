@@ -0,0 +1,77 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Message::Table)
+                    .add_column(ColumnDef::new(Message::ParentId).integer())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Message::Table)
+                    .add_column(
+                        ColumnDef::new(Message::Active)
+                            .boolean()
+                            .not_null()
+                            .default(true),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // Conversations were a single line, a message replies to the one
+        // before it. Summaries hang off the last message they cover.
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            "UPDATE message SET parent_id = (
+                SELECT previous.id FROM message AS previous
+                WHERE previous.conversation_id = message.conversation_id
+                    AND previous.kind = 'text'
+                    AND previous.id < message.id
+                ORDER BY previous.id DESC
+                LIMIT 1
+            )
+            WHERE kind = 'text'",
+        )
+        .await?;
+        db.execute_unprepared(
+            "UPDATE message SET parent_id = summary_until WHERE kind = 'summary'",
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Message::Table)
+                    .drop_column(Message::Active)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Message::Table)
+                    .drop_column(Message::ParentId)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Message {
+    Table,
+    ParentId,
+    Active,
+}
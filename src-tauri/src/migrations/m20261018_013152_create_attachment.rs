@@ -0,0 +1,63 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Attachment::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Attachment::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(Attachment::ConversationId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        sea_query::ForeignKey::create()
+                            .name("fk-attachment-conversation_id")
+                            .from(Attachment::Table, Attachment::ConversationId)
+                            .to(Conversation::Table, Conversation::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(Attachment::Name).string().not_null())
+                    .col(ColumnDef::new(Attachment::Content).string().not_null())
+                    .col(ColumnDef::new(Attachment::CreatedAt).date_time().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Attachment::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Attachment {
+    Table,
+    Id,
+    ConversationId,
+    Name,
+    Content,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Conversation {
+    Table,
+    Id,
+}
@@ -0,0 +1,54 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(McpServer::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(McpServer::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(McpServer::Name).string().not_null())
+                    .col(ColumnDef::new(McpServer::Command).string().not_null())
+                    .col(ColumnDef::new(McpServer::Args).json().not_null())
+                    .col(ColumnDef::new(McpServer::Env).json().not_null())
+                    .col(
+                        ColumnDef::new(McpServer::Enabled)
+                            .boolean()
+                            .not_null()
+                            .default(true),
+                    )
+                    .col(ColumnDef::new(McpServer::CreatedAt).date_time().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(McpServer::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum McpServer {
+    Table,
+    Id,
+    Name,
+    Command,
+    Args,
+    Env,
+    Enabled,
+    CreatedAt,
+}
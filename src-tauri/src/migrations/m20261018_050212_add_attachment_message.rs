@@ -0,0 +1,48 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite takes a reference on a new column, but sea-query only builds
+        // foreign keys with the table.
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "ALTER TABLE attachment ADD COLUMN message_id integer
+                REFERENCES message (id) ON DELETE CASCADE ON UPDATE CASCADE",
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // A column with a foreign key cannot be dropped, the table is built
+        // again without it.
+        let db = manager.get_connection();
+        db.execute_unprepared("ALTER TABLE attachment RENAME TO attachment_with_message")
+            .await?;
+        db.execute_unprepared(
+            "CREATE TABLE attachment (
+                id integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+                conversation_id integer NOT NULL,
+                name varchar NOT NULL,
+                content varchar NOT NULL,
+                created_at datetime_text NOT NULL,
+                CONSTRAINT \"fk-attachment-conversation_id\" FOREIGN KEY (conversation_id)
+                    REFERENCES conversation (id) ON DELETE CASCADE ON UPDATE CASCADE
+            )",
+        )
+        .await?;
+        db.execute_unprepared(
+            "INSERT INTO attachment (id, conversation_id, name, content, created_at)
+            SELECT id, conversation_id, name, content, created_at FROM attachment_with_message",
+        )
+        .await?;
+        db.execute_unprepared("DROP TABLE attachment_with_message")
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(KnowledgeBase::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(KnowledgeBase::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(KnowledgeBase::ConversationId)
+                            .integer()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .foreign_key(
+                        sea_query::ForeignKey::create()
+                            .name("fk-knowledge_base-conversation_id")
+                            .from(KnowledgeBase::Table, KnowledgeBase::ConversationId)
+                            .to(Conversation::Table, Conversation::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(KnowledgeBase::Path).string().not_null())
+                    .col(
+                        ColumnDef::new(KnowledgeBase::EmbeddingModel)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(KnowledgeBase::IndexedAt).date_time())
+                    .col(
+                        ColumnDef::new(KnowledgeBase::CreatedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(Chunk::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Chunk::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Chunk::KnowledgeBaseId).integer().not_null())
+                    .foreign_key(
+                        sea_query::ForeignKey::create()
+                            .name("fk-chunk-knowledge_base_id")
+                            .from(Chunk::Table, Chunk::KnowledgeBaseId)
+                            .to(KnowledgeBase::Table, KnowledgeBase::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(Chunk::Path).string().not_null())
+                    .col(ColumnDef::new(Chunk::StartLine).integer().not_null())
+                    .col(ColumnDef::new(Chunk::EndLine).integer().not_null())
+                    .col(ColumnDef::new(Chunk::Content).string().not_null())
+                    .col(ColumnDef::new(Chunk::Embedding).blob().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Chunk::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(KnowledgeBase::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum KnowledgeBase {
+    Table,
+    Id,
+    ConversationId,
+    Path,
+    EmbeddingModel,
+    IndexedAt,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Chunk {
+    Table,
+    Id,
+    KnowledgeBaseId,
+    Path,
+    StartLine,
+    EndLine,
+    Content,
+    Embedding,
+}
+
+#[derive(DeriveIden)]
+enum Conversation {
+    Table,
+    Id,
+}
@@ -4,6 +4,24 @@ mod m20230914_053359_create_model;
 mod m20230918_064025_create_conversation;
 mod m20230918_082713_create_messages;
 mod m20231229_125956_create_users;
+mod m20261017_101512_add_message_truncated;
+mod m20261017_143207_add_conversation_parameters;
+mod m20261017_160344_add_model_kind;
+mod m20261017_172518_create_provider;
+mod m20261017_190846_add_message_summary;
+mod m20261017_204513_add_system_prompt;
+mod m20261017_221034_add_message_role;
+mod m20261017_233015_add_message_parent;
+mod m20261018_003417_add_message_metrics;
+mod m20261018_012640_add_message_tool_calls;
+mod m20261018_013152_create_attachment;
+mod m20261018_021907_create_tool_permission;
+mod m20261018_030455_create_mcp_server;
+mod m20261018_041736_add_message_images;
+mod m20261018_050212_add_attachment_message;
+mod m20261018_061504_create_knowledge_base;
+mod m20261018_061505_add_message_sources;
+mod m20261018_072918_add_message_reasoning;
 
 pub struct Migrator;
 
@@ -15,6 +33,24 @@ impl MigratorTrait for Migrator {
             Box::new(m20230914_053359_create_model::Migration),
             Box::new(m20230918_064025_create_conversation::Migration),
             Box::new(m20230918_082713_create_messages::Migration),
+            Box::new(m20261017_101512_add_message_truncated::Migration),
+            Box::new(m20261017_143207_add_conversation_parameters::Migration),
+            Box::new(m20261017_160344_add_model_kind::Migration),
+            Box::new(m20261017_172518_create_provider::Migration),
+            Box::new(m20261017_190846_add_message_summary::Migration),
+            Box::new(m20261017_204513_add_system_prompt::Migration),
+            Box::new(m20261017_221034_add_message_role::Migration),
+            Box::new(m20261017_233015_add_message_parent::Migration),
+            Box::new(m20261018_003417_add_message_metrics::Migration),
+            Box::new(m20261018_012640_add_message_tool_calls::Migration),
+            Box::new(m20261018_013152_create_attachment::Migration),
+            Box::new(m20261018_021907_create_tool_permission::Migration),
+            Box::new(m20261018_030455_create_mcp_server::Migration),
+            Box::new(m20261018_041736_add_message_images::Migration),
+            Box::new(m20261018_050212_add_attachment_message::Migration),
+            Box::new(m20261018_061504_create_knowledge_base::Migration),
+            Box::new(m20261018_061505_add_message_sources::Migration),
+            Box::new(m20261018_072918_add_message_reasoning::Migration),
         ]
     }
 }
@@ -16,6 +16,16 @@ extern "C" {
     #[wasm_bindgen(js_namespace = ["window", "__TAURI_INTERNALS__"])]
     fn convertFileSrc(filepath: &str, protocol: &str) -> JsValue;
 
+    /// Tauri ipc channel, used to receive streamed events from a command.
+    #[wasm_bindgen(js_namespace = ["window", "__TAURI__", "core"])]
+    pub type Channel;
+
+    #[wasm_bindgen(constructor, js_namespace = ["window", "__TAURI__", "core"])]
+    pub fn new() -> Channel;
+
+    #[wasm_bindgen(method, setter)]
+    pub fn set_onmessage(this: &Channel, callback: &js_sys::Function);
+
 
     #[wasm_bindgen(js_namespace = ["window", "__TAURI__", "log"])]
     pub async fn trace(log: &str);
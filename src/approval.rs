@@ -0,0 +1,90 @@
+use crate::app::invoke;
+use crate::state::ToolCall;
+use leptos::*;
+use serde::Serialize;
+use wasm_bindgen_futures::spawn_local;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase", tag = "decision")]
+enum Approval {
+    Approve { arguments: String, always: bool },
+    Reject,
+}
+
+#[derive(Serialize)]
+struct ResolveToolCall {
+    messageid: u32,
+    callid: String,
+    approval: Approval,
+}
+
+/// A tool call the model wants to make, it only runs once approved. The
+/// arguments can be edited first.
+#[component]
+pub fn ToolApproval(message_id: u32, call: ToolCall, on_resolved: Callback<()>) -> impl IntoView {
+    let (arguments, set_arguments) = create_signal(call.arguments.clone());
+    let (always, set_always) = create_signal(false);
+    let (error, set_error) = create_signal(None::<String>);
+    let callid = store_value(call.id);
+
+    let resolve = move |approval: Approval| {
+        spawn_local(async move {
+            let args = serde_wasm_bindgen::to_value(&ResolveToolCall {
+                messageid: message_id,
+                callid: callid.get_value(),
+                approval,
+            })
+            .unwrap();
+            match invoke("resolve_tool_call", args).await {
+                Ok(_) => on_resolved.call(()),
+                Err(err) => set_error.set(Some(err.as_string().unwrap_or_default())),
+            }
+        });
+    };
+    let name = call.name.clone();
+    view! {
+        <div class="flex flex-col gap-2 mx-3 my-2 p-3 text-sm rounded-lg border border-amber-400 bg-amber-50 dark:bg-gray-800 dark:border-amber-600 dark:text-white">
+            <div>
+                "The model wants to run " <code class="font-semibold">{call.name}</code>
+            </div>
+            <textarea
+                class="block w-full p-2 font-mono text-xs text-gray-900 bg-white rounded-lg border border-gray-300 dark:bg-gray-900 dark:border-gray-600 dark:text-white"
+                rows="3"
+                prop:value=arguments
+                on:input=move |ev| set_arguments.set(event_target_value(&ev))
+            />
+            <div class="flex items-center gap-2">
+                <label class="grow flex items-center gap-2">
+                    <input
+                        type="checkbox"
+                        prop:checked=always
+                        on:change=move |ev| set_always.set(event_target_checked(&ev))
+                    />
+                    "Always allow " {name}
+                </label>
+                <button
+                    type="button"
+                    class="px-3 py-1 font-medium rounded-lg border border-gray-300 hover:bg-gray-100 dark:border-gray-600 dark:hover:bg-gray-700"
+                    on:click=move |_| resolve(Approval::Reject)
+                >
+                    Reject
+                </button>
+                <button
+                    type="button"
+                    class="px-3 py-1 font-medium text-white bg-blue-600 rounded-lg hover:bg-blue-700"
+                    on:click=move |_| {
+                        resolve(Approval::Approve {
+                            arguments: arguments.get(),
+                            always: always.get(),
+                        })
+                    }
+                >
+                    Run
+                </button>
+            </div>
+            {move || {
+                error.get().map(|error| view! { <div class="text-red-600 dark:text-red-400">{error}</div> })
+            }}
+        </div>
+    }
+}
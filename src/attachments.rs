@@ -0,0 +1,146 @@
+use crate::app::invoke;
+use crate::state::Attachment;
+use leptos::*;
+use serde::Serialize;
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+
+/// The files the backend can read the text of.
+pub const DOCUMENTS: &str = ".pdf,text/*,.md,.csv,.json,.toml,.yaml,.yml,.rs,.py,.js,.ts,.go,.c,.h,.cpp,.java,.sh,.sql,.html,.css";
+
+#[derive(Serialize)]
+struct GetAttachments {
+    conversationid: u32,
+}
+
+#[derive(Serialize)]
+struct AttachFile {
+    conversationid: u32,
+    name: String,
+    data: Vec<u8>,
+}
+
+#[derive(Serialize)]
+struct DeleteAttachment {
+    attachmentid: u32,
+}
+
+/// Stores the text of the file in the conversation, it is sent with a
+/// message once its id is passed to `new_message`.
+pub async fn upload(conversationid: u32, file: web_sys::File) -> Result<Attachment, String> {
+    let name = file.name();
+    let buffer = JsFuture::from(file.array_buffer())
+        .await
+        .map_err(|_| format!("Could not read {name}"))?;
+    let data = js_sys::Uint8Array::new(&buffer).to_vec();
+    let args = serde_wasm_bindgen::to_value(&AttachFile {
+        conversationid,
+        name,
+        data,
+    })
+    .unwrap();
+    let value = invoke("attach_file", args)
+        .await
+        .map_err(|err| err.as_string().unwrap_or_default())?;
+    Ok(serde_wasm_bindgen::from_value(value).expect("Attachment"))
+}
+
+/// Deletes a file which was not sent yet.
+pub async fn delete(attachmentid: u32) -> Result<(), String> {
+    let args = serde_wasm_bindgen::to_value(&DeleteAttachment { attachmentid }).unwrap();
+    invoke("delete_attachment", args)
+        .await
+        .map(|_| ())
+        .map_err(|err| err.as_string().unwrap_or_default())
+}
+
+/// Files the model can read with its `read_attachment` tool.
+#[component]
+pub fn Attachments(conversationid: u32) -> impl IntoView {
+    let (error, set_error) = create_signal(None::<String>);
+    let attachments = create_resource(
+        || (),
+        move |_| async move {
+            let args = serde_wasm_bindgen::to_value(&GetAttachments { conversationid }).unwrap();
+            match invoke("get_attachments", args).await {
+                Ok(value) => {
+                    serde_wasm_bindgen::from_value::<Vec<Attachment>>(value).expect("Attachments")
+                }
+                Err(err) => {
+                    set_error.set(Some(err.as_string().unwrap_or_default()));
+                    vec![]
+                }
+            }
+        },
+    );
+
+    let attach = move |ev: ev::Event| {
+        let input = event_target::<web_sys::HtmlInputElement>(&ev);
+        let Some(files) = input.files() else {
+            return;
+        };
+        let files: Vec<_> = (0..files.length()).filter_map(|i| files.get(i)).collect();
+        // Picking the same file again should still fire a change.
+        input.set_value("");
+        set_error.set(None);
+        spawn_local(async move {
+            for file in files {
+                if let Err(err) = upload(conversationid, file).await {
+                    set_error.set(Some(err));
+                }
+            }
+            attachments.refetch();
+        });
+    };
+
+    let delete = move |attachmentid: u32| {
+        spawn_local(async move {
+            if let Err(err) = delete(attachmentid).await {
+                set_error.set(Some(err));
+            }
+            attachments.refetch();
+        });
+    };
+
+    view! {
+        <div class="flex flex-wrap items-center gap-2 px-3 py-1 text-sm">
+            {move || {
+                attachments
+                    .get()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|attachment| {
+                        let id = attachment.id;
+                        view! {
+                            <span class="inline-flex items-center gap-1 px-2 py-0.5 rounded-full bg-gray-100 text-gray-700 dark:bg-gray-700 dark:text-gray-300">
+                                {attachment.name}
+                                <button
+                                    type="button"
+                                    class="hover:text-red-600 dark:hover:text-red-400"
+                                    on:click=move |_| delete(id)
+                                >
+                                    "×"
+                                    <span class="sr-only">Remove attachment</span>
+                                </button>
+                            </span>
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            }}
+            <label class="cursor-pointer text-gray-500 hover:text-gray-900 dark:text-gray-400 dark:hover:text-white">
+                "Attach a file"
+                <input
+                    type="file"
+                    class="hidden"
+                    multiple
+                    accept=DOCUMENTS
+                    on:change=attach
+                />
+            </label>
+            {move || {
+                error
+                    .get()
+                    .map(|error| view! { <span class="text-red-600 dark:text-red-400">{error}</span> })
+            }}
+        </div>
+    }
+}
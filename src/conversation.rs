@@ -1,13 +1,18 @@
+use crate::app::Channel;
+use crate::approval::ToolApproval;
+use crate::attachments::{self, Attachments, DOCUMENTS};
+use crate::images::{self, Thumbnail};
 use crate::invoke;
 use crate::loading::Loading;
-use crate::message::{Message, Msg};
-use crate::state::{Message as DbMsg, User};
+use crate::message::{Message, Msg, Summary, ToolResult};
+use crate::settings::Settings;
+use crate::state::{Attachment, Image, Message as DbMsg, Metrics, Source, ToolCall, User};
 use chrono::Utc;
 use leptos::leptos_dom::ev::SubmitEvent;
 use leptos::logging::log;
 use leptos::*;
 use serde::{Deserialize, Serialize};
-use web_sys::window;
+use wasm_bindgen::prelude::*;
 
 #[derive(Serialize, Deserialize)]
 struct GetMessages {
@@ -19,6 +24,8 @@ struct NewMessage {
     conversationid: u32,
     content: String,
     authorid: u32,
+    images: Vec<Image>,
+    attachmentids: Vec<u32>,
 }
 
 #[derive(Serialize)]
@@ -26,6 +33,134 @@ struct Query {
     conversationid: u32,
 }
 
+#[derive(Serialize)]
+struct StartGeneration {
+    conversationid: u32,
+    #[serde(with = "serde_wasm_bindgen::preserve")]
+    channel: JsValue,
+}
+
+#[derive(Deserialize)]
+struct PendingToolCall {
+    message_id: u32,
+    tool_call: ToolCall,
+}
+
+#[derive(Serialize)]
+struct SelectBranch {
+    messageid: u32,
+}
+
+#[derive(Serialize)]
+struct EditMessage {
+    messageid: u32,
+    content: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "event", content = "data")]
+enum GenerationEvent {
+    Summarizing,
+    Summary {
+        message: DbMsg,
+    },
+    Context {
+        omitted: Vec<u32>,
+    },
+    Waiting {
+        kind: ErrorKind,
+        seconds: u64,
+    },
+    Start {
+        message_id: u32,
+        parent_id: Option<u32>,
+        sources: Option<Vec<Source>>,
+    },
+    Delta {
+        content: String,
+    },
+    Reasoning {
+        content: String,
+    },
+    Thought {
+        duration_ms: u64,
+    },
+    ToolCalls {
+        message_id: u32,
+        tool_calls: Vec<ToolCall>,
+    },
+    ToolCallRequest {
+        message_id: u32,
+        tool_call: ToolCall,
+    },
+    ToolResult {
+        message: DbMsg,
+    },
+    Done {
+        truncated: bool,
+        metrics: Option<Metrics>,
+    },
+    Error(GenerationError),
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum ErrorKind {
+    InvalidToken,
+    Unauthorized,
+    RateLimited,
+    ModelLoading,
+    ModelNotFound,
+    ContextTooLong,
+    Validation,
+    Other,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct GenerationError {
+    kind: ErrorKind,
+    message: String,
+}
+
+/// Why the backend is holding off, shown until the reply starts.
+fn waiting_message(kind: &ErrorKind, seconds: u64) -> String {
+    match kind {
+        ErrorKind::ModelLoading => format!("Waiting for model to load… ~{seconds}s"),
+        ErrorKind::RateLimited => format!("Rate limited, retrying in {seconds}s…"),
+        _ => format!("Endpoint unavailable, retrying in {seconds}s…"),
+    }
+}
+
+/// Moves summaries after the last message they cover, and marks the messages
+/// the latest one replaces.
+fn place_summaries(messages: &mut [Msg]) {
+    // Unsaved messages are the most recent.
+    messages.sort_by_key(|message| {
+        let position = message.summary_until.or(message.id).unwrap_or(u32::MAX);
+        (position, message.summary_until.is_some())
+    });
+    let latest = messages
+        .iter()
+        .filter_map(|message| message.summary_until)
+        .max();
+    for message in messages.iter_mut() {
+        message.summarized = match (message.summary_until, latest) {
+            (Some(until), Some(latest)) => until < latest,
+            (None, Some(latest)) => message.id.is_some_and(|id| id <= latest),
+            (_, None) => false,
+        };
+    }
+}
+
+impl GenerationError {
+    fn other(err: JsValue) -> Self {
+        Self {
+            kind: ErrorKind::Other,
+            message: err.as_string().unwrap_or_else(|| format!("{err:?}")),
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 struct ConvData {
     messages: Vec<Msg>,
@@ -33,10 +168,44 @@ struct ConvData {
     other: User,
 }
 
+/// Replaces the messages after `parent_id` with the new `message`, which
+/// becomes a sibling of the first one replaced. Summaries of the messages
+/// kept stay.
+fn branch_off(messages: &mut Vec<Msg>, parent_id: Option<u32>, mut message: Msg) {
+    let is_text = |message: &Msg| message.summary_until.is_none();
+    let after = match parent_id {
+        Some(parent_id) => messages
+            .iter()
+            .position(|message| is_text(message) && message.id == Some(parent_id))
+            // Not shown, nothing is replaced.
+            .map_or(messages.len(), |i| i + 1),
+        None => 0,
+    };
+    if let Some(i) = messages.iter().skip(after).position(is_text) {
+        let replaced = &messages[after + i];
+        message.siblings = if replaced.siblings.is_empty() {
+            replaced.id.into_iter().collect()
+        } else {
+            replaced.siblings.clone()
+        };
+        message.siblings.extend(message.id);
+        messages.truncate(after + i);
+    }
+    messages.push(message);
+}
+
+#[derive(Serialize, Deserialize)]
+struct Branch {
+    message_id: u32,
+    siblings: Vec<u32>,
+}
+
 #[derive(Serialize, Deserialize)]
 struct DbConvData {
     messages: Vec<DbMsg>,
     users: Vec<User>,
+    branches: Vec<Branch>,
+    attachments: Vec<Attachment>,
 }
 
 #[component]
@@ -50,6 +219,35 @@ pub fn Conversation(conversationid: u32, me: u32, model: u32) -> impl IntoView {
         }
     });
     let (message, set_message) = create_signal(String::new());
+    let (error, set_error) = create_signal(None::<GenerationError>);
+    let (generating, set_generating) = create_signal(false);
+    let (waiting, set_waiting) = create_signal(None::<String>);
+    // Pictures uploaded for the next message.
+    let (pictures, set_pictures) = create_signal(Vec::<Image>::new());
+    // Documents attached to the next message.
+    let (documents, set_documents) = create_signal(Vec::<Attachment>::new());
+    // Tool calls waiting for the user, with the message calling them.
+    let (pending, set_pending) = create_signal(Vec::<(u32, ToolCall)>::new());
+    // Calls asked for before the view was opened still wait for an answer.
+    spawn_local(async move {
+        let args = serde_wasm_bindgen::to_value(&Query { conversationid }).unwrap();
+        match invoke("get_pending_tool_calls", args).await {
+            Ok(value) => {
+                let calls: Vec<PendingToolCall> =
+                    serde_wasm_bindgen::from_value(value).expect("Pending tool calls");
+                set_pending.update(|pending| {
+                    for call in calls {
+                        let key = (call.message_id, &call.tool_call.id);
+                        if !pending.iter().any(|(id, known)| (*id, &known.id) == key) {
+                            pending.push((call.message_id, call.tool_call));
+                        }
+                    }
+                });
+            }
+            Err(err) => log!("Could not get the pending tool calls {err:?}"),
+        }
+    });
+    let (settings_open, set_settings_open) = create_signal(false);
     let convdata = create_resource(
         move || (),
         move |_| async move {
@@ -66,7 +264,7 @@ pub fn Conversation(conversationid: u32, me: u32, model: u32) -> impl IntoView {
                 .find(|u| u.id == model)
                 .expect("Other");
 
-            let messages = convdata
+            let mut messages: Vec<Msg> = convdata
                 .messages
                 .into_iter()
                 .map(|message| {
@@ -76,14 +274,44 @@ pub fn Conversation(conversationid: u32, me: u32, model: u32) -> impl IntoView {
                     } else {
                         other.clone()
                     };
+                    let siblings = convdata
+                        .branches
+                        .iter()
+                        .find(|branch| branch.message_id == message.id)
+                        .map(|branch| branch.siblings.clone())
+                        .unwrap_or_default();
                     Msg {
+                        id: Some(message.id),
                         created_at: message.created_at,
                         content: message.content,
+                        truncated: message.truncated,
+                        omitted: false,
+                        summary_until: message.summary_until,
+                        summarized: false,
+                        siblings,
+                        metrics: message.metrics,
+                        tool_calls: message.tool_calls.unwrap_or_default(),
+                        tool_call_id: message.tool_call_id,
+                        images: message.images.unwrap_or_default(),
+                        attachments: convdata
+                            .attachments
+                            .iter()
+                            .filter(|attachment| attachment.message_id == Some(message.id))
+                            .cloned()
+                            .collect(),
+                        sources: message.sources.unwrap_or_default(),
+                        reasoning: message
+                            .reasoning
+                            .as_ref()
+                            .map(|reasoning| reasoning.content.clone())
+                            .unwrap_or_default(),
+                        reasoning_ms: message.reasoning.map(|reasoning| reasoning.duration_ms),
                         is_me,
                         user,
                     }
                 })
                 .collect();
+            place_summaries(&mut messages);
 
             ConvData {
                 messages,
@@ -97,69 +325,428 @@ pub fn Conversation(conversationid: u32, me: u32, model: u32) -> impl IntoView {
         let v = event_target_value(&ev);
         set_message.set(v);
     };
+    // Retrying repeats whichever of `start_generation` or `regenerate` failed.
+    let (command, set_command) = create_signal("start_generation");
+    // The channel's callback, kept until the generation is over.
+    let on_event = store_value(None::<Closure<dyn FnMut(JsValue)>>);
+    // Not from inside the callback itself, it is dropped once it returned.
+    let release = move || spawn_local(async move { on_event.set_value(None) });
+    on_cleanup(move || {
+        on_event.try_set_value(None);
+    });
+    // The reply is pushed by the backend through the channel.
+    let generate = move |cmd: &'static str| {
+        set_command.set(cmd);
+        set_generating.set(true);
+        set_error.set(None);
+        spawn_local(async move {
+            let channel = Channel::new();
+            let callback = Closure::<dyn FnMut(JsValue)>::new(move |value: JsValue| {
+                let event: GenerationEvent =
+                    serde_wasm_bindgen::from_value(value).expect("Generation event");
+                if !matches!(
+                    event,
+                    GenerationEvent::Context { .. }
+                        | GenerationEvent::Delta { .. }
+                        | GenerationEvent::Reasoning { .. }
+                ) {
+                    set_waiting.set(None);
+                }
+                match event {
+                    GenerationEvent::Summarizing => {
+                        set_waiting.set(Some("Summarizing earlier messages…".to_string()));
+                    }
+                    GenerationEvent::Summary { message } => {
+                        convdata.update(|convdata| {
+                            if let Some(convdata) = convdata.as_mut() {
+                                convdata.messages.push(Msg {
+                                    id: Some(message.id),
+                                    created_at: message.created_at,
+                                    user: convdata.other.clone(),
+                                    is_me: false,
+                                    content: message.content,
+                                    truncated: false,
+                                    omitted: false,
+                                    summary_until: message.summary_until,
+                                    summarized: false,
+                                    siblings: vec![],
+                                    metrics: None,
+                                    tool_calls: vec![],
+                                    tool_call_id: None,
+                                    images: vec![],
+                                    attachments: vec![],
+                                    sources: vec![],
+                                    reasoning: String::new(),
+                                    reasoning_ms: None,
+                                });
+                                place_summaries(&mut convdata.messages);
+                            }
+                        });
+                    }
+                    GenerationEvent::Context { omitted } => {
+                        convdata.update(|convdata| {
+                            if let Some(convdata) = convdata.as_mut() {
+                                for message in &mut convdata.messages {
+                                    message.omitted =
+                                        message.id.is_some_and(|id| omitted.contains(&id));
+                                }
+                            }
+                        });
+                    }
+                    GenerationEvent::Waiting { kind, seconds } => {
+                        set_waiting.set(Some(waiting_message(&kind, seconds)));
+                    }
+                    GenerationEvent::Start {
+                        message_id,
+                        parent_id,
+                        sources,
+                    } => {
+                        convdata.update(|convdata| {
+                            if let Some(convdata) = convdata.as_mut() {
+                                let reply = Msg {
+                                    id: Some(message_id),
+                                    created_at: Utc::now(),
+                                    user: convdata.other.clone(),
+                                    is_me: false,
+                                    content: String::new(),
+                                    truncated: false,
+                                    omitted: false,
+                                    summary_until: None,
+                                    summarized: false,
+                                    siblings: vec![],
+                                    metrics: None,
+                                    tool_calls: vec![],
+                                    tool_call_id: None,
+                                    images: vec![],
+                                    attachments: vec![],
+                                    sources: sources.unwrap_or_default(),
+                                    reasoning: String::new(),
+                                    reasoning_ms: None,
+                                };
+                                branch_off(&mut convdata.messages, parent_id, reply);
+                            }
+                        });
+                    }
+                    GenerationEvent::Delta { content } => {
+                        convdata.update(|convdata| {
+                            if let Some(message) = convdata
+                                .as_mut()
+                                .and_then(|convdata| convdata.messages.last_mut())
+                            {
+                                message.content.push_str(&content);
+                            }
+                        });
+                    }
+                    GenerationEvent::Reasoning { content } => {
+                        convdata.update(|convdata| {
+                            if let Some(message) = convdata
+                                .as_mut()
+                                .and_then(|convdata| convdata.messages.last_mut())
+                            {
+                                message.reasoning.push_str(&content);
+                            }
+                        });
+                    }
+                    GenerationEvent::Thought { duration_ms } => {
+                        convdata.update(|convdata| {
+                            if let Some(message) = convdata
+                                .as_mut()
+                                .and_then(|convdata| convdata.messages.last_mut())
+                            {
+                                message.reasoning_ms = Some(duration_ms);
+                            }
+                        });
+                    }
+                    GenerationEvent::ToolCalls {
+                        message_id,
+                        tool_calls,
+                    } => {
+                        convdata.update(|convdata| {
+                            if let Some(message) = convdata.as_mut().and_then(|convdata| {
+                                convdata
+                                    .messages
+                                    .iter_mut()
+                                    .find(|message| message.id == Some(message_id))
+                            }) {
+                                message.tool_calls = tool_calls;
+                            }
+                        });
+                    }
+                    GenerationEvent::ToolCallRequest {
+                        message_id,
+                        tool_call,
+                    } => {
+                        set_pending.update(|pending| pending.push((message_id, tool_call)));
+                    }
+                    GenerationEvent::ToolResult { message } => {
+                        convdata.update(|convdata| {
+                            if let Some(convdata) = convdata.as_mut() {
+                                convdata.messages.push(Msg {
+                                    id: Some(message.id),
+                                    created_at: message.created_at,
+                                    user: convdata.other.clone(),
+                                    is_me: false,
+                                    content: message.content,
+                                    truncated: false,
+                                    omitted: false,
+                                    summary_until: None,
+                                    summarized: false,
+                                    siblings: vec![],
+                                    metrics: None,
+                                    tool_calls: vec![],
+                                    tool_call_id: message.tool_call_id,
+                                    images: vec![],
+                                    attachments: vec![],
+                                    sources: vec![],
+                                    reasoning: String::new(),
+                                    reasoning_ms: None,
+                                });
+                            }
+                        });
+                    }
+                    GenerationEvent::Done { truncated, metrics } => {
+                        log!("Generation done");
+                        set_generating.set(false);
+                        set_pending.set(vec![]);
+                        release();
+                        convdata.update(|convdata| {
+                            if let Some(message) = convdata
+                                .as_mut()
+                                .and_then(|convdata| convdata.messages.last_mut())
+                                .filter(|message| !message.is_me)
+                            {
+                                // Without a new reply, the previous one is untouched.
+                                message.truncated |= truncated;
+                                if metrics.is_some() {
+                                    message.metrics = metrics;
+                                }
+                            }
+                        });
+                    }
+                    GenerationEvent::Error(error) => {
+                        set_generating.set(false);
+                        set_pending.set(vec![]);
+                        set_error.set(Some(error));
+                        release();
+                    }
+                }
+            });
+            channel.set_onmessage(callback.as_ref().unchecked_ref());
+            // The channel outlives this future, so does its callback.
+            on_event.set_value(Some(callback));
+
+            let args = serde_wasm_bindgen::to_value(&StartGeneration {
+                conversationid,
+                channel: channel.into(),
+            })
+            .unwrap();
+            if let Err(err) = invoke(cmd, args).await {
+                set_generating.set(false);
+                set_waiting.set(None);
+                set_error.set(Some(GenerationError::other(err)));
+                on_event.set_value(None);
+            }
+        });
+    };
+    let start_generation = move || generate("start_generation");
+    let regenerate = Callback::new(move |_| generate("regenerate"));
+    // The rest of the branch changes too, it is loaded again.
+    let select_branch = Callback::new(move |messageid: u32| {
+        spawn_local(async move {
+            let args = serde_wasm_bindgen::to_value(&SelectBranch { messageid }).unwrap();
+            match invoke("select_branch", args).await {
+                Ok(_) => convdata.refetch(),
+                Err(err) => set_error.set(Some(GenerationError::other(err))),
+            }
+        });
+    });
+    let edit_message = move |messageid: u32, content: String| {
+        if generating.get_untracked() {
+            return;
+        }
+        set_generating.set(true);
+        spawn_local(async move {
+            let args = serde_wasm_bindgen::to_value(&EditMessage {
+                messageid,
+                content: content.clone(),
+            })
+            .unwrap();
+            let edited = match invoke("edit_message", args).await {
+                Ok(edited) => {
+                    serde_wasm_bindgen::from_value::<DbMsg>(edited).expect("Edited message")
+                }
+                Err(err) => {
+                    set_generating.set(false);
+                    set_error.set(Some(GenerationError::other(err)));
+                    return;
+                }
+            };
+            convdata.update(|convdata| {
+                if let Some(convdata) = convdata.as_mut() {
+                    let parent_id = convdata
+                        .messages
+                        .iter()
+                        .take_while(|message| message.id != Some(messageid))
+                        .filter(|message| message.summary_until.is_none())
+                        .last()
+                        .and_then(|message| message.id);
+                    // The backend copied the documents to the new version.
+                    let attachments = convdata
+                        .messages
+                        .iter()
+                        .find(|message| message.id == Some(messageid))
+                        .map(|message| message.attachments.clone())
+                        .unwrap_or_default();
+                    let message = Msg {
+                        id: Some(edited.id),
+                        created_at: edited.created_at,
+                        user: convdata.me.clone(),
+                        is_me: true,
+                        content,
+                        truncated: false,
+                        omitted: false,
+                        summary_until: None,
+                        summarized: false,
+                        siblings: vec![],
+                        metrics: None,
+                        tool_calls: vec![],
+                        tool_call_id: None,
+                        images: edited.images.unwrap_or_default(),
+                        attachments,
+                        sources: vec![],
+                        reasoning: String::new(),
+                        reasoning_ms: None,
+                    };
+                    branch_off(&mut convdata.messages, parent_id, message);
+                }
+            });
+            start_generation();
+        });
+    };
     let send_message = move |ev: SubmitEvent| {
         ev.prevent_default();
+        if generating.get() {
+            return;
+        }
+        set_generating.set(true);
         let content = message.get();
+        let images = pictures.get();
+        let attached = documents.get();
+        let attachmentids = attached.iter().map(|document| document.id).collect();
         spawn_local(async move {
             let args = serde_wasm_bindgen::to_value(&NewMessage {
                 conversationid,
-                content,
+                content: content.clone(),
                 authorid: me,
+                images: images.clone(),
+                attachmentids,
             })
             .unwrap();
-            invoke("new_message", args).await.unwrap();
-
-            let args = Query { conversationid };
-            loop {
-                let arg = serde_wasm_bindgen::to_value(&args).unwrap();
-                let res = invoke("get_chunk", arg).await;
-                if res.is_err() {
-                    window().unwrap().location().reload().unwrap();
-                }
-                let res = res.unwrap();
-                let chunk: Option<String> = serde_wasm_bindgen::from_value(res).expect("Chunk");
-                if let Some(chunk) = chunk {
+            let value = match invoke("new_message", args).await {
+                Ok(value) => value,
+                Err(err) => {
+                    // Nothing was stored, the draft is given back to send again.
                     convdata.update(|convdata| {
-                        convdata.as_mut().map(|convdata| {
-                            let user = convdata.other.clone();
-                            if let Some(message) = convdata.messages.last_mut() {
-                                if !message.is_me {
-                                    message.content.push_str(&chunk);
-                                } else {
-                                    convdata.messages.push(Msg {
-                                        created_at: Utc::now(),
-                                        user,
-                                        is_me: false,
-                                        content: chunk,
-                                    })
-                                }
-                            } else {
-                                convdata.messages.push(Msg {
-                                    created_at: Utc::now(),
-                                    user,
-                                    is_me: false,
-                                    content: chunk,
-                                })
-                            }
-                        });
+                        if let Some(convdata) = convdata.as_mut() {
+                            convdata.messages.retain(|message| message.id.is_some());
+                        }
                     });
-                } else {
-                    break;
+                    set_message.set(content);
+                    set_pictures.set(images);
+                    set_documents.set(attached);
+                    set_error.set(Some(GenerationError {
+                        kind: ErrorKind::Validation,
+                        ..GenerationError::other(err)
+                    }));
+                    set_generating.set(false);
+                    return;
                 }
-            }
+            };
+            let sent: DbMsg = serde_wasm_bindgen::from_value(value).expect("Sent message");
+            convdata.update(|convdata| {
+                if let Some(message) = convdata.as_mut().and_then(|convdata| {
+                    convdata
+                        .messages
+                        .iter_mut()
+                        .rev()
+                        .find(|message| message.id.is_none())
+                }) {
+                    message.id = Some(sent.id);
+                }
+            });
+
+            start_generation();
         });
         log!("Inserting new message");
+        set_error.set(None);
         convdata.update(|convdata| {
             convdata.as_mut().map(|convdata| {
                 convdata.messages.push(Msg {
+                    id: None,
                     created_at: Utc::now(),
                     user: convdata.me.clone(),
                     is_me: true,
+                    truncated: false,
+                    omitted: false,
+                    summary_until: None,
+                    summarized: false,
+                    siblings: vec![],
+                    metrics: None,
+                    tool_calls: vec![],
+                    tool_call_id: None,
+                    images: pictures.get(),
+                    attachments: documents.get(),
+                    sources: vec![],
+                    reasoning: String::new(),
+                    reasoning_ms: None,
                     content: message.get(),
                 })
             });
         });
         set_message.set(String::new());
+        set_pictures.set(vec![]);
+        set_documents.set(vec![]);
+    };
+    // Pictures go to the model as they are, other files as their text.
+    let attach = move |files: web_sys::FileList| {
+        let files: Vec<_> = (0..files.length()).filter_map(|i| files.get(i)).collect();
+        spawn_local(async move {
+            for file in files {
+                if file.type_().starts_with("image/") {
+                    match images::upload(file).await {
+                        Ok(image) => set_pictures.update(|pictures| pictures.push(image)),
+                        Err(err) => set_error.set(Some(GenerationError::other(err.into()))),
+                    }
+                } else {
+                    match attachments::upload(conversationid, file).await {
+                        Ok(document) => set_documents.update(|documents| documents.push(document)),
+                        Err(err) => set_error.set(Some(GenerationError::other(err.into()))),
+                    }
+                }
+            }
+        });
+    };
+    let attach_files = move |ev: ev::Event| {
+        let input = event_target::<web_sys::HtmlInputElement>(&ev);
+        if let Some(files) = input.files() {
+            attach(files);
+        }
+        // Picking the same file again should still fire a change.
+        input.set_value("");
+    };
+    let drop_files = move |ev: ev::DragEvent| {
+        ev.prevent_default();
+        if let Some(files) = ev.data_transfer().and_then(|data| data.files()) {
+            attach(files);
+        }
+    };
+    let stop_generation = move |_| {
+        spawn_local(async move {
+            let args = serde_wasm_bindgen::to_value(&Query { conversationid }).unwrap();
+            if let Err(err) = invoke("cancel_generation", args).await {
+                set_error.set(Some(GenerationError::other(err)));
+            }
+        });
     };
 
     view! {
@@ -172,13 +759,37 @@ pub fn Conversation(conversationid: u32, me: u32, model: u32) -> impl IntoView {
                         convdata
                             .get()
                             .map(|convdata| {
+                                let last = convdata.messages.len().saturating_sub(1);
                                 convdata
                                     .messages
                                     .into_iter()
+                                    .enumerate()
                                     .rev()
-                                    .map(|message| {
-
-                                        view! { <Message message=message /> }
+                                    .map(|(i, message)| {
+                                        if message.summary_until.is_some() {
+                                            return view! { <Summary message=message /> }.into_view();
+                                        }
+                                        if message.tool_call_id.is_some() {
+                                            return view! { <ToolResult message=message /> }.into_view();
+                                        }
+                                        let idle = !generating.get();
+                                        let on_regenerate = (i == last && !message.is_me && idle)
+                                            .then_some(regenerate);
+                                        let on_edit = message
+                                            .id
+                                            .filter(|_| message.is_me && idle)
+                                            .map(|messageid| {
+                                                Callback::new(move |content| edit_message(messageid, content))
+                                            });
+                                        view! {
+                                            <Message
+                                                message
+                                                on_select=Some(select_branch)
+                                                on_regenerate
+                                                on_edit
+                                            />
+                                        }
+                                            .into_view()
                                     })
                                     .collect::<Vec<_>>()
                             })
@@ -186,7 +797,163 @@ pub fn Conversation(conversationid: u32, me: u32, model: u32) -> impl IntoView {
                 </Suspense>
 
             </main>
-            <form class="w-full" on:submit=send_message>
+            {move || {
+                waiting
+                    .get()
+                    .map(|waiting| {
+                        view! {
+                            <div class="px-3 py-2 text-sm text-gray-500 dark:text-gray-400 animate-pulse">
+                                {waiting}
+                            </div>
+                        }
+                    })
+            }}
+            <For
+                each=move || pending.get()
+                key=|(message_id, call)| (*message_id, call.id.clone())
+                children=move |(message_id, call)| {
+                    let callid = call.id.clone();
+                    let on_resolved = Callback::new(move |_| {
+                        set_pending
+                            .update(|pending| {
+                                pending.retain(|(id, call)| (*id, &call.id) != (message_id, &callid))
+                            })
+                    });
+                    view! { <ToolApproval message_id call on_resolved /> }
+                }
+            />
+            {move || {
+                error
+                    .get()
+                    .map(|error| {
+                        let action = match error.kind {
+                            ErrorKind::InvalidToken => {
+                                view! {
+                                    <button
+                                        type="button"
+                                        class="px-3 py-1 font-medium rounded-lg border border-red-600 hover:bg-red-100 dark:border-red-400 dark:hover:bg-gray-700"
+                                        on:click=move |_| {
+                                            window().location().reload().ok();
+                                        }
+                                    >
+                                        Log in again
+                                    </button>
+                                }
+                                    .into_view()
+                            }
+                            ErrorKind::RateLimited | ErrorKind::ModelLoading | ErrorKind::Other => {
+                                view! {
+                                    <button
+                                        type="button"
+                                        class="px-3 py-1 font-medium rounded-lg border border-red-600 hover:bg-red-100 dark:border-red-400 dark:hover:bg-gray-700"
+                                        on:click=move |_| generate(command.get_untracked())
+                                    >
+                                        Retry
+                                    </button>
+                                }
+                                    .into_view()
+                            }
+                            ErrorKind::Unauthorized
+                            | ErrorKind::ModelNotFound
+                            | ErrorKind::ContextTooLong
+                            | ErrorKind::Validation => ().into_view(),
+                        };
+                        view! {
+                            <div class="flex items-center gap-2 px-3 py-2 text-sm text-red-600 dark:text-red-400">
+                                <span class="grow">{error.message}</span>
+                                {action}
+                            </div>
+                        }
+                    })
+            }}
+            {move || {
+                settings_open
+                    .get()
+                    .then(|| view! { <Settings conversationid set_open=set_settings_open /> })
+            }}
+            <Attachments conversationid />
+            {move || {
+                let pictures = pictures.get();
+                (!pictures.is_empty())
+                    .then(|| {
+                        view! {
+                            <div class="flex flex-wrap gap-2 px-3 py-1">
+                                {pictures
+                                    .into_iter()
+                                    .enumerate()
+                                    .map(|(i, image)| {
+                                        view! {
+                                            <div class="relative">
+                                                <Thumbnail image />
+                                                <button
+                                                    type="button"
+                                                    class="absolute top-1 right-1 px-1 text-xs rounded-full bg-white/80 hover:text-red-600 dark:bg-gray-800/80 dark:text-white"
+                                                    on:click=move |_| {
+                                                        set_pictures.update(|pictures| {
+                                                            pictures.remove(i);
+                                                        })
+                                                    }
+                                                >
+                                                    "×"
+                                                    <span class="sr-only">Remove picture</span>
+                                                </button>
+                                            </div>
+                                        }
+                                    })
+                                    .collect_view()}
+                            </div>
+                        }
+                    })
+            }}
+            {move || {
+                let documents = documents.get();
+                (!documents.is_empty())
+                    .then(|| {
+                        view! {
+                            <div class="flex flex-wrap gap-2 px-3 py-1 text-sm">
+                                {documents
+                                    .into_iter()
+                                    .map(|document| {
+                                        let id = document.id;
+                                        view! {
+                                            <span class="inline-flex items-center gap-1 px-2 py-0.5 rounded-full bg-gray-100 text-gray-700 dark:bg-gray-700 dark:text-gray-300">
+                                                {document.name}
+                                                <button
+                                                    type="button"
+                                                    class="hover:text-red-600 dark:hover:text-red-400"
+                                                    on:click=move |_| {
+                                                        spawn_local(async move {
+                                                            match attachments::delete(id).await {
+                                                                Ok(()) => {
+                                                                    set_documents
+                                                                        .update(|documents| {
+                                                                            documents.retain(|document| document.id != id)
+                                                                        })
+                                                                }
+                                                                Err(err) => {
+                                                                    set_error.set(Some(GenerationError::other(err.into())))
+                                                                }
+                                                            }
+                                                        })
+                                                    }
+                                                >
+                                                    "×"
+                                                    <span class="sr-only">Remove document</span>
+                                                </button>
+                                            </span>
+                                        }
+                                    })
+                                    .collect_view()}
+                            </div>
+                        }
+                    })
+            }}
+            <form
+                class="w-full"
+                on:submit=send_message
+                on:dragover=|ev| ev.prevent_default()
+                on:drop=drop_files
+            >
                 <label for="chat" class="sr-only">
                     Your message
                 </label>
@@ -194,7 +961,32 @@ pub fn Conversation(conversationid: u32, me: u32, model: u32) -> impl IntoView {
                     <button
                         type="button"
                         class="inline-flex justify-center p-2 text-gray-500 rounded-lg cursor-pointer hover:text-gray-900 hover:bg-gray-100 dark:text-gray-400 dark:hover:text-white dark:hover:bg-gray-600"
+                        on:click=move |_| set_settings_open.update(|open| *open = !*open)
                     >
+                        <svg
+                            class="w-5 h-5"
+                            aria-hidden="true"
+                            xmlns="http://www.w3.org/2000/svg"
+                            fill="none"
+                            viewBox="0 0 24 24"
+                            stroke="currentColor"
+                            stroke-width="2"
+                            stroke-linecap="round"
+                            stroke-linejoin="round"
+                        >
+                            <circle cx="12" cy="12" r="3" />
+                            <path d="M19.4 15a1.65 1.65 0 0 0 .33 1.82l.06.06a2 2 0 1 1-2.83 2.83l-.06-.06a1.65 1.65 0 0 0-1.82-.33 1.65 1.65 0 0 0-1 1.51V21a2 2 0 1 1-4 0v-.09A1.65 1.65 0 0 0 9 19.4a1.65 1.65 0 0 0-1.82.33l-.06.06a2 2 0 1 1-2.83-2.83l.06-.06A1.65 1.65 0 0 0 4.6 15a1.65 1.65 0 0 0-1.51-1H3a2 2 0 1 1 0-4h.09A1.65 1.65 0 0 0 4.6 9a1.65 1.65 0 0 0-.33-1.82l-.06-.06a2 2 0 1 1 2.83-2.83l.06.06A1.65 1.65 0 0 0 9 4.6a1.65 1.65 0 0 0 1-1.51V3a2 2 0 1 1 4 0v.09a1.65 1.65 0 0 0 1 1.51 1.65 1.65 0 0 0 1.82-.33l.06-.06a2 2 0 1 1 2.83 2.83l-.06.06A1.65 1.65 0 0 0 19.4 9a1.65 1.65 0 0 0 1.51 1H21a2 2 0 1 1 0 4h-.09a1.65 1.65 0 0 0-1.51 1Z" />
+                        </svg>
+                        <span class="sr-only">Conversation settings</span>
+                    </button>
+                    <label class="inline-flex justify-center p-2 text-gray-500 rounded-lg cursor-pointer hover:text-gray-900 hover:bg-gray-100 dark:text-gray-400 dark:hover:text-white dark:hover:bg-gray-600">
+                        <input
+                            type="file"
+                            class="hidden"
+                            multiple
+                            accept="image/png,image/jpeg,image/gif,image/webp"
+                            on:change=attach_files
+                        />
                         <svg
                             class="w-5 h-5"
                             aria-hidden="true"
@@ -222,7 +1014,31 @@ pub fn Conversation(conversationid: u32, me: u32, model: u32) -> impl IntoView {
                             />
                         </svg>
                         <span class="sr-only">Upload image</span>
-                    </button>
+                    </label>
+                    <label class="inline-flex justify-center p-2 text-gray-500 rounded-lg cursor-pointer hover:text-gray-900 hover:bg-gray-100 dark:text-gray-400 dark:hover:text-white dark:hover:bg-gray-600">
+                        <input
+                            type="file"
+                            class="hidden"
+                            multiple
+                            accept=DOCUMENTS
+                            on:change=attach_files
+                        />
+                        <svg
+                            class="w-5 h-5"
+                            aria-hidden="true"
+                            xmlns="http://www.w3.org/2000/svg"
+                            fill="none"
+                            viewBox="0 0 24 24"
+                            stroke="currentColor"
+                            stroke-width="2"
+                            stroke-linecap="round"
+                            stroke-linejoin="round"
+                        >
+                            <path d="M14 2H6a2 2 0 0 0-2 2v16a2 2 0 0 0 2 2h12a2 2 0 0 0 2-2V8z" />
+                            <path d="M14 2v6h6M16 13H8M16 17H8M10 9H8" />
+                        </svg>
+                        <span class="sr-only">Attach a document</span>
+                    </label>
                     <button
                         type="button"
                         class="p-2 text-gray-500 rounded-lg cursor-pointer hover:text-gray-900 hover:bg-gray-100 dark:text-gray-400 dark:hover:text-white dark:hover:bg-gray-600"
@@ -253,21 +1069,46 @@ pub fn Conversation(conversationid: u32, me: u32, model: u32) -> impl IntoView {
                         on:input=update_message
                         prop:value=message
                     />
-                    <button
-                        type="submit"
-                        class="inline-flex justify-center p-2 text-blue-600 rounded-full cursor-pointer hover:bg-blue-100 dark:text-blue-500 dark:hover:bg-gray-600"
-                    >
-                        <svg
-                            class="w-5 h-5 rotate-90 rtl:-rotate-90"
-                            aria-hidden="true"
-                            xmlns="http://www.w3.org/2000/svg"
-                            fill="currentColor"
-                            viewBox="0 0 18 20"
-                        >
-                            <path d="m17.914 18.594-8-18a1 1 0 0 0-1.828 0l-8 18a1 1 0 0 0 1.157 1.376L8 18.281V9a1 1 0 0 1 2 0v9.281l6.758 1.689a1 1 0 0 0 1.156-1.376Z" />
-                        </svg>
-                        <span class="sr-only">Send message</span>
-                    </button>
+                    {move || {
+                        if generating.get() {
+                            view! {
+                                <button
+                                    type="button"
+                                    class="inline-flex justify-center p-2 text-red-600 rounded-full cursor-pointer hover:bg-red-100 dark:text-red-500 dark:hover:bg-gray-600"
+                                    on:click=stop_generation
+                                >
+                                    <svg
+                                        class="w-5 h-5"
+                                        aria-hidden="true"
+                                        xmlns="http://www.w3.org/2000/svg"
+                                        fill="currentColor"
+                                        viewBox="0 0 20 20"
+                                    >
+                                        <rect x="4" y="4" width="12" height="12" rx="2" />
+                                    </svg>
+                                    <span class="sr-only">Stop generating</span>
+                                </button>
+                            }
+                        } else {
+                            view! {
+                                <button
+                                    type="submit"
+                                    class="inline-flex justify-center p-2 text-blue-600 rounded-full cursor-pointer hover:bg-blue-100 dark:text-blue-500 dark:hover:bg-gray-600"
+                                >
+                                    <svg
+                                        class="w-5 h-5 rotate-90 rtl:-rotate-90"
+                                        aria-hidden="true"
+                                        xmlns="http://www.w3.org/2000/svg"
+                                        fill="currentColor"
+                                        viewBox="0 0 18 20"
+                                    >
+                                        <path d="m17.914 18.594-8-18a1 1 0 0 0-1.828 0l-8 18a1 1 0 0 0 1.157 1.376L8 18.281V9a1 1 0 0 1 2 0v9.281l6.758 1.689a1 1 0 0 0 1.156-1.376Z" />
+                                    </svg>
+                                    <span class="sr-only">Send message</span>
+                                </button>
+                            }
+                        }
+                    }}
                 </div>
             </form>
 
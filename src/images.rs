@@ -0,0 +1,56 @@
+use crate::app::invoke;
+use crate::state::Image;
+use leptos::*;
+use serde::Serialize;
+use wasm_bindgen_futures::JsFuture;
+
+#[derive(Serialize)]
+struct UploadImage {
+    data: Vec<u8>,
+}
+
+#[derive(Serialize)]
+struct GetImage {
+    hash: String,
+}
+
+/// Stores the picture in the app's cache, the message only refers to it.
+pub async fn upload(file: web_sys::File) -> Result<Image, String> {
+    let buffer = JsFuture::from(file.array_buffer())
+        .await
+        .map_err(|_| format!("Could not read {}", file.name()))?;
+    let data = js_sys::Uint8Array::new(&buffer).to_vec();
+    let args = serde_wasm_bindgen::to_value(&UploadImage { data }).unwrap();
+    let value = invoke("upload_image", args)
+        .await
+        .map_err(|err| err.as_string().unwrap_or_default())?;
+    Ok(serde_wasm_bindgen::from_value(value).expect("Image"))
+}
+
+#[component]
+pub fn Thumbnail(image: Image) -> impl IntoView {
+    let hash = image.hash;
+    let src = create_resource(
+        || (),
+        move |_| {
+            let hash = hash.clone();
+            async move {
+                let args = serde_wasm_bindgen::to_value(&GetImage { hash }).unwrap();
+                invoke("get_image", args).await.ok()?.as_string()
+            }
+        },
+    );
+    view! {
+        {move || {
+            src.get()
+                .flatten()
+                .map(|src| {
+                    view! {
+                        <a href=src.clone() target="_blank">
+                            <img class="h-24 max-w-48 object-cover rounded-lg" src=src alt="Attached picture" />
+                        </a>
+                    }
+                })
+        }}
+    }
+}
@@ -1,10 +1,14 @@
 mod app;
+mod approval;
+mod attachments;
 mod conversation;
 mod html;
+mod images;
 mod loading;
 mod login;
 mod message;
 mod nav;
+mod settings;
 mod state;
 
 use app::*;
@@ -1,6 +1,7 @@
 use crate::app::invoke;
 use crate::asset;
-use crate::state::User;
+use crate::images::Thumbnail;
+use crate::state::{Attachment, Image, Message as DbMsg, Metrics, Source, ToolCall, User};
 use chrono::{DateTime, Local, Utc};
 use leptos::logging::log;
 use leptos::IntoView;
@@ -11,10 +12,34 @@ use wasm_bindgen_futures::spawn_local;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Msg {
+    pub id: Option<u32>,
     pub content: String,
     pub user: User,
     pub is_me: bool,
     pub created_at: DateTime<Utc>,
+    pub truncated: bool,
+    /// Left out of the last prompt to fit the model's context.
+    pub omitted: bool,
+    /// Set on summaries, the last message they stand in for.
+    pub summary_until: Option<u32>,
+    /// Replaced in prompts by a summary.
+    pub summarized: bool,
+    /// The messages of other branches this one can be swapped with, itself
+    /// included. Empty when there are none.
+    pub siblings: Vec<u32>,
+    pub metrics: Option<Metrics>,
+    pub tool_calls: Vec<ToolCall>,
+    /// Set on tool results.
+    pub tool_call_id: Option<String>,
+    pub images: Vec<Image>,
+    /// Documents sent with the message, their text is in the prompt.
+    pub attachments: Vec<Attachment>,
+    /// Knowledge base excerpts the reply was given, cited as [1], [2]...
+    pub sources: Vec<Source>,
+    /// What a reasoning model thought before answering, never sent back to it.
+    pub reasoning: String,
+    /// How long it thought, `None` while it still does.
+    pub reasoning_ms: Option<u64>,
 }
 
 #[derive(Debug, Clone)]
@@ -29,18 +54,57 @@ struct PlayMessage {
     content: String,
 }
 
-#[component]
-pub fn Message(message: Msg) -> impl IntoView {
+#[derive(Serialize)]
+struct UpdateMessage {
+    messageid: u32,
+    content: String,
+}
+
+#[derive(Serialize)]
+struct RegenerateSummary {
+    messageid: u32,
+}
+
+fn metrics_detail(metrics: &Metrics) -> String {
+    let about = if metrics.estimated { "~" } else { "" };
+    format!(
+        "{about}{} prompt + {about}{} completion tokens · {:.1}s to first token · {:.1} tokens/s",
+        metrics.prompt_tokens,
+        metrics.completion_tokens,
+        metrics.ttft_ms as f64 / 1000.0,
+        metrics.tokens_per_second,
+    )
+}
+
+fn markdown(content: &str) -> String {
     let mut parsed = String::new();
-    let options = Options::all();
-    let parser = Parser::new_ext(&message.content, options);
+    let parser = Parser::new_ext(content, Options::all());
     crate::html::push_html(&mut parsed, parser);
+    parsed
+}
+
+#[component]
+pub fn Message(
+    message: Msg,
+    /// Switches to the branch of one of the siblings.
+    #[prop(default = None)]
+    on_select: Option<Callback<u32>>,
+    /// Offered on the last reply.
+    #[prop(default = None)]
+    on_regenerate: Option<Callback<()>>,
+    /// Offered on the user's messages, sends the new content on a new branch.
+    #[prop(default = None)]
+    on_edit: Option<Callback<String>>,
+) -> impl IntoView {
+    let parsed = markdown(&message.content);
     let datemsg = format!(
         "{}",
         DateTime::<Local>::from(message.created_at).format("%H:%M")
     );
     let profile = asset(&message.user.profile);
     let (playing, set_playing) = create_signal(Play::Stopped);
+    let (editing, set_editing) = create_signal(false);
+    let (draft, set_draft) = create_signal(String::new());
 
     let content = message.content.clone();
     let play = move |_| {
@@ -73,10 +137,36 @@ pub fn Message(message: Msg) -> impl IntoView {
             }
         })
     };
+    let original = message.content.clone();
+    let edit = move |_| {
+        set_draft.set(original.clone());
+        set_editing.set(true);
+    };
+    let save = move |_| {
+        set_editing.set(false);
+        if let Some(on_edit) = on_edit {
+            on_edit.call(draft.get());
+        }
+    };
+    let siblings = message.siblings.clone();
+    let index = message
+        .id
+        .and_then(|id| siblings.iter().position(|&sibling| sibling == id));
     view! {
-        <div class="flex items-start m-5 gap-2.5" class:flex-row-reverse=move || message.is_me>
+        <div
+            class="flex items-start m-5 gap-2.5"
+            class:flex-row-reverse=move || message.is_me
+            class:opacity-50=message.omitted || message.summarized
+            title=if message.summarized {
+                Some("Summarized, the model sees the summary below instead")
+            } else if message.omitted {
+                Some("Not sent to the model, the conversation is longer than its context")
+            } else {
+                None
+            }
+        >
             <img class="w-8 h-8 rounded-full" src=profile alt="User avatar" />
-            <div class="flex flex-col gap-1 max-w-[90%]">
+            <div class="group flex flex-col gap-1 max-w-[90%]">
                 <div class="flex items-center space-x-2 rtl:space-x-reverse">
                     <span class="text-sm font-semibold text-gray-900 dark:text-white">
                         {message.user.name}
@@ -87,7 +177,105 @@ pub fn Message(message: Msg) -> impl IntoView {
                 </div>
                 <div class="flex flex-col leading-1.5 p-4 border-gray-200 bg-gray-100 rounded-e-xl rounded-es-xl dark:bg-gray-700">
                     <p class="text-sm font-normal text-gray-900 dark:text-white">
-                        <div inner_html=parsed />
+                        {(!message.reasoning.is_empty())
+                            .then(|| {
+                                view! {
+                                    <details class="my-1 text-xs text-gray-500 dark:text-gray-400">
+                                        <summary class="cursor-pointer">
+                                            {match message.reasoning_ms {
+                                                Some(ms) => {
+                                                    format!("Thoughts ({}s)", (ms + 500) / 1000)
+                                                }
+                                                None => "Thinking…".to_string(),
+                                            }}
+                                        </summary>
+                                        <div class="whitespace-pre-wrap">
+                                            {message.reasoning.clone()}
+                                        </div>
+                                    </details>
+                                }
+                            })}
+                        {move || {
+                            if editing.get() {
+                                view! {
+                                    <textarea
+                                        class="w-full min-w-80 p-2 text-sm text-gray-900 bg-white rounded-lg border border-gray-300 dark:bg-gray-800 dark:border-gray-600 dark:text-white"
+                                        rows="3"
+                                        prop:value=draft
+                                        on:input=move |ev| set_draft.set(event_target_value(&ev))
+                                    />
+                                }
+                                    .into_view()
+                            } else {
+                                view! { <div inner_html=parsed.clone() /> }.into_view()
+                            }
+                        }}
+                        {(!message.images.is_empty())
+                            .then(|| {
+                                view! {
+                                    <div class="flex flex-wrap gap-2 my-1">
+                                        {message
+                                            .images
+                                            .iter()
+                                            .map(|image| view! { <Thumbnail image=image.clone() /> })
+                                            .collect_view()}
+                                    </div>
+                                }
+                            })}
+                        {(!message.attachments.is_empty())
+                            .then(|| {
+                                view! {
+                                    <div class="flex flex-wrap gap-2 my-1 text-sm">
+                                        {message
+                                            .attachments
+                                            .iter()
+                                            .map(|attachment| {
+                                                view! {
+                                                    <span class="px-2 py-0.5 rounded-full bg-gray-100 text-gray-700 dark:bg-gray-700 dark:text-gray-300">
+                                                        {attachment.name.clone()}
+                                                    </span>
+                                                }
+                                            })
+                                            .collect_view()}
+                                    </div>
+                                }
+                            })}
+                        {(!message.sources.is_empty())
+                            .then(|| {
+                                view! {
+                                    <ol class="my-1 text-xs text-gray-500 dark:text-gray-400">
+                                        {message
+                                            .sources
+                                            .iter()
+                                            .enumerate()
+                                            .map(|(i, source)| {
+                                                view! {
+                                                    <li>
+                                                        {format!(
+                                                            "[{}] {}:{}-{}",
+                                                            i + 1,
+                                                            source.path,
+                                                            source.start_line,
+                                                            source.end_line,
+                                                        )}
+                                                    </li>
+                                                }
+                                            })
+                                            .collect_view()}
+                                    </ol>
+                                }
+                            })}
+                        {message
+                            .tool_calls
+                            .iter()
+                            .map(|call| {
+                                view! {
+                                    <code class="block my-1 text-xs text-gray-500 dark:text-gray-400">
+                                        {format!("Called {}({})", call.name, call.arguments)}
+                                    </code>
+                                }
+                            })
+                            .collect_view()}
                         <div on:click=play>
                             {move || {
                                 match playing.get() {
@@ -166,9 +354,93 @@ pub fn Message(message: Msg) -> impl IntoView {
 
                     </p>
                 </div>
-                <span class="text-sm invisible font-normal text-gray-500 dark:text-gray-400">
-                    Delivered
-                </span>
+                <div class="flex items-center gap-2 text-sm font-normal text-gray-500 dark:text-gray-400">
+                    {on_select
+                        .zip(index)
+                        .filter(|_| siblings.len() > 1)
+                        .map(|(on_select, index)| {
+                            let previous = index.checked_sub(1).map(|i| siblings[i]);
+                            let next = siblings.get(index + 1).copied();
+                            view! {
+                                <button
+                                    type="button"
+                                    class="px-1 hover:text-gray-900 disabled:opacity-50 dark:hover:text-white"
+                                    disabled=previous.is_none()
+                                    on:click=move |_| previous.into_iter().for_each(|id| on_select.call(id))
+                                >
+                                    "‹"
+                                </button>
+                                <span>{format!("{}/{}", index + 1, siblings.len())}</span>
+                                <button
+                                    type="button"
+                                    class="px-1 hover:text-gray-900 disabled:opacity-50 dark:hover:text-white"
+                                    disabled=next.is_none()
+                                    on:click=move |_| next.into_iter().for_each(|id| on_select.call(id))
+                                >
+                                    "›"
+                                </button>
+                            }
+                        })}
+                    {on_regenerate
+                        .map(|on_regenerate| {
+                            view! {
+                                <button
+                                    type="button"
+                                    class="hover:text-gray-900 dark:hover:text-white"
+                                    on:click=move |_| on_regenerate.call(())
+                                >
+                                    Regenerate
+                                </button>
+                            }
+                        })}
+                    {on_edit
+                        .map(|_| {
+                            view! {
+                                <Show
+                                    when=move || editing.get()
+                                    fallback=move || {
+                                        view! {
+                                            <button
+                                                type="button"
+                                                class="hover:text-gray-900 dark:hover:text-white"
+                                                on:click=edit.clone()
+                                            >
+                                                Edit
+                                            </button>
+                                        }
+                                    }
+                                >
+                                    <button
+                                        type="button"
+                                        class="hover:text-gray-900 dark:hover:text-white"
+                                        on:click=save
+                                    >
+                                        Send
+                                    </button>
+                                    <button
+                                        type="button"
+                                        class="hover:text-gray-900 dark:hover:text-white"
+                                        on:click=move |_| set_editing.set(false)
+                                    >
+                                        Cancel
+                                    </button>
+                                </Show>
+                            }
+                        })}
+                    <span class:invisible=!message.truncated>Stopped</span>
+                </div>
+                {message
+                    .metrics
+                    .map(|metrics| {
+                        view! {
+                            <div
+                                class="invisible group-hover:visible text-xs text-gray-500 dark:text-gray-400"
+                                title=metrics.estimated.then_some("Estimated, the backend did not report token counts")
+                            >
+                                {metrics_detail(&metrics)}
+                            </div>
+                        }
+                    })}
             </div>
             <button
                 id="dropdownMenuIconButton"
@@ -240,3 +512,130 @@ pub fn Message(message: Msg) -> impl IntoView {
         </div>
     }
 }
+
+/// What a tool answered the model, folded away as it is meant for the model.
+#[component]
+pub fn ToolResult(message: Msg) -> impl IntoView {
+    view! {
+        <details class="mx-5 my-2 ps-10 text-xs text-gray-500 dark:text-gray-400" class:opacity-50=message.omitted || message.summarized>
+            <summary class="cursor-pointer">Tool result</summary>
+            <pre class="mt-1 p-2 whitespace-pre-wrap rounded-lg bg-gray-50 dark:bg-gray-800">
+                {message.content}
+            </pre>
+        </details>
+    }
+}
+
+/// Stands in for the messages above it in the prompts, it can be corrected by
+/// hand or written again by the model.
+#[component]
+pub fn Summary(message: Msg) -> impl IntoView {
+    let messageid = message.id.expect("Summaries are saved");
+    let (content, set_content) = create_signal(message.content);
+    let (draft, set_draft) = create_signal(String::new());
+    let (editing, set_editing) = create_signal(false);
+    let (busy, set_busy) = create_signal(false);
+    let (error, set_error) = create_signal(None::<String>);
+
+    let edit = move |_| {
+        set_draft.set(content.get());
+        set_editing.set(true);
+    };
+    let save = move |_| {
+        let content = draft.get();
+        set_busy.set(true);
+        spawn_local(async move {
+            let args = serde_wasm_bindgen::to_value(&UpdateMessage {
+                messageid,
+                content: content.clone(),
+            })
+            .unwrap();
+            match invoke("update_message", args).await {
+                Ok(_) => {
+                    set_content.set(content);
+                    set_editing.set(false);
+                    set_error.set(None);
+                }
+                Err(err) => set_error.set(err.as_string()),
+            }
+            set_busy.set(false);
+        });
+    };
+    let regenerate = move |_| {
+        set_busy.set(true);
+        spawn_local(async move {
+            let args = serde_wasm_bindgen::to_value(&RegenerateSummary { messageid }).unwrap();
+            match invoke("regenerate_summary", args).await {
+                Ok(value) => {
+                    let summary: DbMsg =
+                        serde_wasm_bindgen::from_value(value).expect("Summary message");
+                    set_content.set(summary.content);
+                    set_error.set(None);
+                }
+                Err(err) => set_error.set(err.as_string()),
+            }
+            set_busy.set(false);
+        });
+    };
+    let button = "px-2 py-1 text-xs font-medium rounded-lg border border-gray-300 hover:bg-gray-100 disabled:opacity-50 dark:border-gray-600 dark:hover:bg-gray-700";
+    view! {
+        <div class="m-5 p-4 rounded-xl border border-dashed border-gray-300 dark:border-gray-600" class:opacity-50=message.summarized>
+            <div class="flex items-center gap-2 mb-2">
+                <span class="grow text-sm font-semibold text-gray-500 dark:text-gray-400">
+                    Summary of the earlier messages
+                </span>
+                {move || {
+                    if editing.get() {
+                        view! {
+                            <button type="button" class=button disabled=busy on:click=save>
+                                Save
+                            </button>
+                            <button type="button" class=button on:click=move |_| set_editing.set(false)>
+                                Cancel
+                            </button>
+                        }
+                            .into_view()
+                    } else {
+                        view! {
+                            <button type="button" class=button disabled=busy on:click=edit>
+                                Edit
+                            </button>
+                            <button type="button" class=button disabled=busy on:click=regenerate>
+                                Regenerate
+                            </button>
+                        }
+                            .into_view()
+                    }
+                }}
+            </div>
+            {move || {
+                if editing.get() {
+                    view! {
+                        <textarea
+                            class="w-full h-40 p-2 text-sm text-gray-900 bg-white rounded-lg border border-gray-300 dark:bg-gray-800 dark:border-gray-600 dark:text-white"
+                            prop:value=draft
+                            on:input=move |ev| set_draft.set(event_target_value(&ev))
+                        />
+                    }
+                        .into_view()
+                } else {
+                    view! {
+                        <div
+                            class="text-sm text-gray-700 dark:text-gray-300"
+                            class:animate-pulse=busy
+                            inner_html=move || markdown(&content.get())
+                        />
+                    }
+                        .into_view()
+                }
+            }}
+            {move || {
+                error
+                    .get()
+                    .map(|error| {
+                        view! { <div class="mt-2 text-sm text-red-600 dark:text-red-400">{error}</div> }
+                    })
+            }}
+        </div>
+    }
+}
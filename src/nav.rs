@@ -40,6 +40,21 @@ where
             set_models.set(models);
         });
     };
+    let import_ollama = move |_| {
+        spawn_local(async move {
+            match invoke("import_ollama_models", JsValue::null()).await {
+                Ok(imported) => {
+                    log!("Imported {imported:?} Ollama models");
+                    let models: Vec<Model> = serde_wasm_bindgen::from_value(
+                        invoke("get_models", JsValue::null()).await.unwrap(),
+                    )
+                    .expect("models");
+                    set_models.set(models);
+                }
+                Err(err) => log!("Could not import Ollama models {err:?}"),
+            }
+        });
+    };
     let value = on_select_conv.clone();
     view! {
         {move || {
@@ -229,6 +244,13 @@ where
                                                     >
                                                         Close
                                                     </button>
+                                                    <button
+                                                        type="button"
+                                                        class="text-white bg-gray-800 hover:bg-gray-900 focus:outline-none focus:ring-4 focus:ring-gray-300 font-medium rounded-lg text-sm px-5 py-2.5 me-2 mb-2 dark:bg-gray-800 dark:hover:bg-gray-700 dark:focus:ring-gray-700 dark:border-gray-700"
+                                                        on:click=import_ollama
+                                                    >
+                                                        Import Ollama models
+                                                    </button>
                                                 </div>
                                             </div>
                                         </div>
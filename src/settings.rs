@@ -0,0 +1,481 @@
+use crate::app::invoke;
+use crate::loading::Loading;
+use chrono::{DateTime, Local, Utc};
+use leptos::leptos_dom::ev::SubmitEvent;
+use leptos::*;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::spawn_local;
+
+/// The conversation's overrides, every field is sent back on save so the ones
+/// not shown here are kept.
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct ParametersOverride {
+    temperature: Option<f32>,
+    truncate: Option<usize>,
+    max_new_tokens: Option<usize>,
+    stop: Option<Vec<String>>,
+    top_p: Option<f32>,
+    top_k: Option<usize>,
+    repetition_penalty: Option<f32>,
+    return_full_text: Option<bool>,
+    summarize_after: Option<usize>,
+    summary_model_id: Option<u32>,
+    tools: Option<bool>,
+}
+
+/// The model's values shown as placeholders, zero means the backend default.
+#[derive(Clone, Serialize, Deserialize)]
+struct ModelParameters {
+    temperature: f32,
+    max_new_tokens: usize,
+    summarize_after: usize,
+    #[serde(default)]
+    tools: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct ConversationSettings {
+    model_id: u32,
+    system_prompt: Option<String>,
+    parameters: ParametersOverride,
+    model_system_prompt: Option<String>,
+    model_parameters: ModelParameters,
+}
+
+#[derive(Serialize)]
+struct GetSettings {
+    conversationid: u32,
+}
+
+#[derive(Serialize)]
+struct UpdateSystemPrompt {
+    conversationid: u32,
+    systemprompt: String,
+}
+
+#[derive(Serialize)]
+struct UpdateModelSystemPrompt {
+    modelid: u32,
+    systemprompt: String,
+}
+
+#[derive(Serialize)]
+struct UpdateParameters {
+    conversationid: u32,
+    parameters: ParametersOverride,
+}
+
+#[derive(Clone, Deserialize)]
+struct ToolPermission {
+    name: String,
+    description: String,
+    always_allow: bool,
+}
+
+#[derive(Serialize)]
+struct SetToolPermission {
+    name: String,
+    alwaysallow: bool,
+}
+
+#[derive(Clone, Deserialize)]
+struct KnowledgeBaseInfo {
+    path: String,
+    indexed_at: Option<DateTime<Utc>>,
+    chunks: u64,
+}
+
+#[derive(Serialize)]
+struct GetKnowledgeBase {
+    conversationid: u32,
+}
+
+#[derive(Serialize)]
+struct SetKnowledgeBase {
+    conversationid: u32,
+    path: Option<String>,
+}
+
+fn field<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn placeholder<T: Display + Default + PartialEq>(value: T) -> String {
+    if value == T::default() {
+        "Model default".to_string()
+    } else {
+        format!("Model: {value}")
+    }
+}
+
+const INPUT: &str = "block w-full p-2 text-sm text-gray-900 bg-white rounded-lg border border-gray-300 focus:ring-blue-500 focus:border-blue-500 dark:bg-gray-800 dark:border-gray-600 dark:placeholder-gray-400 dark:text-white";
+const LABEL: &str = "flex flex-col gap-1 text-sm font-medium text-gray-700 dark:text-gray-300";
+
+/// System prompts and generation parameters of a conversation.
+#[component]
+pub fn Settings(conversationid: u32, set_open: WriteSignal<bool>) -> impl IntoView {
+    let settings = create_resource(
+        || (),
+        move |_| async move {
+            let args = serde_wasm_bindgen::to_value(&GetSettings { conversationid }).unwrap();
+            invoke("get_conversation_settings", args)
+                .await
+                .map(|value| {
+                    serde_wasm_bindgen::from_value::<ConversationSettings>(value)
+                        .expect("Conversation settings")
+                })
+                .map_err(|err| err.as_string().unwrap_or_default())
+        },
+    );
+    view! {
+        <Suspense fallback=move || {
+            view! { <Loading /> }
+        }>
+            {move || {
+                settings
+                    .get()
+                    .map(|settings| match settings {
+                        Ok(settings) => {
+                            view! { <SettingsForm conversationid settings set_open /> }.into_view()
+                        }
+                        Err(err) => {
+                            view! { <div class="p-3 text-sm text-red-600 dark:text-red-400">{err}</div> }
+                                .into_view()
+                        }
+                    })
+            }}
+        </Suspense>
+    }
+}
+
+#[component]
+fn SettingsForm(
+    conversationid: u32,
+    settings: ConversationSettings,
+    set_open: WriteSignal<bool>,
+) -> impl IntoView {
+    let modelid = settings.model_id;
+    let overrides = store_value(settings.parameters.clone());
+    let model = settings.model_parameters;
+    let (system_prompt, set_system_prompt) =
+        create_signal(settings.system_prompt.unwrap_or_default());
+    // Shared by every conversation with the model, only saved when changed.
+    let saved_model_system_prompt = settings.model_system_prompt.unwrap_or_default();
+    let (model_system_prompt, set_model_system_prompt) =
+        create_signal(saved_model_system_prompt.clone());
+    let (temperature, set_temperature) = create_signal(field(settings.parameters.temperature));
+    let (max_new_tokens, set_max_new_tokens) =
+        create_signal(field(settings.parameters.max_new_tokens));
+    let (summarize_after, set_summarize_after) =
+        create_signal(field(settings.parameters.summarize_after));
+    let (tools, set_tools) = create_signal(settings.parameters.tools.unwrap_or(model.tools));
+    let (error, set_error) = create_signal(None::<String>);
+
+    let save = move |ev: SubmitEvent| {
+        ev.prevent_default();
+        let mut parameters = overrides.get_value();
+        // Cleared fields go back to the model's value.
+        parameters.temperature = temperature.get().trim().parse().ok();
+        parameters.max_new_tokens = max_new_tokens.get().trim().parse().ok();
+        parameters.summarize_after = summarize_after.get().trim().parse().ok();
+        parameters.tools = (tools.get() != model.tools).then_some(tools.get());
+        let mut calls: Vec<(&str, JsValue)> = vec![
+            (
+                "update_conversation_system_prompt",
+                serde_wasm_bindgen::to_value(&UpdateSystemPrompt {
+                    conversationid,
+                    systemprompt: system_prompt.get(),
+                })
+                .unwrap(),
+            ),
+            (
+                "update_conversation_parameters",
+                serde_wasm_bindgen::to_value(&UpdateParameters {
+                    conversationid,
+                    parameters,
+                })
+                .unwrap(),
+            ),
+        ];
+        if model_system_prompt.get() != saved_model_system_prompt {
+            calls.push((
+                "update_model_system_prompt",
+                serde_wasm_bindgen::to_value(&UpdateModelSystemPrompt {
+                    modelid,
+                    systemprompt: model_system_prompt.get(),
+                })
+                .unwrap(),
+            ));
+        }
+        spawn_local(async move {
+            for (cmd, args) in calls {
+                if let Err(err) = invoke(cmd, args).await {
+                    set_error.set(Some(err.as_string().unwrap_or_default()));
+                    return;
+                }
+            }
+            set_open.set(false);
+        });
+    };
+    view! {
+        <form class="flex flex-col gap-3 p-3 border-t border-gray-200 dark:border-gray-600" on:submit=save>
+            <label class=LABEL>
+                "System prompt for this conversation"
+                <textarea
+                    class=INPUT
+                    rows="3"
+                    placeholder="Uses the model's system prompt when empty"
+                    prop:value=system_prompt
+                    on:input=move |ev| set_system_prompt.set(event_target_value(&ev))
+                />
+            </label>
+            <label class=LABEL>
+                "Default system prompt of the model"
+                <textarea
+                    class=INPUT
+                    rows="2"
+                    prop:value=model_system_prompt
+                    on:input=move |ev| set_model_system_prompt.set(event_target_value(&ev))
+                />
+            </label>
+            <div class="grid grid-cols-3 gap-3">
+                <label class=LABEL>
+                    "Temperature"
+                    <input
+                        class=INPUT
+                        type="number"
+                        min="0"
+                        step="0.1"
+                        placeholder=placeholder(model.temperature)
+                        prop:value=temperature
+                        on:input=move |ev| set_temperature.set(event_target_value(&ev))
+                    />
+                </label>
+                <label class=LABEL>
+                    "Max new tokens"
+                    <input
+                        class=INPUT
+                        type="number"
+                        min="1"
+                        placeholder=placeholder(model.max_new_tokens)
+                        prop:value=max_new_tokens
+                        on:input=move |ev| set_max_new_tokens.set(event_target_value(&ev))
+                    />
+                </label>
+                <label class=LABEL>
+                    "Summarize after (tokens)"
+                    <input
+                        class=INPUT
+                        type="number"
+                        min="0"
+                        placeholder=placeholder(model.summarize_after)
+                        prop:value=summarize_after
+                        on:input=move |ev| set_summarize_after.set(event_target_value(&ev))
+                    />
+                </label>
+            </div>
+            <label class="flex items-center gap-2 text-sm font-medium text-gray-700 dark:text-gray-300">
+                <input
+                    type="checkbox"
+                    prop:checked=tools
+                    on:change=move |ev| set_tools.set(event_target_checked(&ev))
+                />
+                "Let the model use tools (calculator, clock, attached files)"
+            </label>
+            <ToolPermissions />
+            <KnowledgeBase conversationid />
+            {move || {
+                error
+                    .get()
+                    .map(|error| {
+                        view! { <div class="text-sm text-red-600 dark:text-red-400">{error}</div> }
+                    })
+            }}
+            <div class="flex justify-end gap-2">
+                <button
+                    type="button"
+                    class="px-3 py-1 text-sm font-medium rounded-lg border border-gray-300 hover:bg-gray-100 dark:border-gray-600 dark:text-white dark:hover:bg-gray-700"
+                    on:click=move |_| set_open.set(false)
+                >
+                    Cancel
+                </button>
+                <button
+                    type="submit"
+                    class="px-3 py-1 text-sm font-medium text-white bg-blue-600 rounded-lg hover:bg-blue-700"
+                >
+                    Save
+                </button>
+            </div>
+        </form>
+    }
+}
+
+/// Tools which run without asking, saved as soon as they are toggled since
+/// they apply to every conversation.
+#[component]
+fn ToolPermissions() -> impl IntoView {
+    let (error, set_error) = create_signal(None::<String>);
+    let permissions = create_resource(
+        || (),
+        move |_| async move {
+            match invoke("get_tool_permissions", JsValue::NULL).await {
+                Ok(value) => serde_wasm_bindgen::from_value::<Vec<ToolPermission>>(value)
+                    .expect("Tool permissions"),
+                Err(err) => {
+                    set_error.set(Some(err.as_string().unwrap_or_default()));
+                    vec![]
+                }
+            }
+        },
+    );
+    let set_permission = move |name: String, alwaysallow: bool| {
+        spawn_local(async move {
+            let args =
+                serde_wasm_bindgen::to_value(&SetToolPermission { name, alwaysallow }).unwrap();
+            if let Err(err) = invoke("set_tool_permission", args).await {
+                set_error.set(Some(err.as_string().unwrap_or_default()));
+            }
+        });
+    };
+    view! {
+        <fieldset class="flex flex-col gap-1 text-sm text-gray-700 dark:text-gray-300">
+            <legend class="font-medium">"Run without asking"</legend>
+            {move || {
+                permissions
+                    .get()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|permission| {
+                        let name = permission.name.clone();
+                        view! {
+                            <label class="flex items-center gap-2" title=permission.description>
+                                <input
+                                    type="checkbox"
+                                    prop:checked=permission.always_allow
+                                    on:change=move |ev| {
+                                        set_permission(name.clone(), event_target_checked(&ev))
+                                    }
+                                />
+                                <code>{permission.name}</code>
+                            </label>
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            }}
+            {move || {
+                error
+                    .get()
+                    .map(|error| view! { <div class="text-red-600 dark:text-red-400">{error}</div> })
+            }}
+        </fieldset>
+    }
+}
+
+/// The folder the model draws on. It is saved and indexed on its own, apart
+/// from the form, as indexing takes a while.
+#[component]
+fn KnowledgeBase(conversationid: u32) -> impl IntoView {
+    let (path, set_path) = create_signal(String::new());
+    let (indexing, set_indexing) = create_signal(false);
+    let (error, set_error) = create_signal(None::<String>);
+    let base = create_resource(
+        || (),
+        move |_| async move {
+            let args = serde_wasm_bindgen::to_value(&GetKnowledgeBase { conversationid }).unwrap();
+            match invoke("get_knowledge_base", args).await {
+                Ok(value) => {
+                    let base = serde_wasm_bindgen::from_value::<Option<KnowledgeBaseInfo>>(value)
+                        .expect("Knowledge base");
+                    set_path.set(
+                        base.as_ref()
+                            .map(|base| base.path.clone())
+                            .unwrap_or_default(),
+                    );
+                    base
+                }
+                Err(err) => {
+                    set_error.set(Some(err.as_string().unwrap_or_default()));
+                    None
+                }
+            }
+        },
+    );
+    // An empty folder removes the knowledge base.
+    let index = move |_| {
+        let folder = path.get().trim().to_string();
+        set_indexing.set(true);
+        set_error.set(None);
+        spawn_local(async move {
+            let path = (!folder.is_empty()).then_some(folder);
+            let remove = path.is_none();
+            let args = serde_wasm_bindgen::to_value(&SetKnowledgeBase {
+                conversationid,
+                path,
+            })
+            .unwrap();
+            let mut result = invoke("set_knowledge_base", args).await;
+            if result.is_ok() && !remove {
+                let args =
+                    serde_wasm_bindgen::to_value(&GetKnowledgeBase { conversationid }).unwrap();
+                result = invoke("index_knowledge_base", args).await;
+            }
+            if let Err(err) = result {
+                set_error.set(Some(err.as_string().unwrap_or_default()));
+            }
+            set_indexing.set(false);
+            base.refetch();
+        });
+    };
+    view! {
+        <div class="flex flex-col gap-1 text-sm text-gray-700 dark:text-gray-300">
+            <span class="font-medium">"Knowledge base"</span>
+            <div class="flex gap-2">
+                <input
+                    class=INPUT
+                    type="text"
+                    placeholder="A folder of documents to answer from"
+                    prop:value=path
+                    on:input=move |ev| set_path.set(event_target_value(&ev))
+                />
+                <button
+                    type="button"
+                    class="px-3 py-1 font-medium rounded-lg border border-gray-300 hover:bg-gray-100 disabled:opacity-50 dark:border-gray-600 dark:text-white dark:hover:bg-gray-700"
+                    disabled=indexing
+                    on:click=index
+                >
+                    {move || {
+                        if indexing.get() {
+                            "Indexing…"
+                        } else if path.get().trim().is_empty() {
+                            "Remove"
+                        } else {
+                            "Index"
+                        }
+                    }}
+                </button>
+            </div>
+            {move || {
+                base.get()
+                    .flatten()
+                    .map(|base| match base.indexed_at {
+                        Some(indexed_at) => {
+                            format!(
+                                "{} excerpts, indexed {}",
+                                base.chunks,
+                                indexed_at.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+                            )
+                        }
+                        None => "Not indexed yet".to_string(),
+                    })
+                    .map(|status| view! { <span class="text-gray-500 dark:text-gray-400">{status}</span> })
+            }}
+            {move || {
+                error
+                    .get()
+                    .map(|error| view! { <div class="text-red-600 dark:text-red-400">{error}</div> })
+            }}
+        </div>
+    }
+}
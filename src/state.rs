@@ -11,9 +11,72 @@ pub struct User {
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Message {
+    pub id: u32,
     pub content: String,
     pub user_id: u32,
     pub created_at: DateTime<Utc>,
+    pub truncated: bool,
+    /// Only set on summaries.
+    pub summary_until: Option<u32>,
+    /// Only set on generated replies.
+    pub metrics: Option<Metrics>,
+    /// The tools the model called in this message.
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// Set on tool results, the call they answer.
+    pub tool_call_id: Option<String>,
+    pub images: Option<Vec<Image>>,
+    pub sources: Option<Vec<Source>>,
+    pub reasoning: Option<Reasoning>,
+}
+
+/// A picture stored in the app's cache.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Image {
+    pub hash: String,
+    pub mime_type: String,
+}
+
+/// Lines of a knowledge base file a reply was given.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Source {
+    pub path: String,
+    pub start_line: u32,
+    pub end_line: u32,
+}
+
+/// What a reasoning model thought before answering.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Reasoning {
+    pub content: String,
+    pub duration_ms: u64,
+}
+
+/// A file the user attached, only its text is kept by the backend.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Attachment {
+    pub id: u32,
+    /// The message it was sent with.
+    pub message_id: Option<u32>,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    /// Json, as written by the model.
+    pub arguments: String,
+}
+
+/// Token counts and speed of a generated reply.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Metrics {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub ttft_ms: u64,
+    pub tokens_per_second: f32,
+    /// Counted by us, the backend did not report them.
+    pub estimated: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]